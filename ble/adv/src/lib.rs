@@ -0,0 +1,287 @@
+//! Builder for legacy advertising data (AD structures) of ble-batt.
+//!
+//! Every AD structure is `[len, type, data..]` where `len` counts the type byte and the data.
+//! Layout and type values: Core Specification Supplement, part A and
+//! https://www.bluetooth.com/specifications/assigned-numbers/
+//!
+//! Pure code, host tests: `cargo test -p ble-adv --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use heapless::Vec;
+
+/// Max size of a legacy advertising or scan response payload
+pub const LEGACY_LEN: usize = 31;
+
+/// Bits for [`AdStructure::Flags`]
+pub mod flags {
+    pub const LE_LIMITED_DISC: u8 = 0x01;
+    pub const LE_GENERAL_DISC: u8 = 0x02;
+    pub const BR_EDR_NOT_SUPPORTED: u8 = 0x04;
+}
+
+/// AD type values from the assigned numbers
+pub mod ad_type {
+    pub const FLAGS: u8 = 0x01;
+    pub const SERVICE_UUIDS_16_COMPLETE: u8 = 0x03;
+    pub const SERVICE_UUIDS_32_COMPLETE: u8 = 0x05;
+    pub const SERVICE_UUIDS_128_COMPLETE: u8 = 0x07;
+    pub const SHORTENED_LOCAL_NAME: u8 = 0x08;
+    pub const COMPLETE_LOCAL_NAME: u8 = 0x09;
+    pub const TX_POWER_LEVEL: u8 = 0x0A;
+    pub const SERVICE_DATA_16: u8 = 0x16;
+    pub const APPEARANCE: u8 = 0x19;
+    pub const URI: u8 = 0x24;
+    pub const MANUFACTURER_DATA: u8 = 0xFF;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The structure does not fit in what is left of the 31 bytes
+    Overflow,
+}
+
+/// One typed AD structure.
+///
+/// Multi byte values are given in host order and written little endian,
+/// except 128-bit uuids which are taken as the 16 bytes in on-air (little endian) order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdStructure<'a> {
+    Flags(u8),
+    ServiceUuids16(&'a [u16]),
+    ServiceUuids32(&'a [u32]),
+    ServiceUuids128(&'a [[u8; 16]]),
+    CompleteLocalName(&'a str),
+    ShortenedLocalName(&'a str),
+    /// dBm
+    TxPowerLevel(i8),
+    Appearance(u16),
+    ServiceData16 {
+        uuid: u16,
+        data: &'a [u8],
+    },
+    ManufacturerData {
+        company_id: u16,
+        data: &'a [u8],
+    },
+    /// Full uri, `http:` and `https:` are compressed to their scheme code
+    Uri(&'a str),
+}
+
+impl AdStructure<'_> {
+    fn ad_type(&self) -> u8 {
+        match self {
+            AdStructure::Flags(_) => ad_type::FLAGS,
+            AdStructure::ServiceUuids16(_) => ad_type::SERVICE_UUIDS_16_COMPLETE,
+            AdStructure::ServiceUuids32(_) => ad_type::SERVICE_UUIDS_32_COMPLETE,
+            AdStructure::ServiceUuids128(_) => ad_type::SERVICE_UUIDS_128_COMPLETE,
+            AdStructure::CompleteLocalName(_) => ad_type::COMPLETE_LOCAL_NAME,
+            AdStructure::ShortenedLocalName(_) => ad_type::SHORTENED_LOCAL_NAME,
+            AdStructure::TxPowerLevel(_) => ad_type::TX_POWER_LEVEL,
+            AdStructure::Appearance(_) => ad_type::APPEARANCE,
+            AdStructure::ServiceData16 { .. } => ad_type::SERVICE_DATA_16,
+            AdStructure::ManufacturerData { .. } => ad_type::MANUFACTURER_DATA,
+            AdStructure::Uri(_) => ad_type::URI,
+        }
+    }
+
+    /// Length of the data part, without the len and type bytes
+    fn data_len(&self) -> usize {
+        match self {
+            AdStructure::Flags(_) => 1,
+            AdStructure::ServiceUuids16(uuids) => uuids.len() * 2,
+            AdStructure::ServiceUuids32(uuids) => uuids.len() * 4,
+            AdStructure::ServiceUuids128(uuids) => uuids.len() * 16,
+            AdStructure::CompleteLocalName(name) => name.len(),
+            AdStructure::ShortenedLocalName(name) => name.len(),
+            AdStructure::TxPowerLevel(_) => 1,
+            AdStructure::Appearance(_) => 2,
+            AdStructure::ServiceData16 { data, .. } => 2 + data.len(),
+            AdStructure::ManufacturerData { data, .. } => 2 + data.len(),
+            AdStructure::Uri(uri) => 1 + split_uri_scheme(uri).1.len(),
+        }
+    }
+
+    /// Number of bytes this structure takes up in a payload
+    pub fn encoded_len(&self) -> usize {
+        2 + self.data_len()
+    }
+
+    /// Writes the data part, caller has already checked that it fits
+    fn write_data(&self, buf: &mut Vec<u8, LEGACY_LEN>) -> Result<(), ()> {
+        match *self {
+            AdStructure::Flags(flags) => buf.push(flags).map_err(drop),
+            AdStructure::ServiceUuids16(uuids) => uuids
+                .iter()
+                .try_for_each(|uuid| buf.extend_from_slice(&uuid.to_le_bytes())),
+            AdStructure::ServiceUuids32(uuids) => uuids
+                .iter()
+                .try_for_each(|uuid| buf.extend_from_slice(&uuid.to_le_bytes())),
+            AdStructure::ServiceUuids128(uuids) => uuids
+                .iter()
+                .try_for_each(|uuid| buf.extend_from_slice(uuid)),
+            AdStructure::CompleteLocalName(name) | AdStructure::ShortenedLocalName(name) => {
+                buf.extend_from_slice(name.as_bytes())
+            }
+            AdStructure::TxPowerLevel(dbm) => buf.push(dbm as u8).map_err(drop),
+            AdStructure::Appearance(appearance) => buf.extend_from_slice(&appearance.to_le_bytes()),
+            AdStructure::ServiceData16 { uuid, data } => {
+                buf.extend_from_slice(&uuid.to_le_bytes())?;
+                buf.extend_from_slice(data)
+            }
+            AdStructure::ManufacturerData { company_id, data } => {
+                buf.extend_from_slice(&company_id.to_le_bytes())?;
+                buf.extend_from_slice(data)
+            }
+            AdStructure::Uri(uri) => {
+                let (scheme, rest) = split_uri_scheme(uri);
+                buf.push(scheme).map_err(drop)?;
+                buf.extend_from_slice(rest.as_bytes())
+            }
+        }
+    }
+}
+
+/// A legacy advertising (or scan response) payload.
+///
+/// ```
+/// # use ble_adv::*;
+/// let mut adv_data = AdvData::new();
+/// adv_data.push(AdStructure::Flags(flags::LE_GENERAL_DISC | flags::BR_EDR_NOT_SUPPORTED))?;
+/// adv_data.push(AdStructure::ServiceUuids16(&[0x180F]))?;
+/// adv_data.push_name("Embassy Microbit")?;
+/// assert_eq!(adv_data.len(), 3 + 4 + 18);
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvData {
+    buf: Vec<u8, LEGACY_LEN>,
+}
+
+impl AdvData {
+    pub const fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Appends `ad`, leaves the payload untouched if it does not fit
+    pub fn push(&mut self, ad: AdStructure) -> Result<(), Error> {
+        if ad.encoded_len() > self.remaining() {
+            return Err(Error::Overflow);
+        }
+        let len = ad.encoded_len() - 1;
+        // the length check above guarantees room for all of it
+        self.buf.push(len as u8).unwrap();
+        self.buf.push(ad.ad_type()).unwrap();
+        ad.write_data(&mut self.buf).unwrap();
+        Ok(())
+    }
+
+    /// Appends `name` as the complete local name, or as a shortened name cut
+    /// to the space that is left if the complete name does not fit.
+    pub fn push_name(&mut self, name: &str) -> Result<(), Error> {
+        self.push(AdStructure::CompleteLocalName(name))
+            .or_else(|_| {
+                self.push(AdStructure::ShortenedLocalName(shorten(
+                    name,
+                    self.remaining(),
+                )?))
+            })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Bytes left until the legacy limit
+    pub fn remaining(&self) -> usize {
+        LEGACY_LEN - self.buf.len()
+    }
+}
+
+/// Scheme name string codes for [`AdStructure::Uri`], 0x01 means no compressed scheme
+fn split_uri_scheme(uri: &str) -> (u8, &str) {
+    const SCHEMES: [(u8, &str); 2] = [(0x16, "http:"), (0x17, "https:")];
+    SCHEMES
+        .iter()
+        .find_map(|(code, scheme)| uri.strip_prefix(scheme).map(|rest| (*code, rest)))
+        .unwrap_or((0x01, uri))
+}
+
+/// Advertising data and scan response built from one list of AD structures.
+///
+/// The split is done in list order so the same list always gives the same payloads:
+/// - flags always go in the advertising data
+/// - every other structure goes in the advertising data if it fits, else in the scan response
+/// - a local name that fits in neither is shortened into whichever payload has the most room left
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Payload {
+    pub adv_data: AdvData,
+    pub scan_data: AdvData,
+}
+
+impl Payload {
+    pub fn split(structures: &[AdStructure]) -> Result<Self, Error> {
+        let mut payload = Self::default();
+        for ad in structures {
+            payload.place(*ad)?;
+        }
+        Ok(payload)
+    }
+
+    /// What ble-batt advertises while it takes connections: flags, the 16 bit `services`,
+    /// the name, the appearance and the tx power in dBm. A name too long for either
+    /// payload is shortened, so this only fails for a list of services that doesn't fit.
+    pub fn connectable(
+        name: &str,
+        services: &[u16],
+        appearance: u16,
+        tx_power: i8,
+    ) -> Result<Self, Error> {
+        Self::split(&[
+            AdStructure::Flags(flags::LE_GENERAL_DISC | flags::BR_EDR_NOT_SUPPORTED),
+            AdStructure::ServiceUuids16(services),
+            AdStructure::CompleteLocalName(name),
+            AdStructure::Appearance(appearance),
+            AdStructure::TxPowerLevel(tx_power),
+        ])
+    }
+
+    fn place(&mut self, ad: AdStructure) -> Result<(), Error> {
+        if let AdStructure::Flags(_) = ad {
+            return self.adv_data.push(ad);
+        }
+        if self.adv_data.push(ad).is_ok() || self.scan_data.push(ad).is_ok() {
+            return Ok(());
+        }
+        match ad {
+            AdStructure::CompleteLocalName(name) | AdStructure::ShortenedLocalName(name) => {
+                if self.adv_data.remaining() >= self.scan_data.remaining() {
+                    self.adv_data.push_name(name)
+                } else {
+                    self.scan_data.push_name(name)
+                }
+            }
+            _ => Err(Error::Overflow),
+        }
+    }
+}
+
+/// Cuts `name` so that it fits as an AD structure in `space` bytes,
+/// at a char boundary so the result is still valid utf8.
+fn shorten(name: &str, space: usize) -> Result<&str, Error> {
+    let max = space.saturating_sub(2);
+    let end = (1..=max.min(name.len()))
+        .rev()
+        .find(|&end| name.is_char_boundary(end))
+        .ok_or(Error::Overflow)?;
+    Ok(&name[..end])
+}
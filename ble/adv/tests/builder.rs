@@ -0,0 +1,174 @@
+//! Run on the host: `cargo test -p ble-adv --target x86_64-unknown-linux-gnu`
+
+use ble_adv::*;
+
+fn encode(ad: AdStructure) -> Vec<u8> {
+    let mut data = AdvData::new();
+    data.push(ad).unwrap();
+    data.as_slice().to_vec()
+}
+
+/// The layouts of the Core Specification Supplement, part A, section by section
+#[test]
+fn supplement_layouts() {
+    // 1.3 flags: LE General Discoverable, BR/EDR not supported
+    assert_eq!(
+        encode(AdStructure::Flags(
+            flags::LE_GENERAL_DISC | flags::BR_EDR_NOT_SUPPORTED
+        )),
+        [0x02, 0x01, 0x06]
+    );
+    // 1.1 service uuids, little endian
+    assert_eq!(
+        encode(AdStructure::ServiceUuids16(&[0x180F, 0x1812])),
+        [0x05, 0x03, 0x0F, 0x18, 0x12, 0x18]
+    );
+    assert_eq!(
+        encode(AdStructure::ServiceUuids32(&[0x0000_180F])),
+        [0x05, 0x05, 0x0F, 0x18, 0x00, 0x00]
+    );
+    let uuid128 = [
+        0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, 0xA9, 0xE0, 0x93, 0xF3, 0xA3, 0xB5, 0x01, 0x00, 0x40,
+        0x6E,
+    ];
+    let mut expected = vec![0x11, 0x07];
+    expected.extend_from_slice(&uuid128);
+    assert_eq!(encode(AdStructure::ServiceUuids128(&[uuid128])), expected);
+    // 1.2 local name, utf8 without a terminator
+    assert_eq!(
+        encode(AdStructure::CompleteLocalName("micro")),
+        [0x06, 0x09, b'm', b'i', b'c', b'r', b'o']
+    );
+    assert_eq!(
+        encode(AdStructure::ShortenedLocalName("mi")),
+        [0x03, 0x08, b'm', b'i']
+    );
+    // 1.5 tx power level, signed dBm
+    assert_eq!(encode(AdStructure::TxPowerLevel(-4)), [0x02, 0x0A, 0xFC]);
+    // 1.12 appearance, Generic Tag
+    assert_eq!(
+        encode(AdStructure::Appearance(0x0200)),
+        [0x03, 0x19, 0x00, 0x02]
+    );
+    // 1.11 service data, the uuid first
+    assert_eq!(
+        encode(AdStructure::ServiceData16 {
+            uuid: 0xFEAA,
+            data: &[0x10, 0xF4]
+        }),
+        [0x05, 0x16, 0xAA, 0xFE, 0x10, 0xF4]
+    );
+    // 1.4 manufacturer data, the company id first
+    assert_eq!(
+        encode(AdStructure::ManufacturerData {
+            company_id: 0x004C,
+            data: &[0x02, 0x15]
+        }),
+        [0x05, 0xFF, 0x4C, 0x00, 0x02, 0x15]
+    );
+    // 1.18 uri, with the scheme as its code from the assigned numbers
+    assert_eq!(
+        encode(AdStructure::Uri("https://a.io")),
+        [0x08, 0x24, 0x17, b'/', b'/', b'a', b'.', b'i', b'o']
+    );
+    assert_eq!(
+        encode(AdStructure::Uri("http://a")),
+        [0x05, 0x24, 0x16, b'/', b'/', b'a']
+    );
+    assert_eq!(
+        encode(AdStructure::Uri("ftp:x")),
+        [0x07, 0x24, 0x01, b'f', b't', b'p', b':', b'x']
+    );
+}
+
+#[test]
+fn structures_follow_each_other() {
+    let mut data = AdvData::new();
+    data.push(AdStructure::Flags(0x06)).unwrap();
+    data.push(AdStructure::ServiceUuids16(&[0x180F])).unwrap();
+    data.push(AdStructure::TxPowerLevel(0)).unwrap();
+    assert_eq!(
+        data.as_slice(),
+        [0x02, 0x01, 0x06, 0x03, 0x03, 0x0F, 0x18, 0x02, 0x0A, 0x00]
+    );
+    assert_eq!(data.remaining(), LEGACY_LEN - 10);
+}
+
+#[test]
+fn length_byte_counts_type_and_data() {
+    let uuid128 = [[0u8; 16]];
+    let all = [
+        AdStructure::Flags(0),
+        AdStructure::ServiceUuids16(&[1, 2, 3]),
+        AdStructure::ServiceUuids32(&[1, 2]),
+        AdStructure::ServiceUuids128(&uuid128),
+        AdStructure::CompleteLocalName("name"),
+        AdStructure::ShortenedLocalName("n"),
+        AdStructure::TxPowerLevel(0),
+        AdStructure::Appearance(0),
+        AdStructure::ServiceData16 {
+            uuid: 0,
+            data: &[1, 2, 3],
+        },
+        AdStructure::ManufacturerData {
+            company_id: 0,
+            data: &[1],
+        },
+        AdStructure::Uri("https://x.y"),
+    ];
+    let expected_len = [2, 7, 9, 17, 5, 2, 2, 3, 6, 4, 7];
+    for (ad, expected) in all.iter().zip(expected_len) {
+        let bytes = encode(*ad);
+        assert_eq!(bytes[0], expected, "{:?}", ad);
+        assert_eq!(bytes.len(), bytes[0] as usize + 1, "{:?}", ad);
+        assert_eq!(ad.encoded_len(), bytes.len(), "{:?}", ad);
+    }
+}
+
+#[test]
+fn overflow_at_31_bytes() {
+    let mut data = AdvData::new();
+    // 2 + 27 bytes, 2 left
+    data.push(AdStructure::ManufacturerData {
+        company_id: 0xFFFF,
+        data: &[0; 25],
+    })
+    .unwrap();
+    assert_eq!(data.remaining(), 2);
+    let before = data.clone();
+    assert_eq!(
+        data.push(AdStructure::TxPowerLevel(0)),
+        Err(Error::Overflow)
+    );
+    // nothing of the structure that didn't fit is left behind
+    assert_eq!(data, before);
+    // exactly full
+    data.push(AdStructure::ShortenedLocalName("")).unwrap();
+    assert_eq!(data.len(), LEGACY_LEN);
+    assert_eq!(data.remaining(), 0);
+    assert_eq!(data.push(AdStructure::Flags(0)), Err(Error::Overflow));
+
+    let mut data = AdvData::new();
+    assert_eq!(
+        data.push(AdStructure::CompleteLocalName(&"x".repeat(30))),
+        Err(Error::Overflow)
+    );
+    assert!(data.is_empty());
+    data.push(AdStructure::CompleteLocalName(&"x".repeat(29)))
+        .unwrap();
+    assert_eq!(data.len(), LEGACY_LEN);
+}
+
+#[test]
+fn push_name_shortens_to_the_space_left() {
+    let mut data = AdvData::new();
+    data.push(AdStructure::Flags(0x06)).unwrap();
+    data.push_name(&"n".repeat(40)).unwrap();
+    // 3 for the flags, 2 + 26 for the name
+    assert_eq!(data.as_slice()[3..5], [27, 0x08]);
+    assert_eq!(data.len(), LEGACY_LEN);
+
+    let mut data = AdvData::new();
+    data.push_name("short").unwrap();
+    assert_eq!(data.as_slice()[1], 0x09);
+}
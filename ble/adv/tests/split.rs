@@ -0,0 +1,151 @@
+//! Run on the host: `cargo test -p ble-adv --target x86_64-unknown-linux-gnu`
+
+use ble_adv::*;
+
+/// What main.rs passes, `ADVERTISED_SERVICES`, `APPEARANCE`, `TX_POWER` and `DEFAULT_NAME`
+const BATTERY_SERVICE: u16 = 0x180F;
+const HID_SERVICE: u16 = 0x1812;
+const GENERIC_TAG: u16 = 0x0200;
+const KEYBOARD: u16 = 0x03C1;
+const TX_POWER: i8 = 0;
+const DEFAULT_NAME: &str = "Embassy Microbit";
+
+const FLAGS: [u8; 3] = [0x02, 0x01, 0x06];
+
+fn structures(payload: &AdvData) -> Vec<(u8, Vec<u8>)> {
+    let mut found = Vec::new();
+    let mut rest = payload.as_slice();
+    while let [len, ad_type, ..] = *rest {
+        let len = len as usize;
+        found.push((ad_type, rest[2..1 + len].to_vec()));
+        rest = &rest[1 + len..];
+    }
+    found
+}
+
+fn types(payload: &AdvData) -> Vec<u8> {
+    structures(payload).iter().map(|(t, _)| *t).collect()
+}
+
+#[test]
+fn default_advertisement() {
+    let payload =
+        Payload::connectable(DEFAULT_NAME, &[BATTERY_SERVICE], GENERIC_TAG, TX_POWER).unwrap();
+    // 3 + 4 + 18 + 4 = 29, the tx power doesn't fit any more
+    assert_eq!(payload.adv_data.as_slice()[..3], FLAGS);
+    assert_eq!(
+        types(&payload.adv_data),
+        [
+            ad_type::FLAGS,
+            ad_type::SERVICE_UUIDS_16_COMPLETE,
+            ad_type::COMPLETE_LOCAL_NAME,
+            ad_type::APPEARANCE
+        ]
+    );
+    assert_eq!(payload.adv_data.len(), 29);
+    assert_eq!(payload.scan_data.as_slice(), [0x02, 0x0A, 0x00]);
+}
+
+#[test]
+fn hid_advertisement() {
+    let payload = Payload::connectable(
+        DEFAULT_NAME,
+        &[BATTERY_SERVICE, HID_SERVICE],
+        KEYBOARD,
+        TX_POWER,
+    )
+    .unwrap();
+    // 3 + 6 + 18 + 4 = 31, exactly full
+    assert_eq!(payload.adv_data.len(), LEGACY_LEN);
+    assert_eq!(
+        structures(&payload.adv_data)[1],
+        (
+            ad_type::SERVICE_UUIDS_16_COMPLETE,
+            vec![0x0F, 0x18, 0x12, 0x18]
+        )
+    );
+    assert_eq!(
+        structures(&payload.adv_data)[3],
+        (ad_type::APPEARANCE, vec![0xC1, 0x03])
+    );
+    assert_eq!(types(&payload.scan_data), [ad_type::TX_POWER_LEVEL]);
+}
+
+#[test]
+fn long_name_moves_to_the_scan_response() {
+    // 27 bytes as a structure, 24 left after the flags and the service
+    let name = "A name of 25 characters..";
+    assert_eq!(name.len(), 25);
+    let payload = Payload::connectable(name, &[BATTERY_SERVICE], GENERIC_TAG, TX_POWER).unwrap();
+    assert_eq!(
+        structures(&payload.scan_data),
+        [(ad_type::COMPLETE_LOCAL_NAME, name.as_bytes().to_vec())]
+    );
+    // what comes after the name still goes in the advertising data
+    assert_eq!(
+        types(&payload.adv_data),
+        [
+            ad_type::FLAGS,
+            ad_type::SERVICE_UUIDS_16_COMPLETE,
+            ad_type::APPEARANCE,
+            ad_type::TX_POWER_LEVEL
+        ]
+    );
+}
+
+#[test]
+fn too_long_name_is_shortened_on_a_char_boundary() {
+    // 32 bytes, 29 fit in the scan response, the 29th is inside the é
+    let name = format!("{}é{}", "a".repeat(28), "bb");
+    assert_eq!(name.len(), 32);
+    let payload = Payload::connectable(&name, &[BATTERY_SERVICE], GENERIC_TAG, TX_POWER).unwrap();
+    assert_eq!(
+        structures(&payload.scan_data),
+        [(ad_type::SHORTENED_LOCAL_NAME, "a".repeat(28).into_bytes())]
+    );
+    assert_eq!(payload.scan_data.as_slice()[0], 29);
+    // all ascii, cut right at the limit
+    let name = "b".repeat(32);
+    let payload = Payload::connectable(&name, &[BATTERY_SERVICE], GENERIC_TAG, TX_POWER).unwrap();
+    assert_eq!(
+        structures(&payload.scan_data),
+        [(ad_type::SHORTENED_LOCAL_NAME, "b".repeat(29).into_bytes())]
+    );
+    assert_eq!(payload.scan_data.len(), LEGACY_LEN);
+}
+
+#[test]
+fn flags_always_go_in_the_advertising_data() {
+    let name = AdStructure::CompleteLocalName("0123456789012345678901234");
+    let flags = AdStructure::Flags(0x06);
+    // after a structure that took most of the advertising data
+    let payload = Payload::split(&[name, flags]).unwrap();
+    assert_eq!(
+        types(&payload.adv_data),
+        [ad_type::COMPLETE_LOCAL_NAME, ad_type::FLAGS]
+    );
+    assert!(payload.scan_data.is_empty());
+    // with no room left they fail instead of going in the scan response
+    let filler = AdStructure::ManufacturerData {
+        company_id: 0,
+        data: &[0; 27],
+    };
+    assert_eq!(Payload::split(&[filler, flags]), Err(Error::Overflow));
+}
+
+#[test]
+fn split_is_deterministic() {
+    let list = [
+        AdStructure::Flags(0x06),
+        AdStructure::ServiceUuids16(&[BATTERY_SERVICE]),
+        AdStructure::CompleteLocalName("a name that is far too long to fit anywhere"),
+        AdStructure::Appearance(GENERIC_TAG),
+        AdStructure::TxPowerLevel(-8),
+    ];
+    let first = Payload::split(&list).unwrap();
+    assert_eq!(Payload::split(&list).unwrap(), first);
+    // list order decides, not size
+    let mut reversed = list;
+    reversed[1..].reverse();
+    assert_ne!(Payload::split(&reversed).unwrap(), first);
+}
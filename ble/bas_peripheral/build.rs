@@ -1,5 +1,7 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
+//! This build script copies the flash layout of the softdevice from the workspace root
+//! into a directory where the linker can always find it at build time, as `memory.x`.
+//! No `memory.x` may sit in the workspace root, the linker looks in the working
+//! directory before the search path.
 //! For many projects this is optional, as the linker always searches the
 //! project root directory -- wherever `Cargo.toml` is. However, if you
 //! are using a workspace or have a more complicated build setup, this
@@ -9,27 +11,95 @@
 //! new memory settings.
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
+use std::process::Command;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // on the linker search path. The S140 softdevice is bigger and has its own layout.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let memory: &[u8] = if env::var_os("CARGO_FEATURE_S140").is_some() {
+        include_bytes!("../../memory-s140.x")
+    } else {
+        include_bytes!("../../memory-s113.x")
+    };
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("../../memory.x"))
+        .write_all(memory)
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
     // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
+    // any file in the project changes. By specifying the layouts
     // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    // one of them is changed.
+    println!("cargo:rerun-if-changed=../../memory-s113.x");
+    println!("cargo:rerun-if-changed=../../memory-s140.x");
+
+    // Firmware revision for the device information service
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+    // HEAD only changes on a checkout, a commit moves the branch it points to, which is
+    // either a file of its own or a line in packed-refs
+    println!("cargo:rerun-if-changed=../../.git/HEAD");
+    if let Some(branch) = fs::read_to_string("../../.git/HEAD")
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_owned()))
+    {
+        rerun_if_exists(&format!("../../.git/{}", branch));
+    }
+    rerun_if_exists("../../.git/packed-refs");
+
+    // PnP ID of the device information service, `<source>:<vendor id>:<product id>` in hex,
+    // source `usb` for a USB-IF vendor id or `sig` for a Bluetooth SIG company identifier.
+    // Without one the pid.codes test id, fine on a desk, not in a product.
+    let pnp_id = env::var("PNP_ID").unwrap_or_else(|_| "usb:1209:0001".to_owned());
+    let (source, vendor_id, product_id) =
+        parse_pnp_id(&pnp_id).unwrap_or_else(|| panic!("PNP_ID {}: want usb|sig:VID:PID", pnp_id));
+    println!("cargo:rustc-env=PNP_VENDOR_ID_SOURCE={}", source);
+    println!("cargo:rustc-env=PNP_VENDOR_ID={}", vendor_id);
+    println!("cargo:rustc-env=PNP_PRODUCT_ID={}", product_id);
+    println!("cargo:rerun-if-env-changed=PNP_ID");
+
+    // Public key DFU images have to be signed with, `sign-image pubkey` prints it
+    let key = env::var("DFU_PUBLIC_KEY").unwrap_or_else(|_| "../image/keys/dev.pub".to_owned());
+    fs::copy(&key, out.join("dfu.pub")).unwrap_or_else(|e| panic!("{}: {}", key, e));
+    println!("cargo:rerun-if-env-changed=DFU_PUBLIC_KEY");
+    println!("cargo:rerun-if-changed={}", key);
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
+
+/// A path cargo watches has to exist, a missing one reruns the script on every build
+fn rerun_if_exists(path: &str) {
+    if fs::metadata(path).is_ok() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
+
+/// `usb:1209:0001` into the vendor id source of the PnP ID (0x01 SIG, 0x02 USB-IF) and the ids
+fn parse_pnp_id(s: &str) -> Option<(u8, u16, u16)> {
+    let mut parts = s.split(':');
+    let source = match parts.next()? {
+        "sig" => 0x01,
+        "usb" => 0x02,
+        _ => return None,
+    };
+    let vendor_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let product_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+    match parts.next() {
+        Some(_) => None,
+        None => Some((source, vendor_id, product_id)),
+    }
+}
@@ -0,0 +1,150 @@
+//! Advertising phases, and the state the rest of the app can look at.
+//!
+//! After boot, a disconnect or a button press the board advertises fast, so a client
+//! finds it quickly, then slow, then not at all until it is woken with [`wake`].
+//! `advertiser_task` in main runs the phases and reports them with [`set_state`], with
+//! rounds of beacon frames in between when there are any, see `beacon.rs`.
+//! Errors of the softdevice end up in [`State::Retrying`], nothing here panics.
+
+use core::cell::Cell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use nrf_softdevice::ble::peripheral;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum State {
+    /// Short interval, right after boot, a disconnect or a button press
+    Fast,
+    /// Long interval, once the fast window is over
+    Slow,
+    /// Not advertising until [`wake`]
+    Stopped,
+    /// Not advertising, every connection slot is taken
+    Full,
+    /// The softdevice turned the last attempt down, trying again shortly
+    Retrying,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Fast => "fast",
+            State::Slow => "slow",
+            State::Stopped => "stopped",
+            State::Full => "full",
+            State::Retrying => "retrying",
+        }
+    }
+
+    /// Where a phase goes when it times out
+    pub fn next(&self) -> State {
+        match self {
+            State::Fast => State::Slow,
+            _ => State::Stopped,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Advertising interval in 0.625 ms units
+    pub fast_interval: u32,
+    pub fast_timeout: Duration,
+    pub slow_interval: u32,
+    /// `None` to keep advertising slowly forever
+    pub slow_timeout: Option<Duration>,
+    /// Pause before another attempt after an error
+    pub retry_after: Duration,
+}
+
+impl Default for Config {
+    /// Intervals from Apple's accessory design guidelines, 20 ms for the first 30 s
+    /// and then one of their longer steps
+    fn default() -> Self {
+        Self {
+            fast_interval: 32,
+            fast_timeout: Duration::from_secs(30),
+            slow_interval: 1636,
+            slow_timeout: Some(Duration::from_secs(300)),
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Config {
+    /// Softdevice parameters for the [`State::Fast`] or [`State::Slow`] phase,
+    /// advertising for at most `time`
+    pub fn params(&self, state: State, time: Option<Duration>) -> peripheral::Config {
+        peripheral::Config {
+            interval: match state {
+                State::Fast => self.fast_interval,
+                _ => self.slow_interval,
+            },
+            timeout: time.map(timeout),
+            ..Default::default()
+        }
+    }
+}
+
+/// An advertising timeout for the softdevice, in 10 ms units
+pub fn timeout(time: Duration) -> u16 {
+    (time.as_millis() / 10).clamp(1, u16::MAX as u64) as u16
+}
+
+/// A phase and when it is over
+#[derive(Debug, Clone, Copy)]
+pub struct Phase {
+    pub state: State,
+    end: Option<Instant>,
+}
+
+impl Phase {
+    pub fn start(state: State, config: &Config) -> Self {
+        let length = match state {
+            State::Fast => Some(config.fast_timeout),
+            State::Slow => config.slow_timeout,
+            _ => None,
+        };
+        Self {
+            state,
+            end: length.map(|length| Instant::now() + length),
+        }
+    }
+
+    /// Time left, `None` for a phase without an end
+    pub fn remaining(&self) -> Option<Duration> {
+        self.end
+            .map(|end| end.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.end.is_some_and(|end| Instant::now() >= end)
+    }
+}
+
+static STATE: Mutex<ThreadModeRawMutex, Cell<State>> = Mutex::new(Cell::new(State::Stopped));
+static WAKE: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// What the advertiser is doing right now
+pub fn state() -> State {
+    STATE.lock(Cell::get)
+}
+
+pub fn set_state(state: State) {
+    if STATE.lock(|cell| cell.replace(state)) != state {
+        info!("advertising: {}", state);
+    }
+}
+
+/// Starts the fast window over, called on a button press and when a connection closes
+pub fn wake() {
+    WAKE.signal(());
+}
+
+pub async fn woken() {
+    WAKE.wait().await
+}
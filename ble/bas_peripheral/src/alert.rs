@@ -0,0 +1,261 @@
+//! Find Me and Proximity: the Immediate Alert (0x1802) and Link Loss (0x1803) services.
+//!
+//! A client writes an alert level to Immediate Alert to make the board beep and flash
+//! right away, and one to Link Loss for when the link drops. The Link Loss level is a
+//! single value in the attribute table, not one per connection.
+//!
+//! A link is lost when it ends without either side meaning to, a supervision timeout
+//! mostly. nrf-softdevice 0.1 keeps the disconnect reason to itself, so [`on_disconnect`]
+//! counts every connection as lost that this side didn't end with [`close`]. A client
+//! that means to leave and doesn't want an alert sets Link Loss to [`Level::None`] first.
+//!
+//! Only [`alert_task`] touches the speaker. An alert stops when a client writes
+//! [`Level::None`], after [`ALERT_TIME`], when the connection that raised an Immediate
+//! Alert closes, or, for a link loss, when a client connects again. The matrix gets its
+//! frame back afterwards.
+
+use core::cell::{Cell, RefCell};
+
+use defmt::{info, warn};
+use embassy_futures::select::{select3, Either3};
+use embassy_nrf::peripherals::PWM0;
+use embassy_nrf::pwm::{Prescaler, SimplePwm};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use microbit_bsp::display::Frame;
+use nrf_softdevice::ble::Connection;
+
+use crate::display::{DisplayCmd, DISPLAY};
+use crate::{Server, LINKS};
+
+/// Longest an alert goes on without anybody stopping it
+pub const ALERT_TIME: Duration = Duration::from_secs(30);
+/// The PWM counts at 1 MHz with [`Prescaler::Div16`]
+const PWM_CLOCK: u32 = 1_000_000;
+
+/// Alert Level values, 0x2A06
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum Level {
+    None = 0,
+    Mild = 1,
+    High = 2,
+}
+
+impl Level {
+    pub fn parse(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Mild),
+            2 => Some(Self::High),
+            _ => None,
+        }
+    }
+
+    /// Tone in Hz, how long it sounds and how long the pause after it is
+    fn pattern(&self) -> Option<(u32, Duration, Duration)> {
+        match self {
+            Level::None => None,
+            Level::Mild => Some((880, Duration::from_millis(150), Duration::from_millis(850))),
+            Level::High => Some((
+                2_000,
+                Duration::from_millis(100),
+                Duration::from_millis(100),
+            )),
+        }
+    }
+
+    /// What the matrix shows while the tone sounds, it is dark in between
+    fn frame(&self) -> Frame<5, 5> {
+        let mut frame = Frame::default();
+        for row in 0..5 {
+            for col in 0..5 {
+                let border = row == 0 || row == 4 || col == 0 || col == 4;
+                if *self == Level::High || (*self == Level::Mild && border) {
+                    frame.set(row, col);
+                }
+            }
+        }
+        frame
+    }
+}
+
+/// Who raised an alert, a link loss alert ends when a client connects again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Source {
+    Immediate,
+    LinkLoss,
+}
+
+static ALERTS: Signal<ThreadModeRawMutex, (Source, Level)> = Signal::new();
+/// Handle of the connection that raised the Immediate Alert, closing it ends the alert
+static IMMEDIATE_FROM: Mutex<ThreadModeRawMutex, Cell<Option<u16>>> = Mutex::new(Cell::new(None));
+/// Handles of the connections [`close`] ended, until the gatt server task sees them go
+static CLOSING: Mutex<ThreadModeRawMutex, RefCell<Vec<u16, LINKS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+#[nrf_softdevice::gatt_service(uuid = "1802")]
+pub struct ImmediateAlertService {
+    #[characteristic(uuid = "2a06", write_without_response, security = "JustWorks")]
+    alert_level: u8,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "1803")]
+pub struct LinkLossService {
+    /// Level of the alert when a link is lost, [`Level::None`] until a client sets it
+    #[characteristic(uuid = "2a06", read, write, security = "JustWorks")]
+    alert_level: u8,
+}
+
+impl ImmediateAlertService {
+    pub fn on_event(&self, conn: &Connection, event: ImmediateAlertServiceEvent) {
+        match event {
+            ImmediateAlertServiceEvent::AlertLevelWrite(value) => match Level::parse(value) {
+                Some(level) => {
+                    info!("immediate alert: {}", level);
+                    let from = conn.handle().filter(|_| level != Level::None);
+                    IMMEDIATE_FROM.lock(|owner| owner.set(from));
+                    ALERTS.signal((Source::Immediate, level));
+                }
+                None => warn!("immediate alert: unknown level {}", value),
+            },
+        }
+    }
+}
+
+impl LinkLossService {
+    pub fn on_event(&self, event: LinkLossServiceEvent) {
+        match event {
+            LinkLossServiceEvent::AlertLevelWrite(value) => match Level::parse(value) {
+                Some(level) => info!("link loss alert: {}", level),
+                None => {
+                    // the write is in the table already, out of range means no alert
+                    warn!("link loss alert: unknown level {}", value);
+                    let _ = self.alert_level_set(&(Level::None as u8));
+                }
+            },
+        }
+    }
+
+    fn level(&self) -> Level {
+        self.alert_level_get()
+            .ok()
+            .and_then(Level::parse)
+            .unwrap_or(Level::None)
+    }
+}
+
+/// Ends connection `conn` on purpose, losing it doesn't raise the Link Loss alert
+pub fn close(conn: &Connection) {
+    if let Some(handle) = conn.handle() {
+        CLOSING.lock(|closing| {
+            // a stale entry only lives until its handle is used again
+            let mut closing = closing.borrow_mut();
+            if closing.push(handle).is_err() {
+                closing.clear();
+                let _ = closing.push(handle);
+            }
+        });
+    }
+    let _ = conn.disconnect();
+}
+
+/// Ends a link loss alert, a client is back on connection `conn_handle`
+pub fn on_connect(conn_handle: Option<u16>) {
+    if let Some(handle) = conn_handle {
+        CLOSING.lock(|closing| closing.borrow_mut().retain(|&h| h != handle));
+    }
+    ALERTS.signal((Source::LinkLoss, Level::None));
+}
+
+/// Ends the Immediate Alert connection `conn_handle` raised, and raises the Link Loss
+/// level unless the connection was ended with [`close`]
+pub fn on_disconnect(server: &Server, conn_handle: Option<u16>) {
+    let Some(handle) = conn_handle else {
+        return;
+    };
+    let raised = IMMEDIATE_FROM.lock(|owner| {
+        let raised = owner.get() == Some(handle);
+        if raised {
+            owner.set(None);
+        }
+        raised
+    });
+    if raised {
+        ALERTS.signal((Source::Immediate, Level::None));
+    }
+    let closed = CLOSING.lock(|closing| {
+        let mut closing = closing.borrow_mut();
+        let len = closing.len();
+        closing.retain(|&h| h != handle);
+        closing.len() != len
+    });
+    let level = server.lls.level();
+    if !closed && level != Level::None {
+        info!("link lost, alert {}", level);
+        ALERTS.signal((Source::LinkLoss, level));
+    }
+}
+
+/// Plays the alerts on the speaker and the matrix
+#[embassy_executor::task]
+pub async fn alert_task(server: &'static Server, mut pwm: SimplePwm<'static, PWM0>) {
+    pwm.set_prescaler(Prescaler::Div16);
+    let mut next = ALERTS.wait().await;
+    loop {
+        let (source, level) = next;
+        let Some(pattern) = level.pattern() else {
+            next = ALERTS.wait().await;
+            continue;
+        };
+        let saved = server.led.frame();
+        let end = Instant::now() + ALERT_TIME;
+        let stopped = select3(
+            play(&mut pwm, level, pattern),
+            Timer::at(end),
+            changed(source),
+        )
+        .await;
+        pwm.disable();
+        DISPLAY.send(DisplayCmd::Frame(saved)).await;
+        next = match stopped {
+            Either3::Third(next) => next,
+            _ => {
+                info!("alert over");
+                ALERTS.wait().await
+            }
+        };
+    }
+}
+
+/// Waits for another alert, or the end of the one from `source`
+async fn changed(source: Source) -> (Source, Level) {
+    loop {
+        let next = ALERTS.wait().await;
+        if next.1 != Level::None || next.0 == source {
+            return next;
+        }
+    }
+}
+
+async fn play(
+    pwm: &mut SimplePwm<'static, PWM0>,
+    level: Level,
+    pattern: (u32, Duration, Duration),
+) {
+    let (tone, on, off) = pattern;
+    let max_duty = (PWM_CLOCK / tone) as u16;
+    pwm.set_max_duty(max_duty);
+    pwm.set_duty(0, max_duty / 2);
+    loop {
+        pwm.enable();
+        DISPLAY.send(DisplayCmd::Frame(level.frame())).await;
+        Timer::after(on).await;
+        pwm.disable();
+        DISPLAY.send(DisplayCmd::Frame(Frame::default())).await;
+        Timer::after(off).await;
+    }
+}
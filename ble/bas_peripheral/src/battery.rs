@@ -0,0 +1,75 @@
+//! Settings of the `battery_monitor` task and the SAADC it samples with.
+//!
+//! The conversion to millivolts, the discharge curves, the filter and the notify policy
+//! are in `ble-battery` (`ble/battery`, host tested).
+
+use embassy_nrf::peripherals::SAADC;
+use embassy_nrf::saadc::{self, ChannelConfig, Saadc};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::Duration;
+
+use crate::Irqs;
+
+pub use ble_battery::{millivolts, Curve, Filter, NotifyPolicy, Source, ALKALINE_2XAAA};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub source: Source,
+    pub curve: Curve,
+    /// Time between samples
+    pub interval: Duration,
+    /// see [`Filter`]
+    pub filter_shift: u8,
+    pub notify: NotifyPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            source: Source::Vdd,
+            curve: ALKALINE_2XAAA,
+            interval: Duration::from_secs(2),
+            filter_shift: 3,
+            notify: NotifyPolicy {
+                min_interval: 10_000,
+                hysteresis: 1,
+            },
+        }
+    }
+}
+
+/// The SAADC, shared by the battery monitor and the analog pins of the micro:bit profile.
+/// Every sample sets it up for its own channel and disables it again.
+static ADC: Mutex<ThreadModeRawMutex, Option<SAADC>> = Mutex::new(None);
+
+/// Hands the SAADC to [`sample`] and calibrates its offset, which it keeps from then on
+pub async fn init_adc(saadc: SAADC) {
+    let mut adc = ADC.lock().await;
+    let saadc = adc.insert(saadc);
+    let channel = ChannelConfig::single_ended(saadc::VddInput);
+    Saadc::new(saadc, Irqs, saadc::Config::default(), [channel])
+        .calibrate()
+        .await;
+}
+
+/// One sample of `channel`, waits while someone else samples
+pub async fn sample(channel: ChannelConfig<'_>) -> i16 {
+    let mut adc = ADC.lock().await;
+    // set by init_adc before any task samples
+    let Some(saadc) = adc.as_mut() else {
+        return 0;
+    };
+    let mut saadc = Saadc::new(saadc, Irqs, saadc::Config::default(), [channel]);
+    let mut buf = [0; 1];
+    saadc.sample(&mut buf).await;
+    buf[0]
+}
+
+/// The channel a supply `source` is sampled on
+pub fn channel(source: Source) -> ChannelConfig<'static> {
+    match source {
+        Source::Vdd => ChannelConfig::single_ended(saadc::VddInput),
+        Source::VddhDiv5 => ChannelConfig::single_ended(saadc::VddhDiv5Input),
+    }
+}
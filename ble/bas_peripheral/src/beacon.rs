@@ -0,0 +1,186 @@
+//! Non-connectable beacon frames, sent in between the connectable advertising.
+//!
+//! The frames are encoded by `ble-beacon` (`ble/beacon`, host tested) and wrapped in
+//! AD structures with `ble-adv`. The softdevice runs one advertising set, so beacons and
+//! the connectable advertisement take turns: `advertiser_task` in main advertises
+//! connectable for [`Schedule::connectable`], then sends a [`round`] of every frame of
+//! the [`Schedule`]. While connectable advertising is stopped or every connection slot
+//! is taken the rounds go on back to back.
+//!
+//! Eddystone-TLM carries the battery voltage and die temperature last measured by
+//! their monitors, which report them with [`set_battery`] and [`set_temperature`].
+
+use core::cell::Cell;
+
+use ble_adv::{flags, AdStructure, AdvData};
+use ble_beacon::{Telemetry, APPLE, EDDYSTONE};
+use defmt::warn;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use nrf_softdevice::ble::peripheral::{self, AdvertiseError};
+use nrf_softdevice::Softdevice;
+
+use crate::{advertising, dis};
+
+pub enum Frame {
+    IBeacon {
+        uuid: [u8; 16],
+        major: u16,
+        minor: u16,
+        /// RSSI at 1 m in dBm
+        measured_power: i8,
+    },
+    /// The instance is the low 6 bytes of the chip's device id
+    EddystoneUid {
+        namespace: [u8; 10],
+    },
+    EddystoneUrl(&'static str),
+    /// Battery voltage, temperature, frames sent and uptime
+    EddystoneTlm,
+}
+
+pub struct Schedule {
+    /// Sent in this order every round, empty for no beacons
+    pub frames: &'static [Frame],
+    /// Connectable advertising between two rounds
+    pub connectable: Duration,
+    /// How long each frame is advertised per round
+    pub frame_time: Duration,
+    /// Advertising interval of the frames in 0.625 ms units, at least 100 ms
+    pub interval: u32,
+    /// RSSI at 0 m in dBm, for the Eddystone frames
+    pub tx_power_0m: i8,
+}
+
+impl Schedule {
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+static TELEMETRY: Mutex<ThreadModeRawMutex, Cell<Telemetry>> = Mutex::new(Cell::new(Telemetry {
+    battery_mv: None,
+    temperature: None,
+    adv_count: 0,
+    uptime: 0,
+}));
+
+pub fn set_battery(mv: u16) {
+    TELEMETRY.lock(|t| {
+        t.set(Telemetry {
+            battery_mv: Some(mv),
+            ..t.get()
+        })
+    });
+}
+
+/// In 0.01 degrees Celsius
+pub fn set_temperature(centi_degrees: i16) {
+    TELEMETRY.lock(|t| {
+        t.set(Telemetry {
+            temperature: Some(centi_degrees),
+            ..t.get()
+        })
+    });
+}
+
+/// Adds the frames sent by advertising for `time` every `interval` (0.625 ms units),
+/// the softdevice doesn't count them so this is an estimate
+pub fn count_adv(time: Duration, interval: u32) {
+    let frames = time.as_micros() / (interval.max(1) as u64 * 625);
+    TELEMETRY.lock(|t| {
+        t.set(Telemetry {
+            adv_count: t.get().adv_count.wrapping_add(frames as u32),
+            ..t.get()
+        })
+    });
+}
+
+fn telemetry() -> Telemetry {
+    Telemetry {
+        // in 0.1 s
+        uptime: (Instant::now().as_millis() / 100) as u32,
+        ..TELEMETRY.lock(Cell::get)
+    }
+}
+
+/// Flags, the Eddystone service uuid and `frame` as its service data
+fn eddystone(data: &mut AdvData, frame: &[u8]) -> Result<(), ble_adv::Error> {
+    data.push(AdStructure::ServiceUuids16(&[EDDYSTONE]))?;
+    data.push(AdStructure::ServiceData16 {
+        uuid: EDDYSTONE,
+        data: frame,
+    })
+}
+
+/// Flags and the frame, `None` if the frame can't be encoded
+fn payload(frame: &Frame, tx_power_0m: i8) -> Option<AdvData> {
+    let mut data = AdvData::new();
+    // the first structure always fits
+    data.push(AdStructure::Flags(
+        flags::LE_GENERAL_DISC | flags::BR_EDR_NOT_SUPPORTED,
+    ))
+    .unwrap();
+    let res = match frame {
+        Frame::IBeacon {
+            uuid,
+            major,
+            minor,
+            measured_power,
+        } => data.push(AdStructure::ManufacturerData {
+            company_id: APPLE,
+            data: &ble_beacon::ibeacon(uuid, *major, *minor, *measured_power),
+        }),
+        Frame::EddystoneUid { namespace } => {
+            let id = dis::device_id().to_be_bytes();
+            let instance = id[2..].try_into().unwrap();
+            eddystone(
+                &mut data,
+                &ble_beacon::eddystone_uid(namespace, instance, tx_power_0m),
+            )
+        }
+        Frame::EddystoneUrl(url) => match ble_beacon::eddystone_url(url, tx_power_0m) {
+            Ok(frame) => eddystone(&mut data, &frame),
+            Err(e) => {
+                warn!("beacon url {} not sent: {}", url, e);
+                return None;
+            }
+        },
+        Frame::EddystoneTlm => eddystone(&mut data, &ble_beacon::eddystone_tlm(&telemetry())),
+    };
+    match res {
+        Ok(_) => Some(data),
+        Err(e) => {
+            warn!("beacon frame doesn't fit: {}", e);
+            None
+        }
+    }
+}
+
+/// Advertises every frame of `schedule` once, for [`Schedule::frame_time`] each
+pub async fn round(sd: &Softdevice, schedule: &Schedule) {
+    for frame in schedule.frames {
+        let Some(data) = payload(frame, schedule.tx_power_0m) else {
+            continue;
+        };
+        let config = peripheral::Config {
+            interval: schedule.interval,
+            timeout: Some(advertising::timeout(schedule.frame_time)),
+            ..Default::default()
+        };
+        let adv = peripheral::NonconnectableAdvertisement::NonscannableUndirected {
+            adv_data: data.as_slice(),
+        };
+        match peripheral::advertise(sd, adv, &config).await {
+            Ok(_) | Err(AdvertiseError::Timeout) => {
+                count_adv(schedule.frame_time, schedule.interval)
+            }
+            Err(e) => {
+                warn!("beacon failed: {}", e);
+                // the error may well come back, don't spin on it
+                Timer::after(schedule.frame_time).await;
+            }
+        }
+    }
+}
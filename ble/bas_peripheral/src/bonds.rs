@@ -0,0 +1,312 @@
+//! Pairing and bonding.
+//!
+//! The softdevice runs the pairing, [`Bonder`] answers its questions: Just Works by
+//! default, or with [`Pairing::Passkey`] a passkey scrolled over the LED matrix for the
+//! client to type in. A passkey bond is only kept once button A is pressed on the board.
+//! In passkey mode MITM protection is required: a client that can't type a passkey and
+//! ends up with an unauthenticated Just Works bond is disconnected and its bond dropped.
+//!
+//! Pairing is LE legacy pairing, not LE Secure Connections, so no characteristic can ask
+//! for security mode 1 level 4. nrf-softdevice 0.1 answers the security parameters
+//! request itself, with neither the LESC nor the MITM bit, and doesn't pass on
+//! BLE_GAP_EVT_LESC_DHKEY_REQUEST, and nothing here has P-256 to compute the DH key. A
+//! legacy Just Works or passkey exchange can be cracked by someone who sniffed the
+//! pairing itself, later links of the bond are only at risk if they did. LESC has to wait
+//! for nrf-softdevice to hand out those two events. Our [`request_security`] does set the
+//! MITM bit in passkey mode, which makes the client ask for a passkey.
+//!
+//! Bonds (LTK, IRK and CCCD values) live in their own flash page, `BONDS` in memory-*.x,
+//! outside the app's FLASH region so reflashing the app keeps them. The record layout is
+//! in `ble-bonds` (`ble/bonds`, host tested). The security callbacks can't await, they
+//! change the table in ram and [`bond_task`] writes it back.
+
+use core::cell::{Cell, RefCell};
+
+use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::{channel::Channel, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+use nrf_softdevice::ble::security::{IoCapabilities, SecurityHandler};
+use nrf_softdevice::ble::{
+    gatt_server, Address, AddressType, Connection, EncryptionInfo, IdentityKey,
+    IdentityResolutionKey, MasterId,
+};
+use nrf_softdevice::{raw, Flash, FlashError, RawError};
+
+use crate::display::{DisplayCmd, DISPLAY};
+use crate::{Server, PAIRING};
+
+use ble_bonds::{Bond, BondTable, MAX_SYS_ATTRS, TABLE_LEN};
+
+/// How long button A may take to confirm a passkey bond
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+/// Bit of `ltk_flags`: the key came out of a pairing with MITM protection
+const LTK_FLAG_AUTH: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Pairing {
+    /// No input or output on our side, nothing to confirm
+    JustWorks,
+    /// Passkey shown on the LED matrix, bond confirmed with button A
+    Passkey,
+}
+
+extern "C" {
+    /// First byte of the bond page, defined in memory-*.x
+    static __bonds_start: u32;
+}
+
+fn page_start() -> u32 {
+    core::ptr::addr_of!(__bonds_start) as u32
+}
+
+/// The flash api only takes word aligned buffers
+#[repr(align(4))]
+struct Page([u8; TABLE_LEN]);
+
+enum StoreCmd {
+    /// The table in ram changed
+    Save,
+    /// Passkey pairing is done, the bond is kept once button A is pressed
+    Confirm(Connection, Bond),
+}
+
+static STORE: Channel<ThreadModeRawMutex, StoreCmd, 4> = Channel::new();
+static CONFIRMED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Button A was pressed, keeps a bond waiting for confirmation
+pub fn confirm() {
+    CONFIRMED.signal(());
+}
+
+fn store(cmd: StoreCmd) {
+    if STORE.try_send(cmd).is_err() {
+        warn!("bond store busy, change dropped");
+    }
+}
+
+fn show(text: &str) {
+    if DISPLAY
+        .try_send(DisplayCmd::Text(text.try_into().unwrap_or_default(), None))
+        .is_err()
+    {
+        warn!("display busy, {} not shown", text);
+    }
+}
+
+pub struct Bonder {
+    server: &'static Server,
+    pairing: Pairing,
+    bonds: RefCell<BondTable>,
+    /// A passkey was displayed for the pairing in progress
+    passkey_shown: Cell<bool>,
+}
+
+impl Bonder {
+    pub fn new(server: &'static Server, pairing: Pairing, bonds: BondTable) -> Self {
+        info!("{} bonds, pairing: {}", bonds.len(), pairing);
+        Self {
+            server,
+            pairing,
+            bonds: RefCell::new(bonds),
+            passkey_shown: Cell::new(false),
+        }
+    }
+
+    /// Index of the bond of the peer on `conn`, resolving its private address if needed
+    fn find(bonds: &BondTable, conn: &Connection) -> Option<usize> {
+        let addr = conn.peer_address();
+        bonds.position(|bond| identity_key(bond).is_some_and(|key| key.is_match(addr)))
+    }
+}
+
+fn identity_key(bond: &Bond) -> Option<IdentityKey> {
+    let addr_type = AddressType::try_from(bond.addr_type).ok()?;
+    Some(IdentityKey {
+        irk: IdentityResolutionKey::from_raw(raw::ble_gap_irk_t { irk: bond.irk }),
+        addr: Address::new(addr_type, bond.addr),
+    })
+}
+
+impl SecurityHandler for Bonder {
+    fn io_capabilities(&self) -> IoCapabilities {
+        match self.pairing {
+            Pairing::JustWorks => IoCapabilities::None,
+            Pairing::Passkey => IoCapabilities::DisplayOnly,
+        }
+    }
+
+    fn can_bond(&self, _conn: &Connection) -> bool {
+        true
+    }
+
+    fn display_passkey(&self, passkey: &[u8; 6]) {
+        info!("passkey: {:a}", passkey);
+        self.passkey_shown.set(true);
+        // a press from before this pairing doesn't count
+        CONFIRMED.reset();
+        // the softdevice hands out ascii digits
+        show(core::str::from_utf8(passkey).unwrap_or("?"));
+    }
+
+    fn on_bonded(
+        &self,
+        conn: &Connection,
+        master_id: MasterId,
+        key: EncryptionInfo,
+        peer_id: IdentityKey,
+    ) {
+        let bond = Bond {
+            ediv: master_id.ediv,
+            rand: master_id.rand,
+            ltk: key.ltk,
+            ltk_flags: key.flags,
+            irk: peer_id.irk.as_raw().irk,
+            addr_type: peer_id.addr.address_type() as u8,
+            addr: peer_id.addr.bytes(),
+            sys_attrs: Vec::new(),
+        };
+        let passkey_shown = self.passkey_shown.take();
+        if self.pairing == Pairing::Passkey && bond.ltk_flags & LTK_FLAG_AUTH == 0 {
+            warn!("bond without MITM protection, disconnecting");
+            crate::alert::close(conn);
+            return;
+        }
+        if passkey_shown {
+            info!("bonded, press A to keep the bond");
+            store(StoreCmd::Confirm(conn.clone(), bond));
+        } else {
+            info!("bonded");
+            self.bonds.borrow_mut().insert(bond);
+            store(StoreCmd::Save);
+        }
+    }
+
+    fn get_key(&self, conn: &Connection, master_id: MasterId) -> Option<EncryptionInfo> {
+        let mut bonds = self.bonds.borrow_mut();
+        // LE Secure Connections keys have no master id, the peer is known by its address
+        let idx = if master_id.ediv == 0 && master_id.rand == [0; 8] {
+            Self::find(&bonds, conn)
+        } else {
+            bonds.position(|bond| bond.ediv == master_id.ediv && bond.rand == master_id.rand)
+        }?;
+        // the new order is written with the next save, it only matters for eviction
+        let idx = bonds.touch(idx);
+        let bond = bonds.get(idx)?;
+        // a Just Works bond from before passkey pairing was turned on, the client pairs again
+        if self.pairing == Pairing::Passkey && bond.ltk_flags & LTK_FLAG_AUTH == 0 {
+            warn!("stored bond has no MITM protection, refused");
+            return None;
+        }
+        Some(EncryptionInfo {
+            ltk: bond.ltk,
+            flags: bond.ltk_flags,
+        })
+    }
+
+    fn save_sys_attrs(&self, conn: &Connection) {
+        let mut bonds = self.bonds.borrow_mut();
+        let Some(bond) = Self::find(&bonds, conn).and_then(|idx| bonds.get_mut(idx)) else {
+            return;
+        };
+        let mut buf = [0; MAX_SYS_ATTRS];
+        let len = match gatt_server::get_sys_attrs(conn, &mut buf) {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("failed to read system attributes: {}", e);
+                return;
+            }
+        };
+        if bond.sys_attrs[..] != buf[..len] {
+            // get_sys_attrs doesn't write past the buffer
+            bond.sys_attrs = Vec::from_slice(&buf[..len]).unwrap();
+            store(StoreCmd::Save);
+        }
+    }
+
+    fn load_sys_attrs(&self, conn: &Connection) {
+        let bonds = self.bonds.borrow();
+        let sys_attrs = Self::find(&bonds, conn)
+            .and_then(|idx| bonds.get(idx))
+            .map(|bond| &bond.sys_attrs[..])
+            .filter(|sys_attrs| !sys_attrs.is_empty());
+        if let Err(e) = gatt_server::set_sys_attrs(conn, sys_attrs) {
+            warn!("failed to restore system attributes: {}", e);
+        }
+        crate::restore_subscriptions(self.server, conn);
+    }
+}
+
+/// Asks the client on `conn` to encrypt the link, with its bond or by pairing first: a
+/// Security Request, which nrf-softdevice has no call for. The keys reach the [`Bonder`]
+/// as for a pairing the client starts on its own.
+pub fn request_security(conn: &Connection) -> Result<(), RawError> {
+    let handle = conn.handle().ok_or(RawError::BleInvalidConnHandle)?;
+    // a peripheral only sends the bond and MITM bits of these
+    let mut params: raw::ble_gap_sec_params_t = unsafe { core::mem::zeroed() };
+    params.set_bond(1);
+    params.set_mitm((PAIRING == Pairing::Passkey) as u8);
+    let ret = unsafe { raw::sd_ble_gap_authenticate(handle, &params) };
+    RawError::convert(ret)
+}
+
+/// Reads the bonds saved in flash
+pub async fn load(flash: &mut Flash) -> BondTable {
+    let mut page = Page([0; TABLE_LEN]);
+    match flash.read(page_start(), &mut page.0).await {
+        Ok(_) => BondTable::decode(&page.0),
+        Err(e) => {
+            warn!("failed to read bonds: {}", e);
+            BondTable::new()
+        }
+    }
+}
+
+/// Forgets every bond
+pub async fn erase(flash: &mut Flash) -> Result<(), FlashError> {
+    let start = page_start();
+    flash.erase(start, start + Flash::ERASE_SIZE as u32).await
+}
+
+/// Writes the page to flash, unless it is already there
+async fn save(flash: &mut Flash, page: &Page) -> Result<(), FlashError> {
+    let mut stored = Page([0; TABLE_LEN]);
+    flash.read(page_start(), &mut stored.0).await?;
+    if stored.0 == page.0 {
+        return Ok(());
+    }
+    erase(flash).await?;
+    flash.write(page_start(), &page.0).await
+}
+
+/// Persists the bond table and waits for button A on passkey bonds
+#[embassy_executor::task]
+pub async fn bond_task(flash: &'static Mutex<NoopRawMutex, Flash>, bonder: &'static Bonder) {
+    loop {
+        match STORE.receive().await {
+            StoreCmd::Save => {}
+            StoreCmd::Confirm(conn, bond) => {
+                match select(CONFIRMED.wait(), Timer::after(CONFIRM_TIMEOUT)).await {
+                    Either::First(_) => {
+                        info!("bond confirmed");
+                        show("OK");
+                        bonder.bonds.borrow_mut().insert(bond);
+                    }
+                    Either::Second(_) => {
+                        warn!("bond not confirmed, disconnecting");
+                        crate::alert::close(&conn);
+                        continue;
+                    }
+                }
+            }
+        }
+        // a copy, the security callbacks may change the table while flash is busy
+        let page = Page(bonder.bonds.borrow().encode());
+        if let Err(e) = save(&mut *flash.lock().await, &page).await {
+            warn!("failed to save bonds: {}", e);
+        }
+    }
+}
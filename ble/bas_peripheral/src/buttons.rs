@@ -0,0 +1,127 @@
+//! Buttons A and B over BLE, same uuids and values as the micro:bit button service:
+//! https://lancaster-university.github.io/microbit-docs/resources/bluetooth/bluetooth_profile.html
+
+use defmt::{debug, info};
+use embassy_futures::select::{select, Either};
+use embassy_nrf::gpio::{AnyPin, Input};
+use embassy_time::{Duration, Timer};
+
+use crate::conns::{self, CONNS};
+use crate::{advertising, bonds, Server};
+
+/// Held this long a press becomes a long press
+pub const LONG_PRESS: Duration = Duration::from_secs(2);
+const DEBOUNCE: Duration = Duration::from_millis(10);
+
+type Btn = Input<'static, AnyPin>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Button {
+    A = 0,
+    B = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum ButtonState {
+    NotPressed = 0,
+    Pressed = 1,
+    LongPressed = 2,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "e95d9882-251d-470a-a062-fa1922dfa9a8")]
+pub struct ButtonService {
+    #[characteristic(uuid = "e95dda90-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    button_a_state: u8,
+    #[characteristic(uuid = "e95dda91-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    button_b_state: u8,
+}
+
+impl ButtonService {
+    /// CCCD handles of the A and B states, in [`Button`] order
+    pub fn cccd_handles(&self) -> [u16; 2] {
+        [
+            self.button_a_state_cccd_handle,
+            self.button_b_state_cccd_handle,
+        ]
+    }
+
+    /// Records the subscription of connection slot `idx`
+    pub fn on_event(&self, idx: usize, event: ButtonServiceEvent) {
+        let (button, notifications) = match event {
+            ButtonServiceEvent::ButtonAStateCccdWrite { notifications } => {
+                (Button::A, notifications)
+            }
+            ButtonServiceEvent::ButtonBStateCccdWrite { notifications } => {
+                (Button::B, notifications)
+            }
+        };
+        info!("button {} notifications {}: {}", button, idx, notifications);
+        conns::subscriptions(idx, |sub| {
+            sub.button_notify[button as usize] = notifications
+        });
+    }
+
+    /// Stores the new state and notifies every subscribed client
+    async fn publish(
+        &self,
+        server: &Server,
+        button: Button,
+        from: ButtonState,
+        state: ButtonState,
+    ) {
+        debug!("button {}: {}", button, state);
+        if state == ButtonState::Pressed {
+            // a client looking for the board after advertising stopped
+            advertising::wake();
+            if button == Button::A {
+                bonds::confirm();
+            }
+        }
+        server.profile.on_button(button, from, state).await;
+        server.hid.on_button(button, from, state).await;
+        let value = state as u8;
+        let _ = match button {
+            Button::A => self.button_a_state_set(&value),
+            Button::B => self.button_b_state_set(&value),
+        };
+        for (idx, slot) in CONNS.lock().await.indexed() {
+            if !conns::subscriptions(idx, |sub| sub.button_notify[button as usize]) {
+                continue;
+            }
+            let res = match button {
+                Button::A => self.button_a_state_notify(&slot.conn, &value),
+                Button::B => self.button_b_state_notify(&slot.conn, &value),
+            };
+            if let Err(e) = res {
+                info!("failed to send button notice: {}", e);
+            }
+        }
+    }
+}
+
+/// Follows one (active low) button through pressed, long pressed and released
+#[embassy_executor::task(pool_size = 2)]
+pub async fn button_task(server: &'static Server, button: Button, mut btn: Btn) {
+    let buttons = &server.buttons;
+    loop {
+        btn.wait_for_low().await;
+        let mut state = ButtonState::Pressed;
+        buttons
+            .publish(server, button, ButtonState::NotPressed, state)
+            .await;
+        Timer::after(DEBOUNCE).await;
+
+        if let Either::Second(_) = select(btn.wait_for_high(), Timer::after(LONG_PRESS)).await {
+            buttons
+                .publish(server, button, state, ButtonState::LongPressed)
+                .await;
+            state = ButtonState::LongPressed;
+            btn.wait_for_high().await;
+        }
+        buttons
+            .publish(server, button, state, ButtonState::NotPressed)
+            .await;
+        Timer::after(DEBOUNCE).await;
+    }
+}
@@ -0,0 +1,163 @@
+//! Central role: finding other devices and connecting to them, S140 builds only.
+//!
+//! [`discover`] scans for a while and keeps what it heard, strongest first, and
+//! [`connect`] opens a link to one of those devices by its index in that list. The link
+//! stays in [`PEER`] until [`disconnect`] or the other side goes away. It doesn't take a
+//! slot in `CONNS`, those are the clients connected to us. `client.rs` subscribes to the
+//! peer's services.
+//!
+//! The NUS console drives it with `scan`, `connect <n>` and `disconnect`.
+
+use core::cmp::Reverse;
+use core::fmt::{self, Write};
+
+use ble_adv::ad_type;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use embassy_time::Duration;
+use heapless::Vec;
+use nrf_softdevice::ble::central::{self, ConnectConfig, ConnectError, ScanConfig, ScanError};
+use nrf_softdevice::ble::{Address, Connection};
+use nrf_softdevice::{raw, Softdevice};
+
+use crate::{advertising, client, link, name, ATT_MTU};
+
+/// Links we open ourselves, on top of the `MAX_CONNS` clients
+pub const MAX_PEERS: usize = 1;
+/// Devices kept from a scan, the weakest ones don't make it
+pub const MAX_FOUND: usize = 8;
+/// How long the console's `scan` listens
+pub const SCAN_TIME: Duration = Duration::from_secs(3);
+/// How long [`connect`] looks for the device before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, defmt::Format)]
+pub enum Error {
+    /// No device with that index in the last scan
+    NotFound,
+    /// Already connected to a peer, [`disconnect`] first
+    Busy,
+    Connect(ConnectError),
+}
+
+/// A device heard during a scan
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub addr: Address,
+    /// Of the last report, in dBm
+    pub rssi: i8,
+    pub connectable: bool,
+    /// From the advertising data or the scan response, empty if it has none
+    pub name: name::Name,
+}
+
+static FOUND: Mutex<ThreadModeRawMutex, Vec<Found, MAX_FOUND>> = Mutex::new(Vec::new());
+/// The link opened by [`connect`]
+pub static PEER: Mutex<ThreadModeRawMutex, Option<Connection>> = Mutex::new(None);
+
+/// Scans actively for `time` and returns the devices it heard, strongest first.
+/// The list is kept for [`connect`].
+pub async fn discover(sd: &Softdevice, time: Duration) -> Result<Vec<Found, MAX_FOUND>, ScanError> {
+    let config = ScanConfig {
+        active: true,
+        timeout: advertising::timeout(time),
+        ..Default::default()
+    };
+    let mut found = Vec::new();
+    let res = central::scan(sd, &config, |report| {
+        note(&mut found, report);
+        None::<()>
+    })
+    .await;
+    match res {
+        Ok(()) | Err(ScanError::Timeout) => {}
+        Err(e) => return Err(e),
+    }
+    found.sort_unstable_by_key(|found: &Found| Reverse(found.rssi));
+    *FOUND.lock().await = found.clone();
+    Ok(found)
+}
+
+/// Adds a report to `found`, the scan response of a device goes into the same entry
+fn note(found: &mut Vec<Found, MAX_FOUND>, report: &raw::ble_gap_evt_adv_report_t) {
+    let addr = Address::from_raw(report.peer_addr);
+    // the softdevice hands out the data of the report it is called for
+    let data = unsafe { core::slice::from_raw_parts(report.data.p_data, report.data.len as usize) };
+    let name = local_name(data).and_then(|name| name.parse().ok());
+    match found.iter_mut().find(|found| found.addr == addr) {
+        Some(found) => {
+            found.rssi = report.rssi;
+            if let Some(name) = name {
+                found.name = name;
+            }
+        }
+        None => {
+            // a full list drops the rest, the scan is short
+            let _ = found.push(Found {
+                addr,
+                rssi: report.rssi,
+                connectable: report.type_.connectable() != 0,
+                name: name.unwrap_or_default(),
+            });
+        }
+    }
+}
+
+/// The complete or shortened local name in advertising data
+fn local_name(mut data: &[u8]) -> Option<&str> {
+    while let [len, rest @ ..] = data {
+        let len = *len as usize;
+        // a zero length ends the data early
+        if len == 0 || len > rest.len() {
+            return None;
+        }
+        let (ad, tail) = rest.split_at(len);
+        if let [ad_type::COMPLETE_LOCAL_NAME | ad_type::SHORTENED_LOCAL_NAME, name @ ..] = ad {
+            return core::str::from_utf8(name).ok();
+        }
+        data = tail;
+    }
+    None
+}
+
+/// `addr` the way phones show it, most significant byte first
+pub fn write_addr(out: &mut impl Write, addr: &Address) -> fmt::Result {
+    for (i, byte) in addr.bytes().iter().rev().enumerate() {
+        let sep = if i == 0 { "" } else { ":" };
+        write!(out, "{}{:02X}", sep, byte)?;
+    }
+    Ok(())
+}
+
+/// Connects to device `idx` of the last [`discover`], on the low latency profile
+pub async fn connect(sd: &Softdevice, idx: usize) -> Result<Address, Error> {
+    let mut peer = PEER.lock().await;
+    if peer.as_ref().is_some_and(|conn| conn.handle().is_some()) {
+        return Err(Error::Busy);
+    }
+    let addr = FOUND.lock().await.get(idx).ok_or(Error::NotFound)?.addr;
+
+    let whitelist = [&addr];
+    let config = ConnectConfig {
+        scan_config: ScanConfig {
+            whitelist: Some(&whitelist),
+            timeout: advertising::timeout(CONNECT_TIMEOUT),
+            ..Default::default()
+        },
+        conn_params: link::LOW_LATENCY.params(),
+        att_mtu: Some(ATT_MTU),
+    };
+    let conn = central::connect(sd, &config)
+        .await
+        .map_err(Error::Connect)?;
+    client::on_connect(conn.clone());
+    *peer = Some(conn);
+    Ok(addr)
+}
+
+/// Drops the link to the peer, `false` if there was none or it was gone already
+pub async fn disconnect() -> bool {
+    match PEER.lock().await.take() {
+        Some(conn) => conn.disconnect().is_ok(),
+        None => false,
+    }
+}
@@ -0,0 +1,154 @@
+//! GATT client: reads the battery or heart rate service of the peer we connected to,
+//! S140 builds only.
+//!
+//! Once `central::connect` has a link, [`client_task`] discovers the peer's primary
+//! services and characteristics. The handles it finds stay in the client struct for as
+//! long as the link lasts, so reads, CCCD writes and notifications go straight to them.
+//! The battery service is preferred, the heart rate service is the fallback. Every value
+//! goes into [`READINGS`], [`show_task`] scrolls them across the matrix.
+
+use core::fmt::Write;
+
+use defmt::{info, warn};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel, signal::Signal};
+use heapless::{String, Vec};
+use nrf_softdevice::ble::gatt_client::{self, DiscoverError, ReadError, WriteError};
+use nrf_softdevice::ble::Connection;
+
+use crate::display::{DisplayCmd, DISPLAY, MAX_TEXT};
+use crate::nus::MAX_PAYLOAD;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Reading {
+    /// Battery level in percent
+    Battery(u8),
+    /// Beats per minute
+    HeartRate(u16),
+}
+
+/// Values from the peer for the rest of the app, new ones are dropped while it is full
+pub static READINGS: Channel<ThreadModeRawMutex, Reading, 4> = Channel::new();
+static CONNECTED: Signal<ThreadModeRawMutex, Connection> = Signal::new();
+
+#[nrf_softdevice::gatt_client(uuid = "180f")]
+struct BatteryServiceClient {
+    #[characteristic(uuid = "2a19", read, notify)]
+    battery_level: u8,
+}
+
+#[nrf_softdevice::gatt_client(uuid = "180d")]
+struct HeartRateServiceClient {
+    /// Flags, the rate and optional fields, see [`heart_rate`]
+    #[characteristic(uuid = "2a37", notify)]
+    heart_rate_measurement: Vec<u8, MAX_PAYLOAD>,
+}
+
+#[derive(Debug, defmt::Format)]
+enum Error {
+    Discover(DiscoverError),
+    Read(ReadError),
+    Write(WriteError),
+}
+
+impl From<DiscoverError> for Error {
+    fn from(e: DiscoverError) -> Self {
+        Self::Discover(e)
+    }
+}
+
+impl From<ReadError> for Error {
+    fn from(e: ReadError) -> Self {
+        Self::Read(e)
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(e: WriteError) -> Self {
+        Self::Write(e)
+    }
+}
+
+/// Hands a new link to [`client_task`]
+pub fn on_connect(conn: Connection) {
+    CONNECTED.signal(conn);
+}
+
+/// Subscribes to every link [`on_connect`] hands over, until it goes away
+#[embassy_executor::task]
+pub async fn client_task() {
+    loop {
+        let conn = CONNECTED.wait().await;
+        match subscribe(&conn).await {
+            Ok(()) => info!("gatt client: peer disconnected"),
+            Err(e) => warn!("gatt client: {}", e),
+        }
+    }
+}
+
+async fn subscribe(conn: &Connection) -> Result<(), Error> {
+    match gatt_client::discover::<BatteryServiceClient>(conn).await {
+        Ok(client) => {
+            info!("gatt client: battery service");
+            publish(Reading::Battery(client.battery_level_read().await?));
+            client.battery_level_cccd_write(true).await?;
+            let _ = gatt_client::run(conn, &client, |event| match event {
+                BatteryServiceClientEvent::BatteryLevelNotification(level) => {
+                    publish(Reading::Battery(level))
+                }
+            })
+            .await;
+            return Ok(());
+        }
+        Err(DiscoverError::ServiceNotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let client: HeartRateServiceClient = gatt_client::discover(conn).await?;
+    info!("gatt client: heart rate service");
+    client.heart_rate_measurement_cccd_write(true).await?;
+    let _ = gatt_client::run(conn, &client, |event| match event {
+        HeartRateServiceClientEvent::HeartRateMeasurementNotification(data) => {
+            match heart_rate(&data) {
+                Some(bpm) => publish(Reading::HeartRate(bpm)),
+                None => warn!("gatt client: short heart rate measurement"),
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+
+fn publish(reading: Reading) {
+    // gatt callbacks can't wait, a newer reading comes along soon
+    let _ = READINGS.try_send(reading);
+}
+
+/// The rate of a Heart Rate Measurement: a u8 after the flags, or a u16 when bit 0 of
+/// the flags is set (Heart Rate Service spec, 3.1.1)
+fn heart_rate(data: &[u8]) -> Option<u16> {
+    match data {
+        [flags, rate, ..] if flags & 1 == 0 => Some(*rate as u16),
+        [_, lo, hi, ..] => Some(u16::from_le_bytes([*lo, *hi])),
+        _ => None,
+    }
+}
+
+/// Scrolls every reading that differs from the last one
+#[embassy_executor::task]
+pub async fn show_task() {
+    let mut last = None;
+    loop {
+        let reading = READINGS.receive().await;
+        if last == Some(reading) {
+            continue;
+        }
+        last = Some(reading);
+        let mut text: String<MAX_TEXT> = String::new();
+        // a few digits always fit
+        let _ = match reading {
+            Reading::Battery(level) => write!(text, "{}%", level),
+            Reading::HeartRate(bpm) => write!(text, "{} bpm", bpm),
+        };
+        DISPLAY.send(DisplayCmd::Text(text, None)).await;
+    }
+}
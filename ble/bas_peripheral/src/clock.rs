@@ -0,0 +1,155 @@
+//! Wall clock, set from the Current Time Service (0x1805) of a connected phone.
+//!
+//! On every connection [`clock_task`] looks for CTS on the client, reads the Current Time
+//! and subscribes to it, the phone notifies when its clock is adjusted. The discovery
+//! waits for `link.rs` to have exchanged the ATT_MTU, the softdevice would turn the
+//! exchange down while our client is busy. The time is kept
+//! as an offset against the embassy clock, which counts on the RTC, so [`now`] costs
+//! nothing and keeps going after the phone is gone. Until a phone has set it there is no
+//! calendar time, only uptime. The phone sends its local time, so that is what [`now`]
+//! returns. Decoding and the date arithmetic are in `ble-clock` (`ble/clock`, host
+//! tested).
+//!
+//! Most phones only serve CTS on an encrypted link. A read the phone turns down asks it
+//! to pair, then tries again.
+
+use core::cell::Cell;
+
+use ble_clock::{CurrentTime, DateTime};
+use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use nrf_softdevice::ble::gatt_client::{self, DiscoverError, ReadError, WriteError};
+use nrf_softdevice::ble::Connection;
+
+use crate::bonds;
+use crate::link;
+use crate::nus::MAX_PAYLOAD;
+
+/// Reads turned down before giving up on a phone
+const RETRIES: usize = 3;
+/// Time the phone gets to pair before the next read
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Wall clock milliseconds since 1970 at `Instant` 0, `None` until a phone sets it
+static OFFSET: Mutex<ThreadModeRawMutex, Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+/// A new link and its connection slot
+static CONNECTED: Signal<ThreadModeRawMutex, (Connection, usize)> = Signal::new();
+
+#[nrf_softdevice::gatt_client(uuid = "1805")]
+struct CurrentTimeServiceClient {
+    /// Exact Time 256 and the adjust reason, see [`CurrentTime::decode`]
+    #[characteristic(uuid = "2a2b", read, notify)]
+    current_time: Vec<u8, MAX_PAYLOAD>,
+}
+
+#[derive(Debug, defmt::Format)]
+enum Error {
+    Discover(DiscoverError),
+    Read(ReadError),
+    Write(WriteError),
+}
+
+impl From<DiscoverError> for Error {
+    fn from(e: DiscoverError) -> Self {
+        Self::Discover(e)
+    }
+}
+
+impl From<ReadError> for Error {
+    fn from(e: ReadError) -> Self {
+        Self::Read(e)
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(e: WriteError) -> Self {
+        Self::Write(e)
+    }
+}
+
+/// The local time, once a phone has set it
+pub fn now() -> Option<DateTime> {
+    let offset = OFFSET.lock(Cell::get)?;
+    Some(DateTime::from_unix_millis(
+        offset + Instant::now().as_millis(),
+    ))
+}
+
+/// Sets the clock to `value`, a Current Time from the phone
+fn set(value: &[u8]) {
+    let time = match CurrentTime::decode(value) {
+        Ok(time) => time.time,
+        Err(e) => {
+            warn!("clock: bad current time: {}", e);
+            return;
+        }
+    };
+    let Some(millis) = time.to_unix_millis() else {
+        warn!("clock: {} is before 1970", time);
+        return;
+    };
+    // a phone set to a date before the board booted, the clock can't go that far back
+    let offset = millis.saturating_sub(Instant::now().as_millis());
+    OFFSET.lock(|cell| cell.set(Some(offset)));
+    info!("clock set: {}", time);
+}
+
+/// Hands the new link in connection slot `idx` to [`clock_task`]
+pub fn on_connect(conn: Connection, idx: usize) {
+    CONNECTED.signal((conn, idx));
+}
+
+/// Keeps the clock in step with the latest phone [`on_connect`] hands over
+#[embassy_executor::task]
+pub async fn clock_task() {
+    let mut link = CONNECTED.wait().await;
+    loop {
+        let (conn, idx) = &link;
+        let next = match select(sync(conn, *idx), CONNECTED.wait()).await {
+            Either::First(res) => {
+                match res {
+                    Ok(()) => info!("clock: phone disconnected"),
+                    Err(Error::Discover(DiscoverError::ServiceNotFound)) => {
+                        info!("clock: no current time service")
+                    }
+                    Err(e) => warn!("clock: {}", e),
+                }
+                CONNECTED.wait().await
+            }
+            Either::Second(next) => next,
+        };
+        link = next;
+    }
+}
+
+async fn sync(conn: &Connection, idx: usize) -> Result<(), Error> {
+    link::negotiated(idx).await;
+    let client: CurrentTimeServiceClient = gatt_client::discover(conn).await?;
+    let mut retries = 0;
+    let value = loop {
+        match client.current_time_read().await {
+            Ok(value) => break value,
+            Err(ReadError::Gatt(e)) if retries < RETRIES => {
+                info!("clock: read turned down ({}), pairing", e);
+                if let Err(e) = bonds::request_security(conn) {
+                    warn!("clock: failed to ask for pairing: {}", e);
+                }
+                retries += 1;
+                Timer::after(RETRY_AFTER).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    set(&value);
+    client.current_time_cccd_write(true).await?;
+    let _ = gatt_client::run(conn, &client, |event| match event {
+        CurrentTimeServiceClientEvent::CurrentTimeNotification(value) => set(&value),
+    })
+    .await;
+    Ok(())
+}
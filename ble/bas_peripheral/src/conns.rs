@@ -0,0 +1,163 @@
+//! Registry of the live connections.
+//!
+//! Every connection gets a slot, the slot index doubles as the id of the
+//! gatt server task that serves it. The advertiser only runs while there is a free slot,
+//! see `advertising.rs`.
+//!
+//! What a client subscribed to is kept per slot as well, in [`Subscriptions`], and so is
+//! its partial console line. The CCCD and NUS writes come in through gatt callbacks,
+//! which can't wait for the [`CONNS`] lock, so those live in blocking mutexes of their
+//! own and a write is never dropped.
+
+use core::cell::RefCell;
+
+use ble_console::LineBuffer;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Instant};
+use nrf_softdevice::ble::Connection;
+
+/// Max simultaneous connections, also what the softdevice is configured for
+pub const MAX_CONNS: usize = 2;
+
+/// Book keeping for one connection
+pub struct ConnState {
+    pub connected_at: Instant,
+}
+
+/// CCCD state of one connection and what it was notified last
+#[derive(Debug, Clone, Copy)]
+pub struct Subscriptions {
+    /// `None` while the slot is free
+    pub conn_handle: Option<u16>,
+    /// The client has enabled battery level notifications in its CCCD
+    pub battery_notify: bool,
+    /// Last battery level notified to this client and when
+    pub battery_sent: Option<(u8, Instant)>,
+    /// The client has enabled temperature notifications in its CCCD
+    pub temperature_notify: bool,
+    /// Last temperature notified to this client and when
+    pub temperature_sent: Option<(i16, Instant)>,
+    /// The client wants button notifications, indexed by `Button`
+    pub button_notify: [bool; 2],
+}
+
+impl Subscriptions {
+    const NONE: Self = Self {
+        conn_handle: None,
+        battery_notify: false,
+        battery_sent: None,
+        temperature_notify: false,
+        temperature_sent: None,
+        button_notify: [false; 2],
+    };
+}
+
+static SUBSCRIPTIONS: BlockingMutex<ThreadModeRawMutex, RefCell<[Subscriptions; MAX_CONNS]>> =
+    BlockingMutex::new(RefCell::new([Subscriptions::NONE; MAX_CONNS]));
+
+/// Runs `f` on the subscriptions of connection slot `idx`
+pub fn subscriptions<R>(idx: usize, f: impl FnOnce(&mut Subscriptions) -> R) -> R {
+    SUBSCRIPTIONS.lock(|subs| f(&mut subs.borrow_mut()[idx]))
+}
+
+/// Runs `f` on the subscriptions of the connection with `conn_handle`, if it has a slot
+pub fn subscriptions_of<R>(conn_handle: u16, f: impl FnOnce(&mut Subscriptions) -> R) -> Option<R> {
+    SUBSCRIPTIONS.lock(|subs| {
+        let mut subs = subs.borrow_mut();
+        let sub = subs
+            .iter_mut()
+            .find(|sub| sub.conn_handle == Some(conn_handle))?;
+        Some(f(sub))
+    })
+}
+
+/// Partial console line written over NUS, per slot
+static CONSOLES: BlockingMutex<ThreadModeRawMutex, RefCell<[LineBuffer; MAX_CONNS]>> =
+    BlockingMutex::new(RefCell::new([const { LineBuffer::new() }; MAX_CONNS]));
+
+/// Runs `f` on the console line buffer of connection slot `idx`
+pub fn console<R>(idx: usize, f: impl FnOnce(&mut LineBuffer) -> R) -> R {
+    CONSOLES.lock(|consoles| f(&mut consoles.borrow_mut()[idx]))
+}
+
+pub struct Slot {
+    pub conn: Connection,
+    pub state: ConnState,
+}
+
+pub struct Registry {
+    slots: [Option<Slot>; MAX_CONNS],
+}
+
+impl Registry {
+    const EMPTY: Option<Slot> = None;
+
+    pub const fn new() -> Self {
+        Self {
+            slots: [Self::EMPTY; MAX_CONNS],
+        }
+    }
+
+    /// Stores `conn` in a free slot and returns its index, gives `conn` back if full
+    pub fn insert(&mut self, conn: Connection) -> Result<usize, Connection> {
+        let Some(idx) = self.slots.iter().position(Option::is_none) else {
+            return Err(conn);
+        };
+        subscriptions(idx, |sub| {
+            *sub = Subscriptions {
+                conn_handle: conn.handle(),
+                ..Subscriptions::NONE
+            }
+        });
+        console(idx, |console| *console = LineBuffer::new());
+        self.slots[idx] = Some(Slot {
+            conn,
+            state: ConnState {
+                connected_at: Instant::now(),
+            },
+        });
+        Ok(idx)
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Option<Slot> {
+        let slot = self.slots.get_mut(idx).and_then(Option::take);
+        if slot.is_some() {
+            subscriptions(idx, |sub| *sub = Subscriptions::NONE);
+        }
+        slot
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Slot> {
+        self.slots.get(idx).and_then(Option::as_ref)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Slot> {
+        self.slots.iter().flatten()
+    }
+
+    /// The slots in use with their index
+    pub fn indexed(&self) -> impl Iterator<Item = (usize, &Slot)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, slot)| Some((idx, slot.as_ref()?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == MAX_CONNS
+    }
+}
+
+pub static CONNS: Mutex<ThreadModeRawMutex, Registry> = Mutex::new(Registry::new());
+
+/// Frees the slot `idx`, the caller wakes the advertiser. Returns how long the
+/// connection lasted
+pub async fn release(idx: usize) -> Option<Duration> {
+    let slot = CONNS.lock().await.remove(idx)?;
+    Some(slot.state.connected_at.elapsed())
+}
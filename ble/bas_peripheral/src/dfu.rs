@@ -0,0 +1,527 @@
+//! Firmware update over BLE into the second flash slot.
+//!
+//! memory-*.x split the flash into the app slot (FLASH), the DFU slot, the bootloader
+//! (`ble/bootloader`) and its state page. A transfer goes:
+//!
+//! 1. Start with the image length and CRC-32 on the control point, the slot is erased
+//! 2. the image on the data characteristic, [`BLOCK_LEN`] bytes at a time, the client
+//!    waits for the progress notification of a block before it sends the next one.
+//!    The header is kept in ram, an image that is too old or not for this slot is
+//!    turned down with its first block
+//! 3. Finish, the binary is read back from flash and checked against the CRC and the
+//!    signature, only then the bootloader is told to swap the slots
+//! 4. Reset, the bootloader swaps and starts the new image
+//!
+//! One client at a time: the transfer belongs to the connection that started it, data
+//! and commands from other connections are turned down, and it is aborted when that
+//! connection closes.
+//!
+//! A new image is on trial until it has run for [`TRIAL_PERIOD`] with the watchdog fed,
+//! then it marks itself booted. A reset before that, including one from the watchdog
+//! on a hang, makes the bootloader swap the old image back.
+//!
+//! Images are signed by `sign-image` from `ble/image`, see its docs for the format. The
+//! key they are checked with comes from `DFU_PUBLIC_KEY` at build time, see build.rs.
+//! The version is the package version, `sign-image` checks it against the
+//! [`VERSION_RECORD`] in the binary. An image older than the running one, or than the
+//! newest one accepted before, is turned down. That one is kept in `settings`, so an
+//! image that failed its trial can't be followed by an older one.
+
+use core::cell::RefCell;
+
+use defmt::{info, warn};
+use embassy_boot::State;
+use embassy_boot_nrf::{AlignedBuffer, FirmwareUpdater, FirmwareUpdaterConfig};
+use embassy_embedded_hal::flash::partition::Partition;
+use embassy_futures::select::{select, Either};
+use embassy_nrf::peripherals::WDT;
+use embassy_nrf::wdt::{self, Watchdog, WatchdogHandle};
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::Flash;
+
+use ble_image::{Error as ImageError, Header, Verifier, HEADER_LEN};
+
+use crate::nus::MAX_PAYLOAD;
+use crate::{settings, Server};
+
+pub mod protocol;
+use protocol::{opcode, Command, Crc32, Status, BLOCK_LEN};
+
+/// How long a new image has to run before it counts as good
+pub const TRIAL_PERIOD: Duration = Duration::from_secs(30);
+/// Watchdog timeout when the bootloader hasn't started it already, in 32768 Hz ticks
+const WATCHDOG_TICKS: u32 = 32768 * 8;
+const PET_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Updates have to be at least this version
+const VERSION: u32 = match ble_image::parse_version(env!("CARGO_PKG_VERSION")) {
+    Some(version) => version,
+    None => panic!("the package version doesn't fit an image version"),
+};
+/// Tells `sign-image` the version of the binary
+#[used]
+static VERSION_RECORD: [u8; ble_image::VERSION_RECORD_LEN] = ble_image::version_record(VERSION);
+/// Start of the record of the newest accepted version in `settings`
+const ACCEPTED_MAGIC: [u8; 4] = *b"VER1";
+/// Magic and the version
+pub const ACCEPTED_LEN: usize = 8;
+/// Updates have to be signed with the secret key of this one
+const PUBLIC_KEY: [u8; 32] =
+    match ble_image::parse_key(include_str!(concat!(env!("OUT_DIR"), "/dfu.pub"))) {
+        Some(key) => key,
+        None => panic!("DFU_PUBLIC_KEY is not a 32 byte key in hex"),
+    };
+
+/// Longest control point write, Start
+const MAX_CONTROL: usize = 9;
+
+type DfuSlot = Partition<'static, NoopRawMutex, Flash>;
+
+#[nrf_softdevice::gatt_service(uuid = "5d0a0010-6d62-4b6f-9f21-3c3b2a9e8f10")]
+pub struct DfuService {
+    /// Commands in, responses and progress out, see `protocol`
+    #[characteristic(
+        uuid = "5d0a0011-6d62-4b6f-9f21-3c3b2a9e8f10",
+        write,
+        notify,
+        security = "JustWorks"
+    )]
+    control: Vec<u8, MAX_CONTROL>,
+    /// The image, in order
+    #[characteristic(
+        uuid = "5d0a0012-6d62-4b6f-9f21-3c3b2a9e8f10",
+        write,
+        write_without_response,
+        security = "JustWorks"
+    )]
+    data: Vec<u8, MAX_PAYLOAD>,
+}
+
+// blocks are what the channel is for, and there is no heap to box them
+#[allow(clippy::large_enum_variant)]
+enum DfuCmd {
+    /// A command from the client on connection `conn`
+    Control {
+        conn: u16,
+        op: u8,
+        command: Result<Command, Status>,
+    },
+    /// The next block of the image from connection `conn`, the last one may be short
+    Block {
+        conn: u16,
+        block: Vec<u8, BLOCK_LEN>,
+    },
+    /// Connection `conn` is closed
+    Disconnected(u16),
+}
+
+static DFU: Channel<ThreadModeRawMutex, DfuCmd, 3> = Channel::new();
+
+/// Data written since the last full block
+struct Pending {
+    /// Connection of the transfer, set by [`dfu_task`], data from others is dropped
+    owner: Option<u16>,
+    block: Vec<u8, BLOCK_LEN>,
+}
+
+static BLOCK: BlockingMutex<ThreadModeRawMutex, RefCell<Pending>> =
+    BlockingMutex::new(RefCell::new(Pending {
+        owner: None,
+        block: Vec::new(),
+    }));
+
+/// Data writes from `conn` go into blocks from now on, an empty block
+fn own(conn: Option<u16>) {
+    BLOCK.lock(|pending| {
+        let mut pending = pending.borrow_mut();
+        pending.owner = conn;
+        pending.block.clear();
+    });
+}
+
+fn queue_block(conn: u16, block: &mut Vec<u8, BLOCK_LEN>) {
+    let block = core::mem::take(block);
+    if DFU.try_send(DfuCmd::Block { conn, block }).is_err() {
+        // the image ends up short, Finish reports it
+        warn!("dfu block dropped, the client didn't wait for progress");
+    }
+}
+
+impl DfuService {
+    /// Collects the data writes into blocks and hands blocks and commands to [`dfu_task`]
+    pub fn on_event(&self, conn: &Connection, event: DfuServiceEvent) {
+        let Some(conn) = conn.handle() else {
+            return;
+        };
+        match event {
+            DfuServiceEvent::ControlWrite(data) => {
+                let (op, command) = protocol::parse(&data);
+                // the rest of the image goes in before the command
+                if command == Ok(Command::Finish) {
+                    BLOCK.lock(|pending| {
+                        let mut pending = pending.borrow_mut();
+                        if pending.owner == Some(conn) && !pending.block.is_empty() {
+                            queue_block(conn, &mut pending.block);
+                        }
+                    });
+                }
+                if DFU.try_send(DfuCmd::Control { conn, op, command }).is_err() {
+                    warn!("dfu busy, command {} dropped", op);
+                }
+            }
+            DfuServiceEvent::ControlCccdWrite { notifications } => {
+                info!("dfu notifications {}: {}", conn, notifications);
+            }
+            DfuServiceEvent::DataWrite(data) => BLOCK.lock(|pending| {
+                let mut pending = pending.borrow_mut();
+                if pending.owner != Some(conn) {
+                    warn!("dfu data from {} without a transfer, dropped", conn);
+                    return;
+                }
+                let block = &mut pending.block;
+                let room = BLOCK_LEN - block.len();
+                let (now, later) = data.split_at(data.len().min(room));
+                // a write is shorter than a block, `later` fits the empty one
+                block.extend_from_slice(now).unwrap();
+                if block.is_full() {
+                    queue_block(conn, block);
+                    block.extend_from_slice(later).unwrap();
+                }
+            }),
+        }
+    }
+}
+
+/// Aborts the transfer of connection `conn`, if it has one
+pub async fn on_disconnect(conn: Option<u16>) {
+    let Some(conn) = conn else {
+        return;
+    };
+    BLOCK.lock(|pending| {
+        let mut pending = pending.borrow_mut();
+        if pending.owner == Some(conn) {
+            pending.owner = None;
+            pending.block.clear();
+        }
+    });
+    DFU.send(DfuCmd::Disconnected(conn)).await;
+}
+
+/// The transfer in progress
+struct Transfer {
+    /// Connection of the client, gets the notifications
+    conn: u16,
+    len: u32,
+    crc: u32,
+    /// Bytes received so far, header included
+    offset: u32,
+    /// Only the binary goes into the slot, the bootloader knows nothing of headers
+    header: Vec<u8, HEADER_LEN>,
+    /// Set up once the header is complete
+    verifier: Option<Verifier>,
+}
+
+impl From<ImageError> for Status {
+    fn from(e: ImageError) -> Self {
+        match e {
+            ImageError::NotAnImage | ImageError::LengthMismatch => Status::BadImage,
+            ImageError::Downgrade => Status::Downgrade,
+            ImageError::BadSignature => Status::BadSignature,
+        }
+    }
+}
+
+/// The flash api only takes word aligned buffers
+#[repr(align(4))]
+struct Aligned([u8; BLOCK_LEN]);
+
+fn notify(server: &Server, conn: u16, data: &[u8]) {
+    let Some(conn) = Connection::from_handle(conn) else {
+        return;
+    };
+    // every message fits MAX_CONTROL
+    let data = Vec::from_slice(data).unwrap();
+    if let Err(e) = server.dfu.control_notify(&conn, &data) {
+        warn!("failed to send dfu notification: {}", e);
+    }
+}
+
+/// Erases the part of the slot the binary needs
+async fn start(slot: &mut DfuSlot, len: u32) -> Result<(), Status> {
+    let Some(binary_len) = len.checked_sub(HEADER_LEN as u32).filter(|&n| n > 0) else {
+        return Err(Status::BadImage);
+    };
+    // one page of the slot is scratch space for the swap
+    let max_len = slot.size() - Flash::ERASE_SIZE as u32;
+    if binary_len > max_len {
+        return Err(Status::TooLarge);
+    }
+    let page = Flash::ERASE_SIZE as u32;
+    let end = binary_len.div_ceil(page) * page;
+    slot.erase(0, end).await.map_err(|_| Status::Flash)
+}
+
+/// Turns down an image that won't pass [`verify`] anyway, before any of it is written
+fn check_header(header: &[u8], len: u32, accepted: u32) -> Result<Verifier, Status> {
+    let header = Header::decode(header)?;
+    if header.len.checked_add(HEADER_LEN as u32) != Some(len) {
+        return Err(Status::BadImage);
+    }
+    Ok(Verifier::new(header, accepted)?)
+}
+
+async fn write_block(
+    slot: &mut DfuSlot,
+    transfer: &mut Transfer,
+    block: &[u8],
+    accepted: u32,
+) -> Result<(), Status> {
+    let len = block.len() as u32;
+    if transfer.offset + len > transfer.len {
+        return Err(Status::InvalidLength);
+    }
+    let mut block = block;
+    if !transfer.header.is_full() {
+        let n = (HEADER_LEN - transfer.header.len()).min(block.len());
+        let (header, rest) = block.split_at(n);
+        transfer.header.extend_from_slice(header).unwrap();
+        block = rest;
+        if transfer.header.is_full() {
+            transfer.verifier = Some(check_header(&transfer.header, transfer.len, accepted)?);
+        }
+    }
+    if !block.is_empty() {
+        // where the binary is, blocks are whole words past the header
+        let at = transfer.offset + len - block.len() as u32 - HEADER_LEN as u32;
+        // pad to whole words, erased flash reads 0xFF anyway
+        let mut buf = Aligned([0xFF; BLOCK_LEN]);
+        buf.0[..block.len()].copy_from_slice(block);
+        let padded = block.len().next_multiple_of(Flash::WRITE_SIZE);
+        slot.write(at, &buf.0[..padded])
+            .await
+            .map_err(|_| Status::Flash)?;
+    }
+    transfer.offset += len;
+    Ok(())
+}
+
+/// Checks what ended up in flash against the CRC from Start and the signature, returns
+/// the version of the image
+async fn verify(slot: &mut DfuSlot, transfer: Transfer) -> Result<u32, Status> {
+    if transfer.offset != transfer.len {
+        return Err(Status::InvalidLength);
+    }
+    // all data is in, so is the header
+    let Some(mut verifier) = transfer.verifier else {
+        return Err(Status::BadImage);
+    };
+    let mut crc = Crc32::new();
+    crc.update(&transfer.header);
+    let binary_len = transfer.len - HEADER_LEN as u32;
+    let mut buf = [0; 256];
+    let mut offset = 0;
+    while offset < binary_len {
+        let n = (binary_len - offset).min(buf.len() as u32) as usize;
+        slot.read(offset, &mut buf[..n])
+            .await
+            .map_err(|_| Status::Flash)?;
+        crc.update(&buf[..n]);
+        verifier.update(&buf[..n]);
+        offset += n as u32;
+    }
+    if crc.finish() != transfer.crc {
+        return Err(Status::CrcMismatch);
+    }
+    let version = verifier.header().version;
+    verifier.finish(&PUBLIC_KEY)?;
+    Ok(version)
+}
+
+/// The newest version accepted so far, never older than the running one
+async fn load_accepted(flash: &Mutex<NoopRawMutex, Flash>) -> u32 {
+    let mut record = [0; ACCEPTED_LEN];
+    let flash = &mut *flash.lock().await;
+    if let Err(e) = settings::read(flash, settings::Record::ImageVersion, &mut record).await {
+        warn!("failed to read the accepted image version: {}", e);
+        return VERSION;
+    }
+    if record[..4] != ACCEPTED_MAGIC {
+        return VERSION;
+    }
+    u32::from_le_bytes(record[4..].try_into().unwrap()).max(VERSION)
+}
+
+async fn save_accepted(flash: &Mutex<NoopRawMutex, Flash>, version: u32) {
+    let mut record = [0; ACCEPTED_LEN];
+    record[..4].copy_from_slice(&ACCEPTED_MAGIC);
+    record[4..].copy_from_slice(&version.to_le_bytes());
+    let flash = &mut *flash.lock().await;
+    if let Err(e) = settings::write(flash, settings::Record::ImageVersion, &record).await {
+        warn!("failed to save the accepted image version: {}", e);
+    }
+}
+
+/// Runs the transfers and ends the trial of a freshly swapped image
+#[embassy_executor::task]
+pub async fn dfu_task(server: &'static Server, flash: &'static Mutex<NoopRawMutex, Flash>) {
+    // the updater only needs the state page, the data goes straight into the slot
+    let mut slot = FirmwareUpdaterConfig::from_linkerfile(flash).dfu;
+    let mut magic = AlignedBuffer([0; Flash::WRITE_SIZE]);
+    let mut updater =
+        FirmwareUpdater::new(FirmwareUpdaterConfig::from_linkerfile(flash), &mut magic.0);
+
+    let mut trial_end = match updater.get_state().await {
+        Ok(State::Swap) => {
+            info!("new image on trial");
+            Some(Instant::now() + TRIAL_PERIOD)
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("failed to read the bootloader state: {}", e);
+            None
+        }
+    };
+    let mut transfer: Option<Transfer> = None;
+    // the linker drops what isn't used, sign-image looks for it
+    core::hint::black_box(&VERSION_RECORD);
+    let mut accepted = load_accepted(flash).await;
+
+    loop {
+        let cmd = match trial_end {
+            Some(end) => match select(DFU.receive(), Timer::at(end)).await {
+                Either::First(cmd) => cmd,
+                Either::Second(_) => {
+                    match updater.mark_booted().await {
+                        Ok(_) => info!("trial over, image marked booted"),
+                        Err(e) => warn!("failed to mark the image booted: {}", e),
+                    }
+                    trial_end = None;
+                    continue;
+                }
+            },
+            None => DFU.receive().await,
+        };
+
+        match cmd {
+            DfuCmd::Block { conn, block } => {
+                // data without a transfer is dropped, Start answered it already
+                let Some(t) = transfer.as_mut().filter(|t| t.conn == conn) else {
+                    continue;
+                };
+                match write_block(&mut slot, t, &block, accepted).await {
+                    Ok(_) => notify(server, conn, &protocol::progress(t.offset)),
+                    Err(status) => {
+                        // the transfer is over, answered as a failed Start
+                        warn!("dfu write failed: {}", status);
+                        notify(server, conn, &protocol::response(opcode::START, status));
+                        transfer = None;
+                        own(None);
+                    }
+                }
+            }
+            DfuCmd::Disconnected(conn) => {
+                if transfer.as_ref().is_some_and(|t| t.conn == conn) {
+                    info!("dfu client gone, transfer aborted");
+                    transfer = None;
+                }
+            }
+            // another client is in the middle of a transfer
+            DfuCmd::Control { conn, op, .. }
+                if transfer.as_ref().is_some_and(|t| t.conn != conn) =>
+            {
+                notify(server, conn, &protocol::response(op, Status::InvalidState));
+            }
+            DfuCmd::Control { conn, op, command } => {
+                let status = match command {
+                    Err(status) => status,
+                    // the new image has to prove itself before it can be replaced
+                    Ok(Command::Start { .. }) if trial_end.is_some() => Status::InvalidState,
+                    Ok(Command::Start { len, crc }) => {
+                        transfer = None;
+                        own(None);
+                        match start(&mut slot, len).await {
+                            Ok(_) => {
+                                info!("dfu start: {} bytes", len);
+                                own(Some(conn));
+                                transfer = Some(Transfer {
+                                    conn,
+                                    len,
+                                    crc,
+                                    offset: 0,
+                                    header: Vec::new(),
+                                    verifier: None,
+                                });
+                                Status::Success
+                            }
+                            Err(status) => status,
+                        }
+                    }
+                    Ok(Command::Finish) => match transfer.take().inspect(|_| own(None)) {
+                        None => Status::InvalidState,
+                        Some(t) => match verify(&mut slot, t).await {
+                            Ok(version) => match updater.mark_updated().await {
+                                Ok(_) => {
+                                    info!("dfu image verified, swapping on the next boot");
+                                    accepted = version;
+                                    save_accepted(flash, version).await;
+                                    Status::Success
+                                }
+                                Err(e) => {
+                                    warn!("failed to mark the update: {}", e);
+                                    Status::Flash
+                                }
+                            },
+                            Err(status) => {
+                                warn!("dfu image rejected: {}", status);
+                                status
+                            }
+                        },
+                    },
+                    Ok(Command::Reset) => Status::Success,
+                    Ok(Command::Abort) => {
+                        transfer = None;
+                        own(None);
+                        Status::Success
+                    }
+                };
+                notify(server, conn, &protocol::response(op, status));
+                if command == Ok(Command::Reset) {
+                    info!("dfu reset");
+                    // let the response go out first
+                    Timer::after_millis(500).await;
+                    cortex_m::peripheral::SCB::sys_reset();
+                }
+            }
+        }
+    }
+}
+
+/// Takes over the watchdog, keeping the settings of the bootloader if it started it
+pub fn watchdog(wdt: WDT) -> WatchdogHandle {
+    let config = wdt::Config::try_new(&wdt).unwrap_or_else(|| {
+        let mut config = wdt::Config::default();
+        config.timeout_ticks = WATCHDOG_TICKS;
+        config.run_during_debug_halt = false;
+        config
+    });
+    match Watchdog::try_new(wdt, config) {
+        Ok((_, [handle])) => handle,
+        Err(_) => panic!("watchdog runs with more than one handle"),
+    }
+}
+
+/// Feeds the watchdog, stops when the executor hangs
+#[embassy_executor::task]
+pub async fn watchdog_task(mut handle: WatchdogHandle) {
+    loop {
+        handle.pet();
+        Timer::after(PET_INTERVAL).await;
+    }
+}
@@ -0,0 +1,109 @@
+//! Messages of the DFU service, see `dfu.rs` for how a transfer goes.
+//!
+//! All multi byte values are little endian. Pure code, so a host tool can share it.
+
+/// Image bytes the client may send before it waits for a [`progress`] notification
+pub const BLOCK_LEN: usize = 1024;
+
+pub mod opcode {
+    pub const START: u8 = 0x01;
+    pub const FINISH: u8 = 0x02;
+    pub const RESET: u8 = 0x03;
+    pub const ABORT: u8 = 0x04;
+
+    pub const RESPONSE: u8 = 0x10;
+    pub const PROGRESS: u8 = 0x11;
+}
+
+/// A write to the control point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Command {
+    /// A new image of `len` bytes, header included, with the CRC-32 `crc` follows on the data characteristic
+    Start { len: u32, crc: u32 },
+    /// All data is sent, check it and swap on the next boot
+    Finish,
+    /// Reboot into the bootloader
+    Reset,
+    /// Drop the transfer in progress
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum Status {
+    Success = 0x00,
+    /// Not possible right now, e.g. data before Start or a new image still on trial
+    InvalidState = 0x01,
+    /// The image doesn't fit in the DFU slot
+    TooLarge = 0x02,
+    /// What is in flash doesn't match the CRC of Start
+    CrcMismatch = 0x03,
+    /// Erasing, writing or reading the flash failed
+    Flash = 0x04,
+    UnknownCommand = 0x05,
+    /// A command with the wrong length, or more data than announced
+    InvalidLength = 0x06,
+    /// No image header, or its length doesn't match Start
+    BadImage = 0x07,
+    /// The image is older than the running one
+    Downgrade = 0x08,
+    /// Not signed with the key the app was built with
+    BadSignature = 0x09,
+}
+
+/// The opcode and the parsed command, or the status to answer with
+pub fn parse(data: &[u8]) -> (u8, Result<Command, Status>) {
+    let Some((&op, args)) = data.split_first() else {
+        return (0, Err(Status::InvalidLength));
+    };
+    let command = match (op, args.len()) {
+        (opcode::START, 8) => Ok(Command::Start {
+            len: u32::from_le_bytes(args[0..4].try_into().unwrap()),
+            crc: u32::from_le_bytes(args[4..8].try_into().unwrap()),
+        }),
+        (opcode::FINISH, 0) => Ok(Command::Finish),
+        (opcode::RESET, 0) => Ok(Command::Reset),
+        (opcode::ABORT, 0) => Ok(Command::Abort),
+        (opcode::START | opcode::FINISH | opcode::RESET | opcode::ABORT, _) => {
+            Err(Status::InvalidLength)
+        }
+        _ => Err(Status::UnknownCommand),
+    };
+    (op, command)
+}
+
+/// Outcome of the command `op`
+pub fn response(op: u8, status: Status) -> [u8; 3] {
+    [opcode::RESPONSE, op, status as u8]
+}
+
+/// Bytes of the image written to flash so far, sent after every block
+pub fn progress(offset: u32) -> [u8; 5] {
+    let mut out = [opcode::PROGRESS, 0, 0, 0, 0];
+    out[1..].copy_from_slice(&offset.to_le_bytes());
+    out
+}
+
+/// CRC-32 as used by zip and Ethernet (reflected, polynomial 0x04C11DB7)
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
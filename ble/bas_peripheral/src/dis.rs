@@ -0,0 +1,96 @@
+//! Device Information Service, tells clients what hardware and firmware they talk to.
+//!
+//! Characteristic uuids from the assigned numbers, PnP ID layout from the DIS spec section 3.9.
+
+use heapless::Vec;
+use nrf_softdevice::ble::gatt_server::SetValueError;
+
+pub const MANUFACTURER_NAME: &str = "Micro:bit Educational Foundation";
+pub const MODEL_NUMBER: &str = "BBC micro:bit V2";
+pub const HARDWARE_REVISION: &str = "nRF52833";
+/// Crate version and the git hash it was built from, see build.rs
+pub const FIRMWARE_REVISION: &str = concat!(env!("CARGO_PKG_VERSION"), "+", env!("GIT_HASH"));
+
+/// Vendor id source (0x01 Bluetooth SIG, 0x02 USB-IF), vendor id and product id of the
+/// PnP ID, `PNP_ID` at build time, see build.rs
+const PNP_VENDOR_ID_SOURCE: &str = env!("PNP_VENDOR_ID_SOURCE");
+const PNP_VENDOR_ID: &str = env!("PNP_VENDOR_ID");
+const PNP_PRODUCT_ID: &str = env!("PNP_PRODUCT_ID");
+
+/// Same size for all strings, long enough for every one of them
+const STR_LEN: usize = 32;
+
+#[nrf_softdevice::gatt_service(uuid = "180a")]
+pub struct DeviceInformationService {
+    #[characteristic(uuid = "2a29", read)]
+    manufacturer_name: Vec<u8, STR_LEN>,
+    #[characteristic(uuid = "2a24", read)]
+    model_number: Vec<u8, STR_LEN>,
+    #[characteristic(uuid = "2a25", read)]
+    serial_number: Vec<u8, STR_LEN>,
+    #[characteristic(uuid = "2a27", read)]
+    hardware_revision: Vec<u8, STR_LEN>,
+    #[characteristic(uuid = "2a26", read)]
+    firmware_revision: Vec<u8, STR_LEN>,
+    #[characteristic(uuid = "2a50", read)]
+    pnp_id: [u8; 7],
+}
+
+impl DeviceInformationService {
+    /// Fills in all the values, they never change after boot
+    pub fn init(&self) -> Result<(), SetValueError> {
+        self.manufacturer_name_set(&str_value(MANUFACTURER_NAME))?;
+        self.model_number_set(&str_value(MODEL_NUMBER))?;
+        self.serial_number_set(&serial_number())?;
+        self.hardware_revision_set(&str_value(HARDWARE_REVISION))?;
+        self.firmware_revision_set(&str_value(FIRMWARE_REVISION))?;
+        self.pnp_id_set(&pnp_id())
+    }
+}
+
+/// `s` cut to what fits in a characteristic
+fn str_value(s: &str) -> Vec<u8, STR_LEN> {
+    let len = s.len().min(STR_LEN);
+    Vec::from_slice(&s.as_bytes()[..len]).unwrap()
+}
+
+/// The 64 bit FICR DEVICEID, unique per chip
+pub fn device_id() -> u64 {
+    // FICR base 0x10000000, DEVICEID[0] at 0x060 and DEVICEID[1] at 0x064 (nRF52833 PS, 4.4)
+    const DEVICEID: *const u32 = 0x1000_0060 as *const u32;
+    unsafe {
+        let lo = core::ptr::read_volatile(DEVICEID) as u64;
+        let hi = core::ptr::read_volatile(DEVICEID.add(1)) as u64;
+        hi << 32 | lo
+    }
+}
+
+/// [`device_id`] as 16 hex digits
+pub fn serial_number() -> Vec<u8, STR_LEN> {
+    let id = device_id();
+    let mut serial = Vec::new();
+    for shift in (0..16).rev().map(|nibble| nibble * 4) {
+        let digit = b"0123456789ABCDEF"[(id >> shift) as usize & 0xF];
+        serial.push(digit).unwrap();
+    }
+    serial
+}
+
+/// Product version is the crate version as 0xJJMN (major, minor, sub-minor)
+fn pnp_id() -> [u8; 7] {
+    let major: u16 = env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0);
+    let minor: u16 = env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0);
+    let patch: u16 = env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or(0);
+    let version = (major & 0xFF) << 8 | (minor & 0xF) << 4 | (patch & 0xF);
+
+    // checked by build.rs
+    let vendor_id: u16 = PNP_VENDOR_ID.parse().unwrap_or(0);
+    let product_id: u16 = PNP_PRODUCT_ID.parse().unwrap_or(0);
+
+    let mut id = [0; 7];
+    id[0] = PNP_VENDOR_ID_SOURCE.parse().unwrap_or(0);
+    id[1..3].copy_from_slice(&vendor_id.to_le_bytes());
+    id[3..5].copy_from_slice(&product_id.to_le_bytes());
+    id[5..7].copy_from_slice(&version.to_le_bytes());
+    id
+}
@@ -0,0 +1,153 @@
+//! 5x5 LED matrix, drawn from GATT writes.
+//!
+//! Only [`display_task`] touches the `LedMatrix`, everything else sends it a
+//! [`DisplayCmd`]. The matrix is multiplexed with embassy timers, which already run
+//! below the softdevice priorities (see `config()` in main), so the gatt callbacks
+//! never block on the display.
+
+use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel};
+use embassy_time::Duration;
+use heapless::{String, Vec};
+use microbit_bsp::display::{Brightness, Frame};
+use microbit_bsp::LedMatrix;
+
+use crate::Server;
+
+/// Longest text the client can scroll
+pub const MAX_TEXT: usize = 64;
+
+pub enum DisplayCmd {
+    Frame(Frame<5, 5>),
+    /// Text to scroll and optionally how long each column is shown
+    Text(String<MAX_TEXT>, Option<Duration>),
+    Brightness(u8),
+}
+
+pub static DISPLAY: Channel<ThreadModeRawMutex, DisplayCmd, 4> = Channel::new();
+
+#[nrf_softdevice::gatt_service(uuid = "5d0a0001-6d62-4b6f-9f21-3c3b2a9e8f10")]
+pub struct LedService {
+    /// One bit per led, bit `row * 5 + col`, little endian
+    #[characteristic(
+        uuid = "5d0a0002-6d62-4b6f-9f21-3c3b2a9e8f10",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    frame: [u8; 4],
+    /// utf8 text to scroll once across the display
+    #[characteristic(
+        uuid = "5d0a0003-6d62-4b6f-9f21-3c3b2a9e8f10",
+        write,
+        security = "JustWorks"
+    )]
+    text: Vec<u8, MAX_TEXT>,
+    /// 0 (off) to 10 (max)
+    #[characteristic(
+        uuid = "5d0a0004-6d62-4b6f-9f21-3c3b2a9e8f10",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    brightness: u8,
+}
+
+impl LedService {
+    /// Turns a gatt write into a command for the display task
+    pub fn on_event(&self, event: LedServiceEvent) {
+        let cmd = match event {
+            LedServiceEvent::FrameWrite(bits) => DisplayCmd::Frame(frame_from_bits(bits)),
+            LedServiceEvent::TextWrite(text) => match core::str::from_utf8(&text) {
+                // fits, the characteristic is as long as the text
+                Ok(text) => DisplayCmd::Text(text.try_into().unwrap_or_default(), None),
+                Err(_) => {
+                    warn!("display text is not utf8");
+                    return;
+                }
+            },
+            LedServiceEvent::BrightnessWrite(level) => DisplayCmd::Brightness(level),
+        };
+        if DISPLAY.try_send(cmd).is_err() {
+            warn!("display busy, command dropped");
+        }
+    }
+
+    /// What the matrix shows, the display task keeps it in the frame characteristic
+    pub fn frame(&self) -> Frame<5, 5> {
+        frame_from_bits(self.frame_get().unwrap_or_default())
+    }
+}
+
+pub fn frame_from_bits(bits: [u8; 4]) -> Frame<5, 5> {
+    let bits = u32::from_le_bytes(bits);
+    let mut frame = Frame::default();
+    for row in 0..5 {
+        for col in 0..5 {
+            if bits & (1 << (row * 5 + col)) != 0 {
+                frame.set(row, col);
+            }
+        }
+    }
+    frame
+}
+
+pub fn frame_to_bits(frame: &Frame<5, 5>) -> [u8; 4] {
+    let mut bits = 0u32;
+    for row in 0..5 {
+        for col in 0..5 {
+            if frame.is_set(row, col) {
+                bits |= 1 << (row * 5 + col);
+            }
+        }
+    }
+    bits.to_le_bytes()
+}
+
+/// Owns the matrix, keeps the current frame lit and runs the commands
+#[embassy_executor::task]
+pub async fn display_task(mut display: LedMatrix, server: &'static Server) {
+    let mut frame = Frame::default();
+    let mut level = 10;
+    display.set_brightness(Brightness::new(level));
+    let _ = server.led.brightness_set(&level);
+    let _ = server.led.frame_set(&frame_to_bits(&frame));
+    server.profile.on_frame(&frame);
+
+    loop {
+        let cmd = match select(
+            display.display(frame, Duration::from_secs(1)),
+            DISPLAY.receive(),
+        )
+        .await
+        {
+            Either::First(_) => continue,
+            Either::Second(cmd) => cmd,
+        };
+        match cmd {
+            DisplayCmd::Frame(new) => {
+                frame = new;
+                let _ = server.led.frame_set(&frame_to_bits(&frame));
+                server.profile.on_frame(&frame);
+            }
+            DisplayCmd::Text(text, None) => {
+                info!("scrolling: {}", text.as_str());
+                display.scroll(&text).await;
+            }
+            DisplayCmd::Text(text, Some(column_delay)) => {
+                info!("scrolling: {}", text.as_str());
+                // every char is 5 columns plus a blank one
+                let columns = text.chars().count() as u32 * 6 + 5;
+                display
+                    .scroll_with_speed(&text, column_delay * columns)
+                    .await;
+            }
+            DisplayCmd::Brightness(new) => {
+                level = new.min(10);
+                display.set_brightness(Brightness::new(level));
+                let _ = server.led.brightness_set(&level);
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Environmental Sensing Service (0x181A) with the die temperature of the nRF52833.
+//!
+//! The gatt_service macro can't declare descriptors, so the service is put together
+//! with the `ServiceBuilder` and implements `gatt_server::Service` by hand. The ES Trigger
+//! Setting a client writes is in `ble-ess` (`ble/ess`, host tested), a "value changed"
+//! trigger with an operand sets how far the temperature has to move for a notification.
+//! Writes of the trigger need our authorization, one that doesn't decode is answered with
+//! Write Request Rejected or Out of Range and never stored.
+
+use ble_ess::{self as trigger, Trigger};
+use defmt::{info, warn};
+use embassy_time::{Duration, Instant, Timer};
+use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
+use nrf_softdevice::ble::gatt_server::characteristic::{Attribute, Metadata, Properties};
+use nrf_softdevice::ble::gatt_server::{
+    self, NotifyValueError, RegisterError, SetValueError, WriteOp,
+};
+use nrf_softdevice::ble::{Connection, DeferredWriteReply, GattError, SecurityMode, Uuid};
+use nrf_softdevice::{raw, Softdevice};
+
+use crate::conns::{self, CONNS};
+use crate::Server;
+
+const ESS: Uuid = Uuid::new_16(0x181A);
+const TEMPERATURE: Uuid = Uuid::new_16(0x2A6E);
+const ES_MEASUREMENT: Uuid = Uuid::new_16(0x290C);
+const ES_TRIGGER_SETTING: Uuid = Uuid::new_16(0x290D);
+
+/// Time between two reads of the TEMP peripheral
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Until a client writes one, any change, that is a step of 0.25 degrees of the TEMP peripheral
+const DEFAULT_TRIGGER: Trigger = Trigger::OnChange(0);
+
+/// ES Measurement descriptor, section 3.1.2.1 of the ESS spec
+const MEASUREMENT: [u8; 11] = [
+    0x00, 0x00, // flags
+    0x01, // sampling function: instantaneous
+    0x00, 0x00, 0x00, // measurement period: not in use
+    0x01, 0x00, 0x00, // update interval: 1 s, same as SAMPLE_INTERVAL
+    0x00, // application: unspecified
+    0xFF, // measurement uncertainty: not available
+];
+
+pub struct EnvironmentalSensingService {
+    pub temperature_value_handle: u16,
+    pub temperature_cccd_handle: u16,
+    pub trigger_handle: u16,
+}
+
+pub enum EnvironmentalSensingServiceEvent {
+    TemperatureCccdWrite { notifications: bool },
+    TriggerSettingWrite(Result<Trigger, trigger::Error>),
+}
+
+impl EnvironmentalSensingService {
+    pub fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let mut service = ServiceBuilder::new(sd, ESS)?;
+
+        let mut temperature = service.add_characteristic(
+            TEMPERATURE,
+            Attribute::new([0u8; 2]),
+            Metadata::new(Properties::new().read().notify()),
+        )?;
+        temperature.add_descriptor(ES_MEASUREMENT, Attribute::new(MEASUREMENT))?;
+        let trigger = temperature.add_descriptor(
+            ES_TRIGGER_SETTING,
+            Attribute::new(DEFAULT_TRIGGER.encode())
+                .variable_len(4)
+                .write_security(SecurityMode::JustWorks)
+                .deferred_write(),
+        )?;
+        let handles = temperature.build();
+        service.build();
+
+        Ok(Self {
+            temperature_value_handle: handles.value_handle,
+            temperature_cccd_handle: handles.cccd_handle,
+            trigger_handle: trigger.handle(),
+        })
+    }
+
+    /// Temperature in 0.01 degrees
+    pub fn temperature_set(&self, centi_degrees: i16) -> Result<(), SetValueError> {
+        let sd = unsafe { Softdevice::steal() };
+        gatt_server::set_value(
+            sd,
+            self.temperature_value_handle,
+            &centi_degrees.to_le_bytes(),
+        )
+    }
+
+    pub fn temperature_notify(
+        &self,
+        conn: &Connection,
+        centi_degrees: i16,
+    ) -> Result<(), NotifyValueError> {
+        gatt_server::notify_value(
+            conn,
+            self.temperature_value_handle,
+            &centi_degrees.to_le_bytes(),
+        )
+    }
+
+    pub fn trigger(&self) -> Trigger {
+        let sd = unsafe { Softdevice::steal() };
+        let mut buf = [0; 4];
+        gatt_server::get_value(sd, self.trigger_handle, &mut buf)
+            .ok()
+            .and_then(|len| Trigger::decode(&buf[..len]).ok())
+            .unwrap_or(DEFAULT_TRIGGER)
+    }
+
+    /// Records the subscription of connection slot `idx` and checks written triggers
+    pub fn on_event(&self, idx: usize, event: EnvironmentalSensingServiceEvent) {
+        match event {
+            EnvironmentalSensingServiceEvent::TemperatureCccdWrite { notifications } => {
+                info!("temperature notifications {}: {}", idx, notifications);
+                conns::subscriptions(idx, |sub| {
+                    sub.temperature_notify = notifications;
+                    sub.temperature_sent = None;
+                });
+            }
+            EnvironmentalSensingServiceEvent::TriggerSettingWrite(Ok(trigger)) => {
+                info!("temperature trigger: {}", trigger);
+            }
+            EnvironmentalSensingServiceEvent::TriggerSettingWrite(Err(e)) => {
+                warn!("invalid temperature trigger rejected: {}", e);
+            }
+        }
+    }
+
+    /// Authorizes a write of the trigger, only one that decodes is stored
+    pub fn on_deferred_write(
+        &self,
+        handle: u16,
+        op: WriteOp,
+        offset: usize,
+        data: &[u8],
+        reply: DeferredWriteReply,
+    ) -> Option<EnvironmentalSensingServiceEvent> {
+        if handle != self.trigger_handle {
+            return None;
+        }
+        // no prepared writes, the trigger fits in one
+        let decoded = match (op, offset) {
+            (WriteOp::Request, 0) => Trigger::decode(data),
+            _ => Err(trigger::Error::BadOperand),
+        };
+        let res = match decoded {
+            Ok(_) => reply.reply(Ok(data)),
+            Err(e) => reply.reply(Err(GattError::from_att_error(e.att_error()))),
+        };
+        if let Err(e) = res {
+            warn!("trigger write reply error: {}", e);
+        }
+        Some(EnvironmentalSensingServiceEvent::TriggerSettingWrite(
+            decoded,
+        ))
+    }
+}
+
+impl gatt_server::Service for EnvironmentalSensingService {
+    type Event = EnvironmentalSensingServiceEvent;
+
+    fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
+        if handle == self.temperature_cccd_handle && !data.is_empty() {
+            Some(EnvironmentalSensingServiceEvent::TemperatureCccdWrite {
+                notifications: data[0] & 0x01 != 0,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Samples the die temperature and notifies the clients whose trigger fires
+#[embassy_executor::task]
+pub async fn temperature_monitor(server: &'static Server) {
+    let ess = &server.ess;
+    loop {
+        let mut quarter_degrees = 0;
+        let ret = unsafe { raw::sd_temp_get(&mut quarter_degrees) };
+        if ret != raw::NRF_SUCCESS {
+            warn!("temperature read failed: {}", ret);
+            Timer::after(SAMPLE_INTERVAL).await;
+            continue;
+        }
+        let value = (quarter_degrees * 25) as i16;
+        crate::beacon::set_temperature(value);
+        if let Err(e) = ess.temperature_set(value) {
+            warn!("temperature set error: {}", e);
+        }
+
+        let trigger = ess.trigger();
+        let now = Instant::now();
+        for (idx, slot) in CONNS.lock().await.indexed() {
+            let sub = conns::subscriptions(idx, |sub| *sub);
+            let sent = sub.temperature_sent.map(|(v, at)| (v, at.as_millis()));
+            if !sub.temperature_notify || !trigger.should_notify(sent, value, now.as_millis()) {
+                continue;
+            }
+            match ess.temperature_notify(&slot.conn, value) {
+                Ok(_) => conns::subscriptions(idx, |sub| sub.temperature_sent = Some((value, now))),
+                Err(e) => info!("failed to send temperature: {}", e),
+            }
+        }
+        Timer::after(SAMPLE_INTERVAL).await;
+    }
+}
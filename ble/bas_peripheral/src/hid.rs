@@ -0,0 +1,46 @@
+//! HID over GATT: buttons A and B as keys of a keyboard or media remote.
+//!
+//! Opt in with the `hid` feature. The board then advertises as a keyboard and pairs with
+//! a phone or laptop like one, the keys the buttons send are `HID_KEYS` in main and the
+//! reports come from `ble-hid` (`ble/hid`, host tested). Without the feature [`HidService`]
+//! registers nothing and its hooks do nothing, like the `microbit-profile` services.
+
+#[cfg(feature = "hid")]
+mod service;
+#[cfg(feature = "hid")]
+pub use service::*;
+
+#[cfg(not(feature = "hid"))]
+pub use disabled::*;
+
+#[cfg(not(feature = "hid"))]
+mod disabled {
+    use nrf_softdevice::ble::gatt_server::{self, RegisterError};
+    use nrf_softdevice::Softdevice;
+
+    use crate::buttons::{Button, ButtonState};
+
+    pub struct HidService;
+
+    pub enum HidServiceEvent {}
+
+    impl HidService {
+        pub fn new(_sd: &mut Softdevice) -> Result<Self, RegisterError> {
+            Ok(Self)
+        }
+
+        pub fn on_event(&self, event: HidServiceEvent) {
+            match event {}
+        }
+
+        pub async fn on_button(&self, _button: Button, _from: ButtonState, _to: ButtonState) {}
+    }
+
+    impl gatt_server::Service for HidService {
+        type Event = HidServiceEvent;
+
+        fn on_write(&self, _handle: u16, _data: &[u8]) -> Option<Self::Event> {
+            None
+        }
+    }
+}
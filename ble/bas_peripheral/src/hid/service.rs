@@ -0,0 +1,225 @@
+//! The HID service (0x1812), HID over GATT Profile 1.0 and HID Service 1.0.
+//!
+//! Report protocol: a keyboard and a consumer input report, told apart by their Report
+//! Reference descriptors, and the keyboard's LED output report. Boot protocol: the boot
+//! keyboard input and output reports, the consumer report has no place there. The
+//! protocol mode is a single value in the attribute table, not one per connection.
+//! Everything needs an encrypted link, so the host pairs and bonds before it subscribes.
+
+use ble_hid::{report_id, Report, CONSUMER_LEN, KEYBOARD_LEN, REPORT_MAP};
+use defmt::{debug, info};
+use nrf_softdevice::ble::gatt_server::builder::ServiceBuilder;
+use nrf_softdevice::ble::gatt_server::characteristic::{Attribute, Metadata, Properties};
+use nrf_softdevice::ble::gatt_server::{self, RegisterError};
+use nrf_softdevice::ble::{SecurityMode, Uuid};
+use nrf_softdevice::Softdevice;
+
+use crate::buttons::{Button, ButtonState};
+use crate::conns::CONNS;
+use crate::HID_KEYS;
+
+const HID: Uuid = Uuid::new_16(0x1812);
+const PROTOCOL_MODE: Uuid = Uuid::new_16(0x2A4E);
+const REPORT: Uuid = Uuid::new_16(0x2A4D);
+const REPORT_MAP_CHAR: Uuid = Uuid::new_16(0x2A4B);
+const BOOT_KEYBOARD_INPUT: Uuid = Uuid::new_16(0x2A22);
+const BOOT_KEYBOARD_OUTPUT: Uuid = Uuid::new_16(0x2A32);
+const HID_INFORMATION: Uuid = Uuid::new_16(0x2A4A);
+const HID_CONTROL_POINT: Uuid = Uuid::new_16(0x2A4C);
+const REPORT_REFERENCE: Uuid = Uuid::new_16(0x2908);
+
+const BOOT_PROTOCOL: u8 = 0;
+const REPORT_PROTOCOL: u8 = 1;
+/// Report types in a Report Reference
+const INPUT: u8 = 1;
+const OUTPUT: u8 = 2;
+/// bcdHID 1.11, no country code, normally connectable
+const INFORMATION: [u8; 4] = [0x11, 0x01, 0x00, 0x02];
+/// Control point values
+const SUSPEND: u8 = 0;
+const EXIT_SUSPEND: u8 = 1;
+
+pub struct HidService {
+    protocol_mode_handle: u16,
+    control_point_handle: u16,
+    /// Value and CCCD handles of the input reports
+    keyboard: (u16, u16),
+    consumer: (u16, u16),
+    boot_keyboard: (u16, u16),
+    output_handle: u16,
+    boot_output_handle: u16,
+}
+
+pub enum HidServiceEvent {
+    ProtocolModeWrite(u8),
+    ControlPointWrite(u8),
+    /// A keyboard output report, [`ble_hid::led`] bits
+    LedsWrite(u8),
+}
+
+/// Readable and writable on an encrypted link only
+fn encrypted<T: AsRef<[u8]>>(value: T) -> Attribute<T> {
+    Attribute::new(value)
+        .read_security(SecurityMode::JustWorks)
+        .write_security(SecurityMode::JustWorks)
+}
+
+impl HidService {
+    pub fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let mut service = ServiceBuilder::new(sd, HID)?;
+
+        let protocol_mode = service
+            .add_characteristic(
+                PROTOCOL_MODE,
+                encrypted([REPORT_PROTOCOL]),
+                Metadata::new(Properties::new().read().write_without_response()),
+            )?
+            .build();
+
+        let mut keyboard = service.add_characteristic(
+            REPORT,
+            encrypted([0u8; KEYBOARD_LEN]),
+            Metadata::new(Properties::new().read().notify()),
+        )?;
+        keyboard.add_descriptor(REPORT_REFERENCE, encrypted([report_id::KEYBOARD, INPUT]))?;
+        let keyboard = keyboard.build();
+
+        let mut output = service.add_characteristic(
+            REPORT,
+            encrypted([0u8; 1]),
+            Metadata::new(Properties::new().read().write().write_without_response()),
+        )?;
+        output.add_descriptor(REPORT_REFERENCE, encrypted([report_id::KEYBOARD, OUTPUT]))?;
+        let output = output.build();
+
+        let mut consumer = service.add_characteristic(
+            REPORT,
+            encrypted([0u8; CONSUMER_LEN]),
+            Metadata::new(Properties::new().read().notify()),
+        )?;
+        consumer.add_descriptor(REPORT_REFERENCE, encrypted([report_id::CONSUMER, INPUT]))?;
+        let consumer = consumer.build();
+
+        service
+            .add_characteristic(
+                REPORT_MAP_CHAR,
+                encrypted(REPORT_MAP),
+                Metadata::new(Properties::new().read()),
+            )?
+            .build();
+
+        let boot_keyboard = service
+            .add_characteristic(
+                BOOT_KEYBOARD_INPUT,
+                encrypted([0u8; KEYBOARD_LEN]),
+                Metadata::new(Properties::new().read().notify()),
+            )?
+            .build();
+        let boot_output = service
+            .add_characteristic(
+                BOOT_KEYBOARD_OUTPUT,
+                encrypted([0u8; 1]),
+                Metadata::new(Properties::new().read().write().write_without_response()),
+            )?
+            .build();
+
+        service
+            .add_characteristic(
+                HID_INFORMATION,
+                encrypted(INFORMATION),
+                Metadata::new(Properties::new().read()),
+            )?
+            .build();
+        let control_point = service
+            .add_characteristic(
+                HID_CONTROL_POINT,
+                encrypted([0u8; 1]),
+                Metadata::new(Properties::new().write_without_response()),
+            )?
+            .build();
+        service.build();
+
+        Ok(Self {
+            protocol_mode_handle: protocol_mode.value_handle,
+            control_point_handle: control_point.value_handle,
+            keyboard: (keyboard.value_handle, keyboard.cccd_handle),
+            consumer: (consumer.value_handle, consumer.cccd_handle),
+            boot_keyboard: (boot_keyboard.value_handle, boot_keyboard.cccd_handle),
+            output_handle: output.value_handle,
+            boot_output_handle: boot_output.value_handle,
+        })
+    }
+
+    pub fn on_event(&self, event: HidServiceEvent) {
+        match event {
+            HidServiceEvent::ProtocolModeWrite(BOOT_PROTOCOL) => info!("hid: boot protocol"),
+            HidServiceEvent::ProtocolModeWrite(_) => info!("hid: report protocol"),
+            HidServiceEvent::ControlPointWrite(SUSPEND) => info!("hid: host suspended"),
+            HidServiceEvent::ControlPointWrite(EXIT_SUSPEND) => info!("hid: host awake"),
+            HidServiceEvent::ControlPointWrite(_) => {}
+            HidServiceEvent::LedsWrite(leds) => debug!("hid leds: {:02x}", leds),
+        }
+    }
+
+    fn protocol_mode(&self) -> u8 {
+        let sd = unsafe { Softdevice::steal() };
+        let mut mode = [REPORT_PROTOCOL];
+        let _ = gatt_server::get_value(sd, self.protocol_mode_handle, &mut mode);
+        mode[0]
+    }
+
+    /// Presses the key of `button` when it goes down and lets go when it comes up, a long
+    /// press keeps it down
+    pub async fn on_button(&self, button: Button, from: ButtonState, to: ButtonState) {
+        let action = HID_KEYS[button as usize];
+        let report = match (from, to) {
+            (ButtonState::NotPressed, ButtonState::Pressed) => action.press(),
+            (_, ButtonState::NotPressed) => action.release(),
+            _ => return,
+        };
+        self.send(&report).await;
+    }
+
+    /// Stores `report` and notifies every host that subscribed to it
+    async fn send(&self, report: &Report) {
+        let boot = self.protocol_mode() == BOOT_PROTOCOL;
+        let (value_handle, cccd_handle) = match report {
+            Report::Keyboard(_) if boot => self.boot_keyboard,
+            Report::Keyboard(_) => self.keyboard,
+            Report::Consumer(_) if boot => return,
+            Report::Consumer(_) => self.consumer,
+        };
+        let sd = unsafe { Softdevice::steal() };
+        let _ = gatt_server::set_value(sd, value_handle, report.as_bytes());
+        for slot in CONNS.lock().await.iter() {
+            let Some(conn_handle) = slot.conn.handle() else {
+                continue;
+            };
+            if !crate::cccd_notifications(conn_handle, cccd_handle) {
+                continue;
+            }
+            if let Err(e) = gatt_server::notify_value(&slot.conn, value_handle, report.as_bytes()) {
+                info!("failed to send hid report: {}", e);
+            }
+        }
+    }
+}
+
+impl gatt_server::Service for HidService {
+    type Event = HidServiceEvent;
+
+    fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
+        let &[value] = data else {
+            return None;
+        };
+        if handle == self.protocol_mode_handle {
+            Some(HidServiceEvent::ProtocolModeWrite(value))
+        } else if handle == self.control_point_handle {
+            Some(HidServiceEvent::ControlPointWrite(value))
+        } else if handle == self.output_handle || handle == self.boot_output_handle {
+            Some(HidServiceEvent::LedsWrite(value))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Link layer policy: connection parameters, PHY, data length and ATT_MTU per connection.
+//!
+//! The central decides, all a peripheral can do is ask. Once a connection has settled
+//! we ask for a data length that fits one full ATT packet per radio packet and the 2M
+//! PHY. After that the connection runs on the [`Config::interactive`] profile while the
+//! client reads and writes, and drops to [`Config::idle`] when it has been quiet for
+//! [`Config::idle_after`].
+//!
+//! The ATT_MTU is up to the central: nrf-softdevice 0.1 only lets a central start the
+//! exchange, and grants up to [`ATT_MTU`] when the other side asks.
+//!
+//! What the central settled on is logged per connection, a central may turn any of it
+//! down and the link keeps working with what it has. nrf-softdevice 0.1 keeps the
+//! events of the PHY and data length updates to itself, the log has what we asked for.
+
+use defmt::{debug, info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::{raw, RawError};
+
+use crate::conns::MAX_CONNS;
+use crate::ATT_MTU;
+
+/// Radio time the softdevice sets aside per connection event, in 1.25 ms units
+pub const EVENT_LENGTH: u16 = 24;
+/// How long the softdevice gets to apply a request before the link is logged
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// Connection parameters in the units of the spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Profile {
+    /// Connection interval range in 1.25 ms units, 6 (7.5 ms) to 3200 (4 s)
+    pub min_interval: u16,
+    pub max_interval: u16,
+    /// Connection events the peripheral may skip when it has nothing to send
+    pub latency: u16,
+    /// Supervision timeout in 10 ms units
+    pub timeout: u16,
+}
+
+/// Short intervals for a client that is browsing or writing, 15 to 30 ms
+pub const LOW_LATENCY: Profile = Profile {
+    min_interval: 12,
+    max_interval: 24,
+    latency: 0,
+    timeout: 400,
+};
+
+/// Long intervals and some latency for a client that only waits for notifications,
+/// 300 to 500 ms
+pub const LOW_POWER: Profile = Profile {
+    min_interval: 240,
+    max_interval: 400,
+    latency: 2,
+    timeout: 600,
+};
+
+impl Profile {
+    /// Within the limits of the spec, the timeout has to outlast the longest time
+    /// without a connection event twice over
+    pub fn is_valid(&self) -> bool {
+        let max_gap_ms = self.max_interval as u32 * 5 / 4 * (self.latency as u32 + 1);
+        (6..=3200).contains(&self.min_interval)
+            && (self.min_interval..=3200).contains(&self.max_interval)
+            && self.latency <= 499
+            && (10..=3200).contains(&self.timeout)
+            && self.timeout as u32 * 10 > max_gap_ms * 2
+    }
+
+    pub fn params(&self) -> raw::ble_gap_conn_params_t {
+        raw::ble_gap_conn_params_t {
+            min_conn_interval: self.min_interval,
+            max_conn_interval: self.max_interval,
+            slave_latency: self.latency,
+            conn_sup_timeout: self.timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// While the client reads and writes
+    pub interactive: Profile,
+    /// Once the client has been quiet for `idle_after`
+    pub idle: Profile,
+    pub idle_after: Duration,
+    /// Time after connecting before the first request, some centrals (iOS) turn down
+    /// requests while they are still discovering services
+    pub settle: Duration,
+    /// PHYs we ask for, `raw::BLE_GAP_PHY_*` bits, the central picks one of them
+    pub phys: u8,
+    /// Link layer payload in bytes, 27 to 251
+    pub data_length: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interactive: LOW_LATENCY,
+            idle: LOW_POWER,
+            idle_after: Duration::from_secs(10),
+            settle: Duration::from_secs(1),
+            // twice the rate of 1M, about the same range indoors
+            phys: raw::BLE_GAP_PHY_2MBPS as u8,
+            // a whole ATT packet and its 4 byte L2CAP header
+            data_length: ATT_MTU + 4,
+        }
+    }
+}
+
+/// Signaled on every request of the client in a connection slot
+static ACTIVITY: [Signal<ThreadModeRawMutex, ()>; MAX_CONNS] = [const { Signal::new() }; MAX_CONNS];
+/// Signaled once the link settings of a connection slot have been asked for
+static NEGOTIATED: [Signal<ThreadModeRawMutex, ()>; MAX_CONNS] =
+    [const { Signal::new() }; MAX_CONNS];
+
+/// The client of connection slot `idx` did something, called from the gatt callback
+pub fn activity(idx: usize) {
+    if let Some(signal) = ACTIVITY.get(idx) {
+        signal.signal(());
+    }
+}
+
+/// Advertised as the Peripheral Preferred Connection Parameters in the GAP service,
+/// some centrals use them from the start
+pub fn set_preferred(profile: &Profile) -> Result<(), RawError> {
+    let ret = unsafe { raw::sd_ble_gap_ppcp_set(&profile.params()) };
+    RawError::convert(ret)
+}
+
+/// Waits until [`manage`] has asked for the link settings of connection slot `idx`. Our
+/// own GATT client procedures wait for it, by then a central has exchanged the ATT_MTU
+/// and they don't start on a link that is still settling.
+pub async fn negotiated(idx: usize) {
+    NEGOTIATED[idx].wait().await
+}
+
+/// Asks for the link settings once and then follows the activity of the client.
+/// Runs for as long as the connection, the gatt server task drops it on disconnect.
+pub async fn manage(conn: &Connection, idx: usize, config: &Config) {
+    for profile in [&config.interactive, &config.idle] {
+        if !profile.is_valid() {
+            warn!("link {}: invalid profile {}, not used", idx, profile);
+        }
+    }
+    // before the first await, nobody waits on what the last connection in the slot left
+    ACTIVITY[idx].reset();
+    NEGOTIATED[idx].reset();
+    Timer::after(config.settle).await;
+    negotiate(conn, idx, config).await;
+    NEGOTIATED[idx].signal(());
+
+    let mut idle = false;
+    request(conn, idx, &config.interactive).await;
+    loop {
+        match select(ACTIVITY[idx].wait(), Timer::after(config.idle_after)).await {
+            Either::First(_) if idle => {
+                debug!("link {}: client active", idx);
+                idle = false;
+                request(conn, idx, &config.interactive).await;
+            }
+            Either::Second(_) if !idle => {
+                debug!("link {}: client idle", idx);
+                idle = true;
+                request(conn, idx, &config.idle).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// PHY and data length, the softdevice answers the requests of the central for these
+/// on its own but never starts them
+async fn negotiate(conn: &Connection, idx: usize, config: &Config) {
+    let Some(handle) = conn.handle() else {
+        return;
+    };
+
+    let phys = raw::ble_gap_phys_t {
+        tx_phys: config.phys,
+        rx_phys: config.phys,
+    };
+    let ret = unsafe { raw::sd_ble_gap_phy_update(handle, &phys) };
+    match RawError::convert(ret) {
+        Ok(_) => info!("link {}: asked for phys {=u8:#04x}", idx, config.phys),
+        Err(e) => debug!("link {}: phy update failed: {}", idx, e),
+    }
+
+    let params = raw::ble_gap_data_length_params_t {
+        max_tx_octets: config.data_length,
+        max_rx_octets: config.data_length,
+        max_tx_time_us: raw::BLE_GAP_DATA_LENGTH_AUTO as u16,
+        max_rx_time_us: raw::BLE_GAP_DATA_LENGTH_AUTO as u16,
+    };
+    let mut limitation: raw::ble_gap_data_length_limitation_t = unsafe { core::mem::zeroed() };
+    let ret = unsafe { raw::sd_ble_gap_data_length_update(handle, &params, &mut limitation) };
+    match RawError::convert(ret) {
+        Ok(_) => info!("link {}: asked for data length {}", idx, config.data_length),
+        // the event length doesn't leave room for packets this long
+        Err(RawError::Resources) => warn!(
+            "link {}: data length limited to tx {} rx {} bytes",
+            idx, limitation.tx_payload_limited_octets, limitation.rx_payload_limited_octets
+        ),
+        // e.g. the central started one already
+        Err(e) => debug!("link {}: data length update failed: {}", idx, e),
+    }
+}
+
+async fn request(conn: &Connection, idx: usize, profile: &Profile) {
+    if !profile.is_valid() {
+        return;
+    }
+    if let Err(e) = conn.set_conn_params(profile.params()) {
+        debug!("link {}: connection parameter request failed: {}", idx, e);
+        return;
+    }
+    Timer::after(SETTLE).await;
+    log_link(conn, idx);
+}
+
+/// What the central settled on
+fn log_link(conn: &Connection, idx: usize) {
+    let params = conn.conn_params();
+    info!(
+        "link {}: interval {}..{} x1.25 ms, latency {}, timeout {} x10 ms, att mtu {}",
+        idx,
+        params.min_conn_interval,
+        params.max_conn_interval,
+        params.slave_latency,
+        params.conn_sup_timeout,
+        conn.att_mtu()
+    );
+}
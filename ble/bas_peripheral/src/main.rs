@@ -1,24 +1,201 @@
 #![no_std]
 #![no_main]
 #![macro_use]
+// the gatt_service macro names its event variants after the writes, `FrameWrite`, ...
+#![allow(clippy::enum_variant_names)]
 
 //! suggested reading: https://docs.silabs.com/bluetooth/4.0/general/adv-and-scanning/bluetooth-adv-data-basics
 
-use defmt::{debug, error, info};
+use ble_adv::Payload;
+use defmt::{debug, error, info, warn};
 use embassy_executor::Spawner;
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
-use embassy_time::Timer;
-use heapless::Vec;
+use embassy_futures::select::{select, Either};
+use embassy_nrf::bind_interrupts;
+use embassy_nrf::interrupt::{self, InterruptExt};
+use embassy_nrf::pwm::SimplePwm;
+use embassy_nrf::saadc;
+#[cfg(feature = "microbit-profile")]
+use embassy_nrf::{
+    gpio::Pin,
+    peripherals::{P0_02, P0_03, P0_04, PWM1},
+    saadc::Input,
+};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
 use microbit_bsp::*;
 // use nrf_softdevice::ble::gatt_server::{notify_value, Server};
-use nrf_softdevice::ble::{gatt_server, peripheral, Connection};
-use nrf_softdevice::{raw, Softdevice};
+use nrf_softdevice::ble::peripheral::{self, AdvertiseError};
+use nrf_softdevice::ble::{gatt_server, Connection, DeferredWriteReply};
+use nrf_softdevice::{raw, Flash, Softdevice};
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
+mod advertising;
+mod alert;
+use advertising::{Phase, State as AdvState};
+use alert::{
+    ImmediateAlertService, ImmediateAlertServiceEvent, LinkLossService, LinkLossServiceEvent,
+};
+mod battery;
+mod beacon;
+mod bonds;
+mod buttons;
+#[cfg(feature = "s140")]
+mod central;
+#[cfg(feature = "s140")]
+mod client;
+mod clock;
+mod conns;
+mod dfu;
+mod dis;
+mod display;
+mod ess;
+mod hid;
+mod link;
+mod name;
+mod nus;
+mod profile;
+#[cfg(feature = "s140")]
+mod radar;
+mod settings;
+use bonds::{Bonder, Pairing};
+use buttons::{Button, ButtonService, ButtonServiceEvent};
+use conns::{CONNS, MAX_CONNS};
+use dfu::{DfuService, DfuServiceEvent};
+use dis::{DeviceInformationService, DeviceInformationServiceEvent};
+use display::{LedService, LedServiceEvent};
+use ess::{EnvironmentalSensingService, EnvironmentalSensingServiceEvent};
+use hid::{HidService, HidServiceEvent};
+use nus::{NusService, NusServiceEvent};
+use profile::{Profile, ProfileEvent};
+
+bind_interrupts!(struct Irqs {
+    SAADC => saadc::InterruptHandler;
+});
+
+/// 16 bit uuid of the battery service
+const BATTERY_SERVICE: u16 = 0x180F;
+/// Generic Tag, or Keyboard for the `hid` feature, from the appearance values in the
+/// assigned numbers
+#[cfg(not(feature = "hid"))]
+const APPEARANCE: u16 = 0x0200;
+#[cfg(feature = "hid")]
+const APPEARANCE: u16 = 0x03C1;
+/// 16 bit services in the advertisement, hosts look for HID there before they connect
+#[cfg(not(feature = "hid"))]
+const ADVERTISED_SERVICES: &[u16] = &[BATTERY_SERVICE];
+#[cfg(feature = "hid")]
+const ADVERTISED_SERVICES: &[u16] = &[BATTERY_SERVICE, 0x1812];
+/// Default radio tx power of the softdevice in dBm
+const TX_POWER: i8 = 0;
+/// Largest ATT_MTU the softdevice will negotiate
+pub const ATT_MTU: u16 = 128;
+/// Until a client gives the board a name of its own, see `name.rs`
+const DEFAULT_NAME: &str = "Embassy Microbit";
+/// `Pairing::Passkey` to show a passkey on the matrix and confirm bonds with button A
+const PAIRING: Pairing = Pairing::JustWorks;
+/// What buttons A and B send with the `hid` feature, next and previous slide. For a media
+/// remote: `Action::Consumer(consumer::PLAY_PAUSE)` and `consumer::VOLUME_UP`.
+#[cfg(feature = "hid")]
+const HID_KEYS: [ble_hid::Action; 2] = [
+    ble_hid::Action::Key {
+        modifiers: 0,
+        key: ble_hid::key::RIGHT_ARROW,
+    },
+    ble_hid::Action::Key {
+        modifiers: 0,
+        key: ble_hid::key::LEFT_ARROW,
+    },
+];
+/// Links the softdevice makes room for, the clients and with S140 the peers we connect to
+#[cfg(not(feature = "s140"))]
+const LINKS: usize = MAX_CONNS;
+#[cfg(feature = "s140")]
+const LINKS: usize = MAX_CONNS + central::MAX_PEERS;
+/// Beacon frames between the connectable advertising, `frames: &[]` for none
+const BEACONS: beacon::Schedule = beacon::Schedule {
+    frames: &[
+        beacon::Frame::EddystoneTlm,
+        beacon::Frame::EddystoneUrl("https://microbit.org"),
+        beacon::Frame::EddystoneUid {
+            // first 10 bytes of the sha-1 of "microbit.org", as the spec suggests
+            namespace: [0x51, 0x70, 0x8d, 0x4d, 0xa0, 0x67, 0x0c, 0xa6, 0x95, 0x59],
+        },
+        beacon::Frame::IBeacon {
+            // 5d0a0000-6d62-4b6f-9f21-3c3b2a9e8f10
+            uuid: [
+                0x5d, 0x0a, 0x00, 0x00, 0x6d, 0x62, 0x4b, 0x6f, 0x9f, 0x21, 0x3c, 0x3b, 0x2a, 0x9e,
+                0x8f, 0x10,
+            ],
+            major: 1,
+            minor: 1,
+            measured_power: -59,
+        },
+    ],
+    connectable: Duration::from_secs(2),
+    frame_time: Duration::from_millis(500),
+    // 100 ms, the shortest for non-connectable advertising
+    interval: 160,
+    // the iBeacon's measured power at 1 m plus the 41 dB lost over that meter
+    tx_power_0m: -18,
+};
+
+/// Every characteristic declares its security next to it, `security = "JustWorks"`
+/// (encrypted, needs a bond) or `"Mitm"` (authenticated, needs passkey pairing) in its
+/// `characteristic` attribute, without one it is open. Reading stays open for status
+/// like the battery level, writes that change the board need a bond.
+///
+/// The softdevice checks the level on every access and answers with Insufficient
+/// Authentication or Insufficient Encryption, which is what makes clients pair. The macro
+/// puts one level on reads and writes, a characteristic that needs different ones is
+/// built with the `ServiceBuilder`, see the ES Trigger Setting in `ess.rs`.
 #[nrf_softdevice::gatt_server]
 pub struct Server {
     bas: BatteryService,
+    dis: DeviceInformationService,
+    ess: EnvironmentalSensingService,
+    nus: NusService,
+    led: LedService,
+    buttons: ButtonService,
+    dfu: DfuService,
+    profile: Profile,
+    hid: HidService,
+    ias: ImmediateAlertService,
+    lls: LinkLossService,
+}
+
+/// [`Server`] as the softdevice serves it. The macro only passes plain writes on, this
+/// adds the writes that wait for our authorization, the ES Trigger Setting in `ess.rs`.
+struct Gatt<'a>(&'a Server);
+
+impl gatt_server::Server for Gatt<'_> {
+    type Event = ServerEvent;
+
+    fn on_write(
+        &self,
+        conn: &Connection,
+        handle: u16,
+        op: gatt_server::WriteOp,
+        offset: usize,
+        data: &[u8],
+    ) -> Option<ServerEvent> {
+        gatt_server::Server::on_write(self.0, conn, handle, op, offset, data)
+    }
+
+    fn on_deferred_write(
+        &self,
+        handle: u16,
+        op: gatt_server::WriteOp,
+        offset: usize,
+        data: &[u8],
+        reply: DeferredWriteReply,
+    ) -> Option<ServerEvent> {
+        self.0
+            .ess
+            .on_deferred_write(handle, op, offset, data, reply)
+            .map(ServerEvent::Ess)
+    }
 }
 
 #[nrf_softdevice::gatt_service(uuid = "180f")]
@@ -36,76 +213,192 @@ fn config() -> Config {
 }
 
 static SERVER: StaticCell<Server> = StaticCell::new();
+static BONDER: StaticCell<Bonder> = StaticCell::new();
+static FLASH: StaticCell<Mutex<NoopRawMutex, Flash>> = StaticCell::new();
 #[embassy_executor::main]
 async fn main(s: Spawner) {
-    let _ = Microbit::new(config());
+    let board = Microbit::new(config());
+    // the board doesn't hand out the WDT, nothing else uses it
+    let watchdog = dfu::watchdog(unsafe { embassy_nrf::peripherals::WDT::steal() });
+    s.spawn(dfu::watchdog_task(watchdog)).unwrap();
 
     // Spawn the underlying softdevice task
-    let sd = enable_softdevice("Embassy Microbit");
+    let sd = enable_softdevice(DEFAULT_NAME);
 
     // Create a BLE GATT server and make it static
     // let server =
     let server = SERVER.init(Server::new(sd).unwrap());
+    server.dis.init().unwrap();
+    if let Err(e) = link::set_preferred(&link::Config::default().interactive) {
+        error!("failed to set the preferred connection parameters: {}", e);
+    }
 
     // server.bas.battery_level_set(&13).unwrap();
     s.spawn(softdevice_task(sd)).unwrap();
+
+    // flash operations go through the softdevice, it has to be running
+    let flash = FLASH.init(Mutex::new(Flash::take(sd)));
+    if board.btn_a.is_low() && board.btn_b.is_low() {
+        info!("A and B held at boot, erasing bonds");
+        match bonds::erase(&mut *flash.lock().await).await {
+            Ok(_) => {
+                let _ = display::DISPLAY.try_send(display::DisplayCmd::Text(
+                    "bonds erased".try_into().unwrap(),
+                    None,
+                ));
+            }
+            Err(e) => error!("failed to erase bonds: {}", e),
+        }
+    }
+    if let Some(saved) = name::load(&mut *flash.lock().await).await {
+        if let Err(e) = name::set(&saved) {
+            error!("failed to restore the device name: {}", e);
+        }
+    }
+    s.spawn(name::name_task(flash)).unwrap();
+    let stored = bonds::load(&mut *flash.lock().await).await;
+    let bonder = BONDER.init(Bonder::new(server, PAIRING, stored));
+    s.spawn(bonds::bond_task(flash, bonder)).unwrap();
+    s.spawn(dfu::dfu_task(server, flash)).unwrap();
+
     // Starts the bluetooth advertisement and GATT server
-    s.spawn(advertiser_task(s, sd, server, "Embassy Microbit"))
+    s.spawn(advertiser_task(s, sd, server, bonder)).unwrap();
+    s.spawn(ess::temperature_monitor(server)).unwrap();
+    s.spawn(nus::console_task(server)).unwrap();
+    s.spawn(clock::clock_task()).unwrap();
+    #[cfg(feature = "s140")]
+    {
+        s.spawn(radar::radar_task(sd)).unwrap();
+        s.spawn(client::client_task()).unwrap();
+        s.spawn(client::show_task()).unwrap();
+    }
+    s.spawn(display::display_task(board.display, server))
+        .unwrap();
+    s.spawn(buttons::button_task(server, Button::A, board.btn_a))
+        .unwrap();
+    s.spawn(buttons::button_task(server, Button::B, board.btn_b))
+        .unwrap();
+    let speaker = SimplePwm::new_1ch(board.pwm0, board.speaker);
+    s.spawn(alert::alert_task(server, speaker)).unwrap();
+    // must not collide with the priorities reserved by the softdevice
+    interrupt::SAADC.set_priority(Priority::P3);
+    battery::init_adc(board.saadc).await;
+    #[cfg(feature = "microbit-profile")]
+    profile::spawn(
+        &s,
+        server,
+        profile::Parts {
+            twim: board.twispi0,
+            // the internal i2c bus, the board names them after the P22 and P23 pads
+            scl: board.p22,
+            sda: board.p23,
+            // the board doesn't hand out P0 and PWM1 and hands P1 and P2 out as gpio only,
+            // the io task owns all three pins and nothing else uses PWM1
+            pins: [
+                unsafe { P0_02::steal() }.degrade(),
+                board.p1.degrade(),
+                board.p2.degrade(),
+            ],
+            analog: unsafe {
+                [
+                    P0_02::steal().degrade_saadc(),
+                    P0_03::steal().degrade_saadc(),
+                    P0_04::steal().degrade_saadc(),
+                ]
+            },
+            pwm: unsafe { PWM1::steal() },
+        },
+    );
+    s.spawn(battery_monitor(server, battery::Config::default()))
         .unwrap();
-    s.spawn(drain_battery(server)).unwrap();
 }
 
 #[embassy_executor::task]
-async fn drain_battery(server: &'static Server) {
-    let mut lvl: u8 = 100;
+async fn battery_monitor(server: &'static Server, config: battery::Config) {
+    let mut filter = battery::Filter::new(config.filter_shift);
+    let mut last_lvl = None;
     loop {
-        for _ in 0..10 {
-            let res = server.bas.battery_level_set(&lvl);
-            if let Err(e) = res {
-                error!("battery set error: {}", e);
-                continue;
-            };
+        let sample = battery::sample(battery::channel(config.source)).await;
+        let mv = filter.update(battery::millivolts(sample, config.source));
+        let lvl = config.curve.percent(mv);
+        debug!("battery: {} mV, {}%", mv, lvl);
+        beacon::set_battery(mv);
 
-            Timer::after_millis(500).await;
-            if lvl > 0 {
-                lvl -= 1;
-            } else {
-                lvl = 100;
+        if last_lvl != Some(lvl) {
+            match server.bas.battery_level_set(&lvl) {
+                Ok(_) => last_lvl = Some(lvl),
+                Err(e) => error!("battery set error: {}", e),
             }
         }
-        if let Some(conn) = CONN.lock().await.as_ref() {
-            match server.bas.battery_level_notify(conn, &lvl) {
-                Ok(_) => info!("notice sent"),
+
+        // checked every sample so a change held back by the policy is sent later
+        let now = Instant::now();
+        for (idx, slot) in CONNS.lock().await.indexed() {
+            let sub = conns::subscriptions(idx, |sub| *sub);
+            let sent = sub.battery_sent.map(|(lvl, at)| (lvl, at.as_millis()));
+            if !sub.battery_notify || !config.notify.should_notify(sent, lvl, now.as_millis()) {
+                continue;
+            }
+            match server.bas.battery_level_notify(&slot.conn, &lvl) {
+                Ok(_) => {
+                    info!("notice sent");
+                    conns::subscriptions(idx, |sub| sub.battery_sent = Some((lvl, now)));
+                }
                 Err(err) => info!("failed to send notice: {}", err),
             }
-        };
+        }
+        Timer::after(config.interval).await;
     }
 }
 
-// Up to 2 connections
-
-static CONN: Mutex<ThreadModeRawMutex, Option<Connection>> = Mutex::new(None);
-
-#[embassy_executor::task(pool_size = "1")]
-pub async fn gatt_server_task(server: &'static Server) {
-    {
-        let conn = {
-            let lock = CONN.lock().await;
-            lock.as_ref().unwrap().clone() // clone is used here so we can drop the lock
-        };
-
-        gatt_server::run(&conn, server, |e| match e {
+/// Serves the connection in slot `idx` until it is closed
+#[embassy_executor::task(pool_size = MAX_CONNS)]
+pub async fn gatt_server_task(server: &'static Server, conn: Connection, idx: usize) {
+    let link_config = link::Config::default();
+    // gone once the connection is closed
+    let conn_handle = conn.handle();
+    alert::on_connect(conn_handle);
+    clock::on_connect(conn.clone(), idx);
+    let gatt = Gatt(server);
+    let serve = gatt_server::run(&conn, &gatt, |e| {
+        link::activity(idx);
+        match e {
             ServerEvent::Bas(e) => match e {
                 BatteryServiceEvent::BatteryLevelCccdWrite { notifications } => {
-                    info!("battery notifications: {}", notifications);
+                    info!("battery notifications {}: {}", idx, notifications);
+                    conns::subscriptions(idx, |sub| {
+                        sub.battery_notify = notifications;
+                        sub.battery_sent = None;
+                    });
+                }
+            },
+            ServerEvent::Dis(e) => match e {},
+            ServerEvent::Ess(e) => server.ess.on_event(idx, e),
+            ServerEvent::Nus(e) => match e {
+                NusServiceEvent::RxWrite(data) => nus::on_rx(idx, &data),
+                NusServiceEvent::TxCccdWrite { notifications } => {
+                    info!("console notifications {}: {}", idx, notifications);
                 }
             },
-        })
-        .await;
-        info!("connection closed");
+            ServerEvent::Led(e) => server.led.on_event(e),
+            ServerEvent::Buttons(e) => server.buttons.on_event(idx, e),
+            ServerEvent::Dfu(e) => server.dfu.on_event(&conn, e),
+            ServerEvent::Profile(e) => server.profile.on_event(e),
+            ServerEvent::Hid(e) => server.hid.on_event(e),
+            ServerEvent::Ias(e) => server.ias.on_event(&conn, e),
+            ServerEvent::Lls(e) => server.lls.on_event(e),
+        }
+    });
+    // the link policy runs for as long as the connection, the server returns on disconnect
+    select(serve, link::manage(&conn, idx, &link_config)).await;
+    info!("connection {} closed", idx);
+    alert::on_disconnect(server, conn_handle);
+    dfu::on_disconnect(conn_handle).await;
+    if let Some(lasted) = conns::release(idx).await {
+        info!("connection {} lasted {}s", idx, lasted.as_secs());
     }
-    let mut lock = CONN.lock().await;
-    lock.take();
+    advertising::wake();
+    name::check();
 }
 
 #[embassy_executor::task]
@@ -113,60 +406,134 @@ pub async fn advertiser_task(
     spawner: Spawner,
     sd: &'static Softdevice,
     server: &'static Server,
-    name: &'static str,
+    bonder: &'static Bonder,
 ) {
-    // spec for assigned numbers: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Assigned_Numbers/out/en/Assigned_Numbers.pdf?v=1715770644767
-    let mut adv_data: Vec<u8, 31> = Vec::new();
-    let flags: [u8; 3] = [
-        2, //the len -1
-        raw::BLE_GAP_AD_TYPE_FLAGS as u8,
-        raw::BLE_GAP_ADV_FLAGS_LE_ONLY_GENERAL_DISC_MODE as u8,
-    ];
-    adv_data.extend(flags.into_iter());
-    let service_list_16 = [
-        3, // the len - 1
-        raw::BLE_GAP_AD_TYPE_16BIT_SERVICE_UUID_COMPLETE as u8,
-        0x018, // part of 0x180F which u16 UIID for battery service
-        0x0F,  // part of 0x180F which u16 UIID for battery service
-    ];
-    adv_data.extend(service_list_16.into_iter());
-
-    adv_data
-        .extend_from_slice(&[
-            (1 + name.len() as u8),
-            raw::BLE_GAP_AD_TYPE_COMPLETE_LOCAL_NAME as u8,
-        ])
-        .unwrap();
-
-    adv_data.extend_from_slice(name.as_bytes()).ok().unwrap();
+    let adv_config = advertising::Config::default();
+    let mut phase = Phase::start(AdvState::Fast, &adv_config);
+    loop {
+        let idle = if CONNS.lock().await.is_full() {
+            Some(AdvState::Full)
+        } else if phase.state == AdvState::Stopped {
+            Some(AdvState::Stopped)
+        } else {
+            None
+        };
+        if let Some(state) = idle {
+            advertising::set_state(state);
+            // nothing connectable to send, only the beacons
+            if BEACONS.is_empty() {
+                advertising::woken().await;
+            } else if let Either::First(_) =
+                select(beacon::round(sd, &BEACONS), advertising::woken()).await
+            {
+                continue;
+            }
+            phase = Phase::start(AdvState::Fast, &adv_config);
+            continue;
+        }
+        if phase.is_over() {
+            phase = Phase::start(phase.state.next(), &adv_config);
+            continue;
+        }
 
-    // TODO: refer to some docs here to explain magic values
-    #[rustfmt::skip]
-    let scan_data = &[
-        0x03, 0x03, 0x18, 0x0F
-    ];
+        // the name may have changed since the last cycle
+        let name = name::current();
+        // spec for assigned numbers: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Assigned_Numbers/out/en/Assigned_Numbers.pdf?v=1715770644767
+        // a name too long for either payload is shortened, the services always fit
+        let payload =
+            Payload::connectable(&name, ADVERTISED_SERVICES, APPEARANCE, TX_POWER).unwrap();
 
-    loop {
-        let config = peripheral::Config::default();
+        // the rest of the phase, cut short for the next beacon round
+        let time = match (phase.remaining(), BEACONS.is_empty()) {
+            (time, true) => time,
+            (Some(time), false) => Some(time.min(BEACONS.connectable)),
+            (None, false) => Some(BEACONS.connectable),
+        };
+        let config = adv_config.params(phase.state, time);
         let adv = peripheral::ConnectableAdvertisement::ScannableUndirected {
-            adv_data: &adv_data[..],
-            scan_data,
+            adv_data: payload.adv_data.as_slice(),
+            scan_data: payload.scan_data.as_slice(),
+        };
+        advertising::set_state(phase.state);
+        let started = Instant::now();
+        let advertise = peripheral::advertise_pairable(sd, adv, &config, bonder);
+        // dropping the advertise future stops advertising
+        let res = select(advertise, advertising::woken()).await;
+        beacon::count_adv(started.elapsed(), config.interval);
+        let conn = match res {
+            Either::First(Ok(conn)) => conn,
+            Either::First(Err(AdvertiseError::Timeout)) => {
+                // the phase may be over too, checked on the next turn
+                if !BEACONS.is_empty() {
+                    if let Either::Second(_) =
+                        select(beacon::round(sd, &BEACONS), advertising::woken()).await
+                    {
+                        phase = Phase::start(AdvState::Fast, &adv_config);
+                    }
+                }
+                continue;
+            }
+            Either::First(Err(e)) => {
+                warn!("advertising failed: {}", e);
+                advertising::set_state(AdvState::Retrying);
+                Timer::after(adv_config.retry_after).await;
+                continue;
+            }
+            Either::Second(_) => {
+                phase = Phase::start(AdvState::Fast, &adv_config);
+                continue;
+            }
         };
-        debug!("advertising");
-        let conn = peripheral::advertise_connectable(sd, adv, &config)
-            .await
-            .unwrap();
 
         defmt::debug!("connection established");
-        let mut lock = CONN.lock().await;
-        lock.replace(conn);
+        let mut conns = CONNS.lock().await;
+        let idx = match conns.insert(conn.clone()) {
+            Ok(idx) => idx,
+            Err(conn) => {
+                defmt::warn!("no free connection slot, disconnecting");
+                let _ = conn.disconnect();
+                continue;
+            }
+        };
+        drop(conns);
 
-        if let Err(e) = spawner.spawn(gatt_server_task(server)) {
+        if let Err(e) = spawner.spawn(gatt_server_task(server, conn, idx)) {
             defmt::warn!("Error spawning gatt task: {:?}", e);
+            conns::release(idx).await;
         }
+        // room for another client, no hurry to find it
+        phase = Phase::start(AdvState::Slow, &adv_config);
     }
 }
 
+/// Picks up the CCCDs restored from a bond, the client won't write them again
+pub fn restore_subscriptions(server: &Server, conn: &Connection) {
+    let Some(conn_handle) = conn.handle() else {
+        return;
+    };
+    let notify = |cccd_handle| cccd_notifications(conn_handle, cccd_handle);
+    let restored = conns::subscriptions_of(conn_handle, |sub| {
+        sub.battery_notify = notify(server.bas.battery_level_cccd_handle);
+        sub.temperature_notify = notify(server.ess.temperature_cccd_handle);
+        sub.button_notify = server.buttons.cccd_handles().map(notify);
+    });
+    if restored.is_none() {
+        warn!("no slot for bonded connection {}", conn_handle);
+    }
+}
+
+/// Are notifications enabled in the CCCD `cccd_handle` of connection `conn_handle`
+fn cccd_notifications(conn_handle: u16, cccd_handle: u16) -> bool {
+    let mut cccd = [0; 2];
+    let mut value = raw::ble_gatts_value_t {
+        len: cccd.len() as u16,
+        offset: 0,
+        p_value: cccd.as_mut_ptr(),
+    };
+    let ret = unsafe { raw::sd_ble_gatts_value_get(conn_handle, cccd_handle, &mut value) };
+    ret == raw::NRF_SUCCESS && u16::from_le_bytes(cccd) & raw::BLE_GATT_HVX_NOTIFICATION as u16 != 0
+}
+
 fn enable_softdevice(name: &'static str) -> &'static mut Softdevice {
     let config = nrf_softdevice::Config {
         clock: Some(raw::nrf_clock_lf_cfg_t {
@@ -176,22 +543,32 @@ fn enable_softdevice(name: &'static str) -> &'static mut Softdevice {
             accuracy: 7,
         }),
         conn_gap: Some(raw::ble_gap_conn_cfg_t {
-            conn_count: 2,
-            event_length: 24,
+            conn_count: LINKS as u8,
+            event_length: link::EVENT_LENGTH,
         }),
-        conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: 128 }),
+        conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: ATT_MTU }),
         gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t {
             attr_tab_size: 32768,
         }),
+        #[cfg(not(feature = "s140"))]
+        gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
+            adv_set_count: 1,
+            periph_role_count: MAX_CONNS as u8,
+        }),
+        #[cfg(feature = "s140")]
         gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
             adv_set_count: 1,
-            periph_role_count: 3,
+            periph_role_count: MAX_CONNS as u8,
+            central_role_count: central::MAX_PEERS as u8,
+            // we don't pair with our peers
+            central_sec_count: 0,
+            _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
         }),
         gap_device_name: Some(raw::ble_gap_cfg_device_name_t {
-            p_value: name.as_ptr() as *const u8 as _,
+            p_value: name.as_ptr() as _,
             current_len: name.len() as u16,
-            max_len: name.len() as u16,
-            write_perm: unsafe { core::mem::zeroed() },
+            max_len: name::MAX_NAME_LEN as u16,
+            write_perm: name::write_perm(),
             _bitfield_1: raw::ble_gap_cfg_device_name_t::new_bitfield_1(
                 raw::BLE_GATTS_VLOC_STACK as u8,
             ),
@@ -0,0 +1,125 @@
+//! The GAP device name, writable by clients and kept in flash.
+//!
+//! A bonded client writes the Device Name characteristic of the GAP service, or sends
+//! the `name` console command. The softdevice holds the current name, the advertiser
+//! reads it at the start of every advertising cycle and `adv` shortens it when it
+//! doesn't fit. [`name_task`] copies it to its record in `settings` after a change,
+//! the softdevice doesn't tell us about GAP writes so it also checks whenever a
+//! connection closes.
+
+use defmt::{info, warn};
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::{mutex::Mutex, signal::Signal};
+use heapless::String;
+use nrf_softdevice::{raw, Flash, RawError};
+
+use crate::settings;
+
+/// Room reserved for the name, in bytes
+pub const MAX_NAME_LEN: usize = 32;
+
+pub type Name = String<MAX_NAME_LEN>;
+
+/// Start of a valid record, also the layout version
+const MAGIC: [u8; 4] = *b"NAM1";
+/// Magic, length byte and the name, rounded up to whole flash words
+pub const RECORD_LEN: usize = (MAGIC.len() + 1 + MAX_NAME_LEN).next_multiple_of(4);
+
+struct Record([u8; RECORD_LEN]);
+
+impl Record {
+    fn encode(name: &str) -> Self {
+        let mut record = Self([0xFF; RECORD_LEN]);
+        record.0[..4].copy_from_slice(&MAGIC);
+        record.0[4] = name.len() as u8;
+        record.0[5..5 + name.len()].copy_from_slice(name.as_bytes());
+        record
+    }
+
+    /// `None` for an erased page or one written by another layout
+    fn decode(&self) -> Option<Name> {
+        let len = self.0[4] as usize;
+        if self.0[..4] != MAGIC || len == 0 || len > MAX_NAME_LEN {
+            return None;
+        }
+        let name = core::str::from_utf8(&self.0[5..5 + len]).ok()?;
+        name.parse().ok()
+    }
+}
+
+/// Signaled when the name may have changed
+static CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Writes to the GAP Device Name need a bond, like every other write
+pub fn write_perm() -> raw::ble_gap_conn_sec_mode_t {
+    let mut perm: raw::ble_gap_conn_sec_mode_t = unsafe { core::mem::zeroed() };
+    // security mode 1 level 2, encrypted without MITM protection
+    perm.set_sm(1);
+    perm.set_lv(2);
+    perm
+}
+
+/// The name the softdevice has now
+pub fn current() -> Name {
+    let mut buf = [0; MAX_NAME_LEN];
+    let mut len = buf.len() as u16;
+    let ret = unsafe { raw::sd_ble_gap_device_name_get(buf.as_mut_ptr(), &mut len) };
+    if ret != raw::NRF_SUCCESS {
+        return Name::new();
+    }
+    // a client may write bytes that aren't utf8, keep the valid start
+    let buf = &buf[..len as usize];
+    let valid = match core::str::from_utf8(buf) {
+        Ok(name) => name,
+        // valid_up_to is a char boundary
+        Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).unwrap(),
+    };
+    valid.parse().unwrap_or_default()
+}
+
+/// Changes the name, advertised from the next advertising cycle on and saved to flash
+pub fn set(name: &str) -> Result<(), RawError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(RawError::InvalidLength);
+    }
+    let ret =
+        unsafe { raw::sd_ble_gap_device_name_set(&write_perm(), name.as_ptr(), name.len() as u16) };
+    RawError::convert(ret)?;
+    CHANGED.signal(());
+    Ok(())
+}
+
+/// A client may have written the name over GAP, called when a connection closes
+pub fn check() {
+    CHANGED.signal(());
+}
+
+/// Reads the name saved in flash, `None` if there is none
+pub async fn load(flash: &mut Flash) -> Option<Name> {
+    let mut record = Record([0; RECORD_LEN]);
+    match settings::read(flash, settings::Record::Name, &mut record.0).await {
+        Ok(_) => record.decode(),
+        Err(e) => {
+            warn!("failed to read the device name: {}", e);
+            None
+        }
+    }
+}
+
+/// Keeps the name in flash in sync with the softdevice
+#[embassy_executor::task]
+pub async fn name_task(flash: &'static Mutex<NoopRawMutex, Flash>) {
+    loop {
+        CHANGED.wait().await;
+        let name = current();
+        if name.is_empty() {
+            continue;
+        }
+        let record = Record::encode(&name);
+        match settings::write(&mut *flash.lock().await, settings::Record::Name, &record.0).await {
+            Ok(true) => info!("device name saved: {}", name.as_str()),
+            Ok(false) => {}
+            Err(e) => warn!("failed to save the device name: {}", e),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Nordic UART Service, a byte pipe over GATT that most BLE terminal apps understand.
+//!
+//! The client writes to rx and subscribes to tx, both named from the client's point of view.
+
+use core::fmt::Write;
+
+use ble_console::{self as console, Command, ParseError, MAX_LINE};
+use defmt::warn;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel};
+use embassy_time::{Instant, Timer};
+use heapless::{String, Vec};
+use nrf_softdevice::ble::gatt_server::NotifyValueError;
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::RawError;
+#[cfg(feature = "s140")]
+use {
+    crate::{central, radar},
+    nrf_softdevice::Softdevice,
+};
+
+use crate::conns::{self, CONNS};
+use crate::{advertising, clock, dis, name, Server, ATT_MTU};
+
+/// Largest write or notification payload, an ATT_MTU minus the 3 byte ATT header
+pub const MAX_PAYLOAD: usize = ATT_MTU as usize - 3;
+
+#[nrf_softdevice::gatt_service(uuid = "6e400001-b5a3-f393-e0a9-e50e24dcca9e")]
+pub struct NusService {
+    #[characteristic(
+        uuid = "6e400002-b5a3-f393-e0a9-e50e24dcca9e",
+        write,
+        write_without_response,
+        security = "JustWorks"
+    )]
+    rx: Vec<u8, MAX_PAYLOAD>,
+    #[characteristic(
+        uuid = "6e400003-b5a3-f393-e0a9-e50e24dcca9e",
+        notify,
+        security = "JustWorks"
+    )]
+    tx: Vec<u8, MAX_PAYLOAD>,
+}
+
+/// How often a full tx queue is retried before the data is dropped
+const QUEUE_FULL_RETRIES: usize = 20;
+
+impl NusService {
+    /// Sends `data` to `conn` in chunks of the negotiated ATT_MTU.
+    ///
+    /// The softdevice only has room for a few queued notifications, when it is full
+    /// we back off and retry instead of dropping the rest of the reply.
+    pub async fn send(&self, conn: &Connection, data: &[u8]) -> Result<(), NotifyValueError> {
+        let chunk_len = (conn.att_mtu() as usize)
+            .saturating_sub(3)
+            .clamp(1, MAX_PAYLOAD);
+        for chunk in data.chunks(chunk_len) {
+            // chunk_len is at most MAX_PAYLOAD
+            let chunk = Vec::from_slice(chunk).unwrap();
+            let mut retries = 0;
+            loop {
+                match self.tx_notify(conn, &chunk) {
+                    Err(NotifyValueError::Raw(RawError::Resources))
+                        if retries < QUEUE_FULL_RETRIES =>
+                    {
+                        retries += 1;
+                        Timer::after_millis(10).await;
+                    }
+                    res => break res?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A complete line written by the client of connection slot `idx`
+pub struct ConsoleLine {
+    pub idx: usize,
+    pub line: Result<Vec<u8, MAX_LINE>, ParseError>,
+}
+
+/// Lines from every connection, waiting for [`console_task`]
+pub static CONSOLE: Channel<ThreadModeRawMutex, ConsoleLine, 4> = Channel::new();
+
+/// Splits rx writes of connection slot `idx` into lines and queues them for the console
+pub fn on_rx(idx: usize, data: &[u8]) {
+    conns::console(idx, |console| {
+        console.feed(data, |line| {
+            let line = line.map(|line| Vec::from_slice(line).unwrap());
+            if CONSOLE.try_send(ConsoleLine { idx, line }).is_err() {
+                warn!("console {}: queue full, line dropped", idx);
+            }
+        })
+    });
+}
+
+/// Runs console commands and sends the replies back over NUS
+#[embassy_executor::task]
+pub async fn console_task(server: &'static Server) {
+    #[cfg(feature = "s140")]
+    let sd = unsafe { Softdevice::steal() };
+    loop {
+        let ConsoleLine { idx, line } = CONSOLE.receive().await;
+        let Some(conn) = CONNS.lock().await.get(idx).map(|slot| slot.conn.clone()) else {
+            continue;
+        };
+
+        let mut reply: String<128> = String::new();
+        let cmd = line
+            .as_ref()
+            .map_err(|e| *e)
+            .and_then(|line| console::parse(line));
+        // the reply always fits in 128 bytes, so the write results are ignored
+        let _ = match cmd {
+            Ok(Command::Help) => writeln!(reply, "{}", console::HELP),
+            Ok(Command::Batt) => match server.bas.battery_level_get() {
+                Ok(lvl) => writeln!(reply, "battery: {}%", lvl),
+                Err(_) => writeln!(reply, "error: battery level unavailable"),
+            },
+            Ok(Command::Info) => writeln!(
+                reply,
+                "fw {}\nserial {}\nuptime {}s\nconnections {}\nadvertising {}",
+                dis::FIRMWARE_REVISION,
+                core::str::from_utf8(&dis::serial_number()).unwrap_or("?"),
+                Instant::now().as_secs(),
+                CONNS.lock().await.len(),
+                advertising::state().as_str(),
+            ),
+            Ok(Command::Time) => match clock::now() {
+                Some(time) => writeln!(reply, "{} {:?}", time, time.weekday()),
+                None => writeln!(reply, "error: clock not set, connect a phone with CTS"),
+            },
+            Ok(Command::Name(name)) => match name::set(name) {
+                Ok(_) => writeln!(reply, "name: {}", name),
+                Err(_) => writeln!(reply, "error: invalid name"),
+            },
+            #[cfg(feature = "s140")]
+            Ok(Command::Scan) => match central::discover(sd, central::SCAN_TIME).await {
+                Ok(found) => {
+                    for (i, found) in found.iter().enumerate() {
+                        let mut line: String<72> = String::new();
+                        let _ = write!(line, "{} ", i);
+                        let _ = central::write_addr(&mut line, &found.addr);
+                        let _ = writeln!(
+                            line,
+                            " {} dBm{} {}",
+                            found.rssi,
+                            if found.connectable { "" } else { " nc" },
+                            found.name
+                        );
+                        if let Err(e) = server.nus.send(&conn, line.as_bytes()).await {
+                            warn!("console {}: reply failed: {}", idx, e);
+                        }
+                    }
+                    writeln!(reply, "{} found", found.len())
+                }
+                Err(e) => {
+                    warn!("scan failed: {}", e);
+                    writeln!(reply, "error: scan failed")
+                }
+            },
+            #[cfg(feature = "s140")]
+            Ok(Command::Connect(n)) => match central::connect(sd, n).await {
+                Ok(addr) => {
+                    let _ = write!(reply, "connected to ");
+                    let _ = central::write_addr(&mut reply, &addr);
+                    writeln!(reply)
+                }
+                Err(central::Error::NotFound) => {
+                    writeln!(reply, "error: no device {}, scan first", n)
+                }
+                Err(central::Error::Busy) => writeln!(reply, "error: connected already"),
+                Err(central::Error::Connect(e)) => {
+                    warn!("connect failed: {}", e);
+                    writeln!(reply, "error: connect failed")
+                }
+            },
+            #[cfg(feature = "s140")]
+            Ok(Command::Disconnect) => match central::disconnect().await {
+                true => writeln!(reply, "disconnected"),
+                false => writeln!(reply, "error: not connected"),
+            },
+            #[cfg(feature = "s140")]
+            Ok(Command::Radar(on)) => {
+                radar::set(on);
+                writeln!(reply, "radar {}", if on { "on" } else { "off" })
+            }
+            #[cfg(not(feature = "s140"))]
+            Ok(Command::Scan | Command::Connect(_) | Command::Disconnect | Command::Radar(_)) => {
+                writeln!(reply, "error: the central role needs the s140 build")
+            }
+            Ok(Command::Reboot) => writeln!(reply, "rebooting"),
+            Err(ParseError::Empty) => continue,
+            Err(ParseError::Unknown) => writeln!(reply, "unknown command, try help"),
+            Err(ParseError::MissingArgument) => writeln!(reply, "error: missing argument"),
+            Err(ParseError::UnexpectedArgument) => writeln!(reply, "error: unexpected argument"),
+            Err(ParseError::InvalidArgument) => writeln!(reply, "error: invalid argument"),
+            Err(ParseError::NotUtf8) => writeln!(reply, "error: not utf8"),
+            Err(ParseError::TooLong) => writeln!(reply, "error: line too long"),
+        };
+
+        if let Err(e) = server.nus.send(&conn, reply.as_bytes()).await {
+            warn!("console {}: reply failed: {}", idx, e);
+        }
+        if cmd == Ok(Command::Reboot) {
+            // give the reply a chance to go out
+            Timer::after_millis(200).await;
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+    }
+}
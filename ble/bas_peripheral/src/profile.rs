@@ -0,0 +1,60 @@
+//! micro:bit Bluetooth profile, so the existing companion apps work with this firmware:
+//! https://lancaster-university.github.io/microbit-docs/resources/bluetooth/bluetooth_profile.html
+//!
+//! Opt in with the `microbit-profile` feature. Without it [`Profile`] registers
+//! nothing and all its hooks do nothing, so the rest of the app does not need to care.
+//! The button service of the profile is always on, see `buttons.rs`. The payload layouts
+//! are in `ble-profile` (`ble/profile`, host tested).
+//!
+//! Not all of the profile is served:
+//! - IO Pin Service serves the edge pins P0 to P2 only. Analog outputs share PWM1 and so
+//!   one period, the last PWM Control write sets it for all of them. Analog inputs are
+//!   sampled against the supply on the SAADC the battery monitor samples with.
+//! - Magnetometer Calibration only takes out the hard iron offset: turn the board around
+//!   every axis while the display scrolls "turn me". There is no tilt compensation.
+//!
+//! Clients that write what isn't served get an error from the softdevice, nothing is
+//! accepted and then ignored.
+
+#[cfg(feature = "microbit-profile")]
+mod services;
+#[cfg(feature = "microbit-profile")]
+pub use services::*;
+
+#[cfg(not(feature = "microbit-profile"))]
+pub use disabled::*;
+
+#[cfg(not(feature = "microbit-profile"))]
+mod disabled {
+    use microbit_bsp::display::Frame;
+    use nrf_softdevice::ble::gatt_server::{self, RegisterError};
+    use nrf_softdevice::Softdevice;
+
+    use crate::buttons::{Button, ButtonState};
+
+    pub struct Profile;
+
+    pub enum ProfileEvent {}
+
+    impl Profile {
+        pub fn new(_sd: &mut Softdevice) -> Result<Self, RegisterError> {
+            Ok(Self)
+        }
+
+        pub fn on_event(&self, event: ProfileEvent) {
+            match event {}
+        }
+
+        pub fn on_frame(&self, _frame: &Frame<5, 5>) {}
+
+        pub async fn on_button(&self, _button: Button, _from: ButtonState, _to: ButtonState) {}
+    }
+
+    impl gatt_server::Service for Profile {
+        type Event = ProfileEvent;
+
+        fn on_write(&self, _handle: u16, _data: &[u8]) -> Option<Self::Event> {
+            None
+        }
+    }
+}
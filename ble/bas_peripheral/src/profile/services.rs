@@ -0,0 +1,703 @@
+//! The profile services and the tasks that feed them.
+
+use ble_profile::{self as codec, calibration, dal, Calibration, Event, PinMode, Pwm, PwmTiming};
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_nrf::bind_interrupts;
+use embassy_nrf::gpio::{AnyPin, Flex, OutputDrive, Pull};
+use embassy_nrf::interrupt::{self, InterruptExt, Priority};
+use embassy_nrf::peripherals::{P0_08, P0_16, PWM1, TWISPI0};
+use embassy_nrf::pwm::{Prescaler, SimplePwm};
+use embassy_nrf::saadc::{AnyInput, ChannelConfig, Gain, Reference};
+use embassy_nrf::twim::{self, Twim};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::{channel::Channel, signal::Signal};
+use embassy_time::{Delay, Duration, Instant, Timer};
+use heapless::Vec;
+use lsm303agr::interface::I2cInterface;
+use lsm303agr::mode::MagContinuous;
+use lsm303agr::{AccelMode, AccelOutputDataRate, Lsm303agr, MagMode, MagOutputDataRate};
+use microbit_bsp::display::Frame;
+use nrf_softdevice::ble::gatt_server::{self, NotifyValueError, RegisterError};
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::{raw, Softdevice};
+
+use crate::battery;
+use crate::buttons::{Button, ButtonState};
+use crate::conns::CONNS;
+use crate::display::{DisplayCmd, DISPLAY, MAX_TEXT};
+use crate::{cccd_notifications, Server};
+
+bind_interrupts!(struct Irqs {
+    SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0 => twim::InterruptHandler<TWISPI0>;
+});
+
+/// Pin Data notifications hold up to this many `(pin, value)` pairs
+const MAX_PIN_PAIRS: usize = 19;
+/// Event service lists hold up to this many `(id, value)` pairs
+const MAX_EVENTS: usize = 5;
+/// Edge connector pins served by the io pin service, P0 to P2
+const IO_PINS: usize = 3;
+/// PWM Control writes hold up to this many entries
+const MAX_PWM_ENTRIES: usize = 2;
+/// How long the board is turned around for a magnetometer calibration
+const CALIBRATION_TIME: Duration = Duration::from_secs(20);
+/// Smallest swing of the field on every axis for a calibration to count, in nT. The earth's
+/// field is 25 to 65 µT, turning the board over swings an axis by twice that.
+const CALIBRATION_MIN_SPAN: i32 = 30_000;
+
+#[nrf_softdevice::gatt_service(uuid = "e95d0753-251d-470a-a062-fa1922dfa9a8")]
+pub struct AccelerometerService {
+    /// x, y, z in milli-g
+    #[characteristic(uuid = "e95dca4b-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    data: [u8; 6],
+    /// ms between updates, see `codec::SENSOR_PERIODS_MS`
+    #[characteristic(
+        uuid = "e95dfb24-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    period: u16,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "e95df2d8-251d-470a-a062-fa1922dfa9a8")]
+pub struct MagnetometerService {
+    /// x, y, z in milli-gauss
+    #[characteristic(uuid = "e95dfb11-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    data: [u8; 6],
+    #[characteristic(
+        uuid = "e95d386c-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    period: u16,
+    /// degrees from north
+    #[characteristic(uuid = "e95d9715-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    bearing: u16,
+    /// `calibration` values, the client writes REQUESTED and is notified of the outcome
+    #[characteristic(
+        uuid = "e95db358-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        notify,
+        security = "JustWorks"
+    )]
+    calibration: u8,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "e95dd91d-251d-470a-a062-fa1922dfa9a8")]
+pub struct LedService {
+    #[characteristic(
+        uuid = "e95d7b77-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    matrix_state: [u8; 5],
+    #[characteristic(
+        uuid = "e95d93ee-251d-470a-a062-fa1922dfa9a8",
+        write,
+        security = "JustWorks"
+    )]
+    text: Vec<u8, 20>,
+    /// ms per scrolled column
+    #[characteristic(
+        uuid = "e95d0d2d-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    scrolling_delay: u16,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "e95d127b-251d-470a-a062-fa1922dfa9a8")]
+pub struct IoPinService {
+    #[characteristic(
+        uuid = "e95d8d00-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        notify,
+        security = "JustWorks"
+    )]
+    pin_data: Vec<u8, { MAX_PIN_PAIRS * 2 }>,
+    /// bit n set: pin n is analog, an analog output is a PWM
+    #[characteristic(
+        uuid = "e95d5899-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    ad_configuration: u32,
+    /// bit n set: pin n is an input
+    #[characteristic(
+        uuid = "e95db9fe-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    io_configuration: u32,
+    /// up to 2 `codec::Pwm` entries, pin, value and period of an analog output
+    #[characteristic(
+        uuid = "e95dd822-251d-470a-a062-fa1922dfa9a8",
+        write,
+        security = "JustWorks"
+    )]
+    pwm_control: Vec<u8, { MAX_PWM_ENTRIES * codec::PWM_ENTRY_LEN }>,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "e95d6100-251d-470a-a062-fa1922dfa9a8")]
+pub struct TemperatureService {
+    /// whole degrees celsius
+    #[characteristic(uuid = "e95d9250-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    temperature: i8,
+    #[characteristic(
+        uuid = "e95d1b25-251d-470a-a062-fa1922dfa9a8",
+        read,
+        write,
+        security = "JustWorks"
+    )]
+    period: u16,
+}
+
+#[nrf_softdevice::gatt_service(uuid = "e95d93af-251d-470a-a062-fa1922dfa9a8")]
+pub struct EventService {
+    /// Events we want from the client, none
+    #[characteristic(uuid = "e95db84c-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    microbit_requirements: Vec<u8, { MAX_EVENTS * 4 }>,
+    #[characteristic(uuid = "e95d9775-251d-470a-a062-fa1922dfa9a8", read, notify)]
+    microbit_event: Vec<u8, { MAX_EVENTS * 4 }>,
+    /// Events the client wants from us
+    #[characteristic(
+        uuid = "e95d23c4-251d-470a-a062-fa1922dfa9a8",
+        write,
+        security = "JustWorks"
+    )]
+    client_requirements: Vec<u8, { MAX_EVENTS * 4 }>,
+    #[characteristic(
+        uuid = "e95d5404-251d-470a-a062-fa1922dfa9a8",
+        write,
+        security = "JustWorks"
+    )]
+    client_event: Vec<u8, { MAX_EVENTS * 4 }>,
+}
+
+/// All profile services as one entry of the gatt server
+pub struct Profile {
+    accel: AccelerometerService,
+    mag: MagnetometerService,
+    led: LedService,
+    io: IoPinService,
+    temp: TemperatureService,
+    event: EventService,
+}
+
+pub enum ProfileEvent {
+    Accel(AccelerometerServiceEvent),
+    Mag(MagnetometerServiceEvent),
+    Led(LedServiceEvent),
+    Io(IoPinServiceEvent),
+    Temp(TemperatureServiceEvent),
+    Event(EventServiceEvent),
+}
+
+impl gatt_server::Service for Profile {
+    type Event = ProfileEvent;
+
+    fn on_write(&self, handle: u16, data: &[u8]) -> Option<Self::Event> {
+        self.accel
+            .on_write(handle, data)
+            .map(ProfileEvent::Accel)
+            .or_else(|| self.mag.on_write(handle, data).map(ProfileEvent::Mag))
+            .or_else(|| self.led.on_write(handle, data).map(ProfileEvent::Led))
+            .or_else(|| self.io.on_write(handle, data).map(ProfileEvent::Io))
+            .or_else(|| self.temp.on_write(handle, data).map(ProfileEvent::Temp))
+            .or_else(|| self.event.on_write(handle, data).map(ProfileEvent::Event))
+    }
+}
+
+/// Pin writes from clients, waiting for [`io_task`]
+enum PinWrite {
+    /// Pin Data, a `(pin, value)` pair
+    Data(u8, u8),
+    /// PWM Control
+    Pwm(Pwm),
+}
+
+static PIN_WRITES: Channel<ThreadModeRawMutex, PinWrite, 8> = Channel::new();
+/// A client asked [`motion_task`] for a magnetometer calibration
+static CALIBRATE: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Notifies every connection that enabled notifications in the CCCD `cccd`
+async fn notify_all(cccd: u16, notify: impl Fn(&Connection) -> Result<(), NotifyValueError>) {
+    for slot in CONNS.lock().await.iter() {
+        let subscribed = slot
+            .conn
+            .handle()
+            .is_some_and(|conn| cccd_notifications(conn, cccd));
+        if subscribed {
+            let _ = notify(&slot.conn);
+        }
+    }
+}
+
+impl Profile {
+    pub fn new(sd: &mut Softdevice) -> Result<Self, RegisterError> {
+        let profile = Self {
+            accel: AccelerometerService::new(sd)?,
+            mag: MagnetometerService::new(sd)?,
+            led: LedService::new(sd)?,
+            io: IoPinService::new(sd)?,
+            temp: TemperatureService::new(sd)?,
+            event: EventService::new(sd)?,
+        };
+        // defaults of the micro:bit runtime
+        let _ = profile.accel.period_set(&20);
+        let _ = profile.mag.period_set(&20);
+        let _ = profile.led.scrolling_delay_set(&120);
+        let _ = profile.temp.period_set(&1000);
+        // all pins digital inputs
+        let _ = profile.io.io_configuration_set(&u32::MAX);
+        Ok(profile)
+    }
+
+    pub fn on_event(&self, event: ProfileEvent) {
+        match event {
+            ProfileEvent::Accel(AccelerometerServiceEvent::PeriodWrite(ms)) => {
+                let _ = self.accel.period_set(&codec::nearest_period(ms));
+            }
+            ProfileEvent::Accel(AccelerometerServiceEvent::DataCccdWrite { .. }) => {}
+            ProfileEvent::Mag(MagnetometerServiceEvent::PeriodWrite(ms)) => {
+                let _ = self.mag.period_set(&codec::nearest_period(ms));
+            }
+            ProfileEvent::Mag(MagnetometerServiceEvent::CalibrationWrite(
+                calibration::REQUESTED,
+            )) => {
+                CALIBRATE.signal(());
+            }
+            ProfileEvent::Mag(MagnetometerServiceEvent::CalibrationWrite(value)) => {
+                warn!("magnetometer calibration {} is not a request", value);
+                let _ = self.mag.calibration_set(&calibration::UNKNOWN);
+            }
+            ProfileEvent::Mag(_) => {}
+            ProfileEvent::Led(e) => self.on_led_event(e),
+            ProfileEvent::Io(IoPinServiceEvent::PinDataWrite(data)) => {
+                for (pin, value) in codec::decode_pin_pairs(&data) {
+                    if PIN_WRITES.try_send(PinWrite::Data(pin, value)).is_err() {
+                        warn!("pin write dropped");
+                    }
+                }
+            }
+            ProfileEvent::Io(IoPinServiceEvent::PwmControlWrite(data)) => {
+                for entry in codec::decode_pwm(&data) {
+                    if PIN_WRITES.try_send(PinWrite::Pwm(entry)).is_err() {
+                        warn!("pwm write dropped");
+                    }
+                }
+            }
+            ProfileEvent::Io(_) => {}
+            ProfileEvent::Temp(_) => {}
+            ProfileEvent::Event(EventServiceEvent::ClientEventWrite(data)) => {
+                for event in codec::decode_events(&data) {
+                    info!("client event: {}", event);
+                }
+            }
+            ProfileEvent::Event(_) => {}
+        }
+    }
+
+    fn on_led_event(&self, event: LedServiceEvent) {
+        let cmd = match event {
+            LedServiceEvent::MatrixStateWrite(rows) => {
+                let mut frame = Frame::default();
+                codec::decode_led_rows(rows, |row, col| frame.set(row, col));
+                DisplayCmd::Frame(frame)
+            }
+            LedServiceEvent::TextWrite(text) => {
+                let Ok(text) = core::str::from_utf8(&text) else {
+                    warn!("led text is not utf8");
+                    return;
+                };
+                let delay = self.led.scrolling_delay_get().unwrap_or(120);
+                let mut scroll = heapless::String::<MAX_TEXT>::new();
+                let _ = scroll.push_str(text);
+                DisplayCmd::Text(scroll, Some(Duration::from_millis(delay as u64)))
+            }
+            LedServiceEvent::ScrollingDelayWrite(_) => return,
+        };
+        if DISPLAY.try_send(cmd).is_err() {
+            warn!("display busy, command dropped");
+        }
+    }
+
+    /// Keeps LED Matrix State in step with what is on the display
+    pub fn on_frame(&self, frame: &Frame<5, 5>) {
+        let rows = codec::encode_led_rows(|row, col| frame.is_set(row, col));
+        let _ = self.led.matrix_state_set(&rows);
+    }
+
+    /// Sends the DAL button events the client asked for in its requirements
+    pub async fn on_button(&self, button: Button, from: ButtonState, to: ButtonState) {
+        let id = match button {
+            Button::A => dal::ID_BUTTON_A,
+            Button::B => dal::ID_BUTTON_B,
+        };
+        let requirements = self.event.client_requirements_get().unwrap_or_default();
+        let requirements: Vec<Event, MAX_EVENTS> = codec::decode_events(&requirements).collect();
+
+        let mut events: Vec<u8, { MAX_EVENTS * 4 }> = Vec::new();
+        for &value in codec::button_events(from as u8, to as u8) {
+            let event = Event { id, value };
+            if codec::is_required(&requirements, event) {
+                let _ = events.extend_from_slice(&codec::encode_event(event));
+            }
+        }
+        if events.is_empty() {
+            return;
+        }
+        let _ = self.event.microbit_event_set(&events);
+        notify_all(self.event.microbit_event_cccd_handle, |conn| {
+            self.event.microbit_event_notify(conn, &events)
+        })
+        .await;
+    }
+}
+
+/// The LSM303AGR on the internal bus with the magnetometer sampling continuously
+type Sensor = Lsm303agr<I2cInterface<Twim<'static, TWISPI0>>, MagContinuous>;
+
+/// Hardware the profile tasks take over
+pub struct Parts {
+    pub twim: TWISPI0,
+    /// internal i2c bus to the motion sensor
+    pub scl: P0_08,
+    pub sda: P0_16,
+    /// edge connector P0 to P2
+    pub pins: [AnyPin; IO_PINS],
+    /// the same pins as SAADC inputs, only sampled while they are analog inputs
+    pub analog: [AnyInput; IO_PINS],
+    /// drives the pins that are analog outputs
+    pub pwm: PWM1,
+}
+
+pub fn spawn(spawner: &Spawner, server: &'static Server, parts: Parts) {
+    // must not collide with the priorities reserved by the softdevice
+    interrupt::SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0.set_priority(Priority::P3);
+    let twim = Twim::new(
+        parts.twim,
+        Irqs,
+        parts.sda,
+        parts.scl,
+        twim::Config::default(),
+    );
+    spawner.must_spawn(motion_task(server, twim));
+    spawner.must_spawn(temperature_task(server));
+    spawner.must_spawn(io_task(server, parts.pins, parts.analog, parts.pwm));
+}
+
+/// Accelerometer and magnetometer, both on the LSM303AGR
+#[embassy_executor::task]
+async fn motion_task(server: &'static Server, twim: Twim<'static, TWISPI0>) {
+    let profile = &server.profile;
+    let mut sensor = Lsm303agr::new_with_i2c(twim);
+    let ready = async {
+        sensor.init().await?;
+        // fast enough for the shortest period, faster than the period just repeats samples
+        sensor
+            .set_accel_mode_and_odr(&mut Delay, AccelMode::Normal, AccelOutputDataRate::Hz400)
+            .await?;
+        sensor
+            .set_mag_mode_and_odr(
+                &mut Delay,
+                MagMode::HighResolution,
+                MagOutputDataRate::Hz100,
+            )
+            .await
+    };
+    if ready.await.is_err() {
+        warn!("motion sensor init failed");
+        return;
+    }
+    let Ok(mut sensor) = sensor.into_mag_continuous().await else {
+        warn!("magnetometer init failed");
+        return;
+    };
+
+    // hard iron offset in nT, none until a client has the board calibrated
+    let mut offset = [0; 3];
+    let mut next_accel = Instant::now();
+    let mut next_mag = Instant::now();
+    loop {
+        if let Either::Second(()) =
+            select(Timer::at(next_accel.min(next_mag)), CALIBRATE.wait()).await
+        {
+            let value = match calibrate(&mut sensor).await {
+                Some(found) => {
+                    info!("magnetometer offset: {} nT", found);
+                    offset = found;
+                    calibration::COMPLETED
+                }
+                None => calibration::ERROR,
+            };
+            let _ = profile.mag.calibration_set(&value);
+            notify_all(profile.mag.calibration_cccd_handle, |conn| {
+                profile.mag.calibration_notify(conn, &value)
+            })
+            .await;
+            continue;
+        }
+        let now = Instant::now();
+
+        if now >= next_accel {
+            let period = profile.accel.period_get().unwrap_or(20);
+            next_accel = now + Duration::from_millis(period as u64);
+            if let Ok(accel) = sensor.acceleration().await {
+                let (x, y, z) = accel.xyz_mg();
+                let data = codec::encode_xyz([x, y, z]);
+                let _ = profile.accel.data_set(&data);
+                notify_all(profile.accel.data_cccd_handle, |conn| {
+                    profile.accel.data_notify(conn, &data)
+                })
+                .await;
+            }
+        }
+
+        if now >= next_mag {
+            let period = profile.mag.period_get().unwrap_or(20);
+            next_mag = now + Duration::from_millis(period as u64);
+            if let Ok(field) = sensor.magnetic_field().await {
+                // 1 milli-gauss is 100 nT
+                let (x, y, z) = field.xyz_nt();
+                let [x, y, z] = [x - offset[0], y - offset[1], z - offset[2]];
+                let data = codec::encode_xyz([x / 100, y / 100, z / 100]);
+                let bearing = codec::bearing(x, y);
+                let _ = profile.mag.data_set(&data);
+                let _ = profile.mag.bearing_set(&bearing);
+                notify_all(profile.mag.data_cccd_handle, |conn| {
+                    profile.mag.data_notify(conn, &data)
+                })
+                .await;
+                notify_all(profile.mag.bearing_cccd_handle, |conn| {
+                    profile.mag.bearing_notify(conn, &bearing)
+                })
+                .await;
+            }
+        }
+    }
+}
+
+/// Collects the field while the user turns the board around, the display shows it is
+/// calibrating. Accelerometer and magnetometer updates stop meanwhile.
+async fn calibrate(sensor: &mut Sensor) -> Option<[i32; 3]> {
+    show("turn me");
+    let mut found = Calibration::new();
+    let end = Instant::now() + CALIBRATION_TIME;
+    while Instant::now() < end {
+        if let Ok(field) = sensor.magnetic_field().await {
+            let (x, y, z) = field.xyz_nt();
+            found.add([x, y, z]);
+        }
+        // the magnetometer runs at 100 Hz
+        Timer::after_millis(10).await;
+    }
+    let offset = found.offset(CALIBRATION_MIN_SPAN);
+    show(if offset.is_some() { "ok" } else { "try again" });
+    offset
+}
+
+/// Scrolls `text` once at the default speed
+fn show(text: &str) {
+    let mut scroll = heapless::String::<MAX_TEXT>::new();
+    let _ = scroll.push_str(text);
+    if DISPLAY.try_send(DisplayCmd::Text(scroll, None)).is_err() {
+        warn!("display busy, command dropped");
+    }
+}
+
+/// Die temperature, the TEMP peripheral belongs to the softdevice so it is read through it
+#[embassy_executor::task]
+async fn temperature_task(server: &'static Server) {
+    let temp = &server.profile.temp;
+    loop {
+        let mut quarter_degrees = 0;
+        let ret = unsafe { raw::sd_temp_get(&mut quarter_degrees) };
+        if ret == raw::NRF_SUCCESS {
+            let celsius = codec::temperature(quarter_degrees);
+            let _ = temp.temperature_set(&celsius);
+            notify_all(temp.temperature_cccd_handle, |conn| {
+                temp.temperature_notify(conn, &celsius)
+            })
+            .await;
+        }
+        let period = temp.period_get().unwrap_or(1000).max(1);
+        Timer::after_millis(period as u64).await;
+    }
+}
+
+/// Drives the edge pins as configured in IO and AD Configuration and reports input changes
+#[embassy_executor::task]
+async fn io_task(
+    server: &'static Server,
+    mut pins: [AnyPin; IO_PINS],
+    mut analog: [AnyInput; IO_PINS],
+    mut pwm: PWM1,
+) {
+    // one PWM drives all analog outputs, so they share the period
+    let mut timing = PwmTiming::new(codec::PWM_DEFAULT_PERIOD_US);
+    loop {
+        serve_pins(server, &mut pins, &mut analog, &mut pwm, &mut timing).await;
+    }
+}
+
+/// IO and AD Configuration, all digital inputs until the client writes them
+fn pin_config(io: &IoPinService) -> (u32, u32) {
+    (
+        io.io_configuration_get().unwrap_or(u32::MAX),
+        io.ad_configuration_get().unwrap_or(0),
+    )
+}
+
+/// Serves the pins in the modes configured now, returns once the client changes them
+async fn serve_pins(
+    server: &'static Server,
+    pins: &mut [AnyPin; IO_PINS],
+    analog: &mut [AnyInput; IO_PINS],
+    pwm: &mut PWM1,
+    timing: &mut PwmTiming,
+) {
+    const POLL: Duration = Duration::from_millis(50);
+    let io = &server.profile.io;
+    let config = pin_config(io);
+    let modes: [PinMode; IO_PINS] = codec::pin_modes(config.0, config.1);
+
+    // analog inputs get no driver, the pin stays disconnected for the SAADC
+    let mut digital: [Option<Flex<'_, AnyPin>>; IO_PINS] = [None, None, None];
+    let mut outputs: Vec<&mut AnyPin, IO_PINS> = Vec::new();
+    // PWM channel of each analog output
+    let mut channels = [None; IO_PINS];
+    for (n, pin) in pins.iter_mut().enumerate() {
+        match modes[n] {
+            PinMode::DigitalIn => {
+                let mut pin = Flex::new(pin);
+                pin.set_as_input(Pull::None);
+                digital[n] = Some(pin);
+            }
+            PinMode::DigitalOut => {
+                let mut pin = Flex::new(pin);
+                pin.set_as_output(OutputDrive::Standard);
+                digital[n] = Some(pin);
+            }
+            PinMode::AnalogOut => {
+                channels[n] = Some(outputs.len());
+                let _ = outputs.push(pin);
+            }
+            PinMode::AnalogIn => {}
+        }
+    }
+    let mut pwm = match outputs.as_mut_slice() {
+        [] => None,
+        [a] => Some(SimplePwm::new_1ch(&mut *pwm, &mut **a)),
+        [a, b] => Some(SimplePwm::new_2ch(&mut *pwm, &mut **a, &mut **b)),
+        [a, b, c, ..] => Some(SimplePwm::new_3ch(&mut *pwm, &mut **a, &mut **b, &mut **c)),
+    };
+    // PWM values of the analog outputs, 0 to PWM_MAX
+    let mut values = [0; IO_PINS];
+    if let Some(pwm) = &mut pwm {
+        set_timing(pwm, timing);
+        for (&channel, &value) in channels.iter().zip(&values) {
+            if let Some(channel) = channel {
+                set_pwm(pwm, channel, timing, value);
+            }
+        }
+    }
+
+    let mut levels = [None; IO_PINS];
+    while pin_config(io) == config {
+        while let Ok(write) = PIN_WRITES.try_receive() {
+            let (n, value) = match write {
+                PinWrite::Data(n, value) => (n, codec::pwm_value(value)),
+                PinWrite::Pwm(entry) => {
+                    if channels
+                        .get(entry.pin as usize)
+                        .is_some_and(Option::is_some)
+                    {
+                        *timing = PwmTiming::new(entry.period_us);
+                        if let Some(pwm) = &mut pwm {
+                            set_timing(pwm, timing);
+                        }
+                    }
+                    (entry.pin, entry.value)
+                }
+            };
+            let n = n as usize;
+            match (modes.get(n), digital.get_mut(n), &mut pwm) {
+                (Some(PinMode::DigitalOut), Some(Some(pin)), _) => match value {
+                    0 => pin.set_low(),
+                    _ => pin.set_high(),
+                },
+                (Some(PinMode::AnalogOut), _, Some(pwm)) => {
+                    values[n] = value;
+                    for (&channel, &value) in channels.iter().zip(&values) {
+                        if let Some(channel) = channel {
+                            set_pwm(pwm, channel, timing, value);
+                        }
+                    }
+                }
+                _ => warn!("pin {} is not an output", n),
+            }
+        }
+
+        let mut changed: Vec<(u8, u8), IO_PINS> = Vec::new();
+        for (n, input) in analog.iter_mut().enumerate() {
+            let level = match (modes[n], &digital[n]) {
+                (PinMode::DigitalIn, Some(pin)) => pin.is_high() as u8,
+                (PinMode::AnalogIn, _) => {
+                    // ratiometric, full scale is the supply
+                    let mut channel = ChannelConfig::single_ended(&mut *input);
+                    channel.reference = Reference::VDD1_4;
+                    channel.gain = Gain::GAIN1_4;
+                    codec::analog_pin_value(battery::sample(channel).await)
+                }
+                _ => continue,
+            };
+            if levels[n] != Some(level) {
+                levels[n] = Some(level);
+                let _ = changed.push((n as u8, level));
+            }
+        }
+        if !changed.is_empty() {
+            let data = codec::encode_pin_pairs(changed);
+            let _ = io.pin_data_set(&data);
+            notify_all(io.pin_data_cccd_handle, |conn| {
+                io.pin_data_notify(conn, &data)
+            })
+            .await;
+        }
+
+        Timer::after(POLL).await;
+    }
+}
+
+/// Sets the period of all PWM channels
+fn set_timing(pwm: &mut SimplePwm<'_, PWM1>, timing: &PwmTiming) {
+    let prescaler = match timing.prescaler {
+        0 => Prescaler::Div1,
+        1 => Prescaler::Div2,
+        2 => Prescaler::Div4,
+        3 => Prescaler::Div8,
+        4 => Prescaler::Div16,
+        5 => Prescaler::Div32,
+        6 => Prescaler::Div64,
+        _ => Prescaler::Div128,
+    };
+    pwm.set_prescaler(prescaler);
+    pwm.set_max_duty(timing.top);
+}
+
+/// Sets the PWM value of one channel, 0 to PWM_MAX
+fn set_pwm(pwm: &mut SimplePwm<'_, PWM1>, channel: usize, timing: &PwmTiming, value: u16) {
+    // the output goes high at the compare value, so the duty counts the low time
+    pwm.set_duty(channel, timing.top - timing.high(value));
+}
@@ -0,0 +1,149 @@
+//! Proximity radar: scans for other boards advertising the battery service and shows how
+//! close the closest one is on the matrix, S140 builds only.
+//!
+//! The device table and the bar levels are in `ble-radar` (`ble/radar`, host tested).
+//! The console turns the radar on and off with `radar on|off`. While it is on it draws
+//! over the matrix: rows lit from the bottom up, one for a board across the room and all
+//! five when the boards touch, a single dot while nobody is around.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use ble_radar::{Config, Table};
+use defmt::{debug, info, warn};
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use microbit_bsp::display::Frame;
+use nrf_softdevice::ble::central::{self, ScanConfig, ScanError};
+use nrf_softdevice::Softdevice;
+
+use crate::display::{DisplayCmd, DISPLAY};
+use crate::BATTERY_SERVICE;
+
+/// Boards tracked at once
+const PEERS: usize = 8;
+const CONFIG: Config = Config {
+    smoothing_shift: 2,
+    max_age: 5_000,
+    near: -45,
+    far: -90,
+};
+/// How often the table is aged and the matrix redrawn
+const REDRAW: Duration = Duration::from_millis(250);
+/// Pause before scanning again when the softdevice turned the scan down
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+static TABLE: Mutex<ThreadModeRawMutex, RefCell<Table<PEERS>>> =
+    Mutex::new(RefCell::new(Table::new(CONFIG)));
+static ON: AtomicBool = AtomicBool::new(false);
+static CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+pub fn set(on: bool) {
+    ON.store(on, Ordering::Relaxed);
+    CHANGED.signal(());
+}
+
+pub fn is_on() -> bool {
+    ON.load(Ordering::Relaxed)
+}
+
+/// Scans and draws while the radar is on
+#[embassy_executor::task]
+pub async fn radar_task(sd: &'static Softdevice) {
+    loop {
+        while !is_on() {
+            CHANGED.wait().await;
+        }
+        info!("radar on");
+        while is_on() {
+            match select3(scan(sd), draw(), off()).await {
+                Either3::First(Err(e)) => {
+                    // the console's scan or a connect may hold the scanner
+                    warn!("radar scan failed: {}", e);
+                    Timer::after(RETRY_AFTER).await;
+                }
+                _ => break,
+            }
+        }
+        TABLE.lock(|table| table.borrow_mut().clear());
+        let _ = DISPLAY.try_send(DisplayCmd::Frame(Frame::default()));
+        info!("radar off");
+    }
+}
+
+async fn off() {
+    loop {
+        CHANGED.wait().await;
+        if !is_on() {
+            return;
+        }
+    }
+}
+
+/// Passive scan without an end, every board advertising the battery service goes into
+/// the table
+async fn scan(sd: &Softdevice) -> Result<(), ScanError> {
+    let config = ScanConfig {
+        active: false,
+        // 100 ms, listening half of it
+        interval: 160,
+        window: 80,
+        ..Default::default()
+    };
+    central::scan(sd, &config, |report| {
+        // the softdevice hands out the data of the report it is called for
+        let data =
+            unsafe { core::slice::from_raw_parts(report.data.p_data, report.data.len as usize) };
+        if ble_radar::advertises_uuid16(data, BATTERY_SERVICE) {
+            let now = Instant::now().as_millis();
+            TABLE.lock(|table| {
+                table
+                    .borrow_mut()
+                    .update(report.peer_addr.addr, report.rssi, now)
+            });
+        }
+        None::<()>
+    })
+    .await
+}
+
+/// Ages the table and draws the closest board whenever its level changes
+async fn draw() {
+    let mut shown = None;
+    loop {
+        let bars = TABLE.lock(|table| {
+            let mut table = table.borrow_mut();
+            table.expire(Instant::now().as_millis());
+            table
+                .closest()
+                .map(|peer| (peer.rssi(), ble_radar::bars(peer.rssi(), table.config())))
+        });
+        let level = bars.map(|(_, level)| level);
+        // a full queue is tried again on the next round
+        if shown != Some(level) && DISPLAY.try_send(DisplayCmd::Frame(frame(level))).is_ok() {
+            if let Some((rssi, level)) = bars {
+                debug!("radar: closest {} dBm, {} bars", rssi, level);
+            }
+            shown = Some(level);
+        }
+        Timer::after(REDRAW).await;
+    }
+}
+
+fn frame(bars: Option<u8>) -> Frame<5, 5> {
+    let mut frame = Frame::default();
+    match bars {
+        Some(bars) => {
+            for row in (5 - bars.min(ble_radar::MAX_BARS)) as usize..5 {
+                for col in 0..5 {
+                    frame.set(row, col);
+                }
+            }
+        }
+        None => frame.set(4, 2),
+    }
+    frame
+}
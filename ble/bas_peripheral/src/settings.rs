@@ -0,0 +1,72 @@
+//! The `SETTINGS` page in memory-*.x, what the app keeps across resets and updates.
+//!
+//! Each setting has a record of its own at a fixed offset, encoded by the module that
+//! owns it. Flash is erased a whole page at a time, so [`write`] reads the other
+//! records first and puts them back.
+
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use nrf_softdevice::{Flash, FlashError};
+
+#[derive(Clone, Copy)]
+pub enum Record {
+    /// The GAP device name, see `name`
+    Name,
+    /// The newest image version accepted over DFU, see `dfu`
+    ImageVersion,
+}
+
+impl Record {
+    /// Where the record is in the page, whole flash words
+    fn range(self) -> Range<usize> {
+        match self {
+            // at the start of the page, where it was before there were other records
+            Record::Name => 0..crate::name::RECORD_LEN,
+            Record::ImageVersion => crate::name::RECORD_LEN..USED_LEN,
+        }
+    }
+}
+
+/// The records end to end
+const USED_LEN: usize = crate::name::RECORD_LEN + crate::dfu::ACCEPTED_LEN;
+
+extern "C" {
+    /// First byte of the settings page, defined in memory-*.x
+    static __settings_start: u32;
+}
+
+fn page_start() -> u32 {
+    core::ptr::addr_of!(__settings_start) as u32
+}
+
+/// The flash api only takes word aligned buffers
+#[repr(align(4))]
+struct Page([u8; USED_LEN]);
+
+async fn read_page(flash: &mut Flash) -> Result<Page, FlashError> {
+    let mut page = Page([0; USED_LEN]);
+    flash.read(page_start(), &mut page.0).await?;
+    Ok(page)
+}
+
+/// Reads a record into `buf`, which is as long as the record. Erased flash reads 0xFF
+pub async fn read(flash: &mut Flash, record: Record, buf: &mut [u8]) -> Result<(), FlashError> {
+    let page = read_page(flash).await?;
+    buf.copy_from_slice(&page.0[record.range()]);
+    Ok(())
+}
+
+/// Writes a record, unless it is already there, `true` if it wasn't
+pub async fn write(flash: &mut Flash, record: Record, data: &[u8]) -> Result<bool, FlashError> {
+    let mut page = read_page(flash).await?;
+    let range = record.range();
+    if page.0[range.clone()] == *data {
+        return Ok(false);
+    }
+    page.0[range].copy_from_slice(data);
+    let start = page_start();
+    flash.erase(start, start + Flash::ERASE_SIZE as u32).await?;
+    flash.write(start, &page.0).await?;
+    Ok(true)
+}
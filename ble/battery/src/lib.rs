@@ -0,0 +1,160 @@
+//! Battery level from supply voltage for ble-batt.
+//!
+//! Turns raw SAADC samples into millivolts, smooths them and maps them to a
+//! percentage through a discharge curve. No hardware access in here, the
+//! sampling itself happens in the `battery_monitor` task. Times are milliseconds
+//! since boot, whatever clock the caller has.
+//!
+//! Pure code, host tests: `cargo test -p ble-battery --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+/// What the SAADC channel is connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Source {
+    Vdd,
+    /// VDDH through the internal 1/5 divider, for boards powered on VDDH
+    VddhDiv5,
+}
+
+/// Converts a sample taken with the SAADC defaults
+/// (12 bit, gain 1/6, internal 0.6 V reference, so 3.6 V full scale) to millivolts.
+pub fn millivolts(raw: i16, source: Source) -> u16 {
+    let mv = raw.max(0) as u32 * 3600 / 4096;
+    match source {
+        Source::Vdd => mv as u16,
+        Source::VddhDiv5 => (mv * 5) as u16,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A point above the one before it, in voltage or in percent
+    Unsorted,
+}
+
+/// Piecewise linear discharge curve, points are `(millivolts, percent)`
+/// sorted by falling voltage, percent falling with it. [`Curve::new`] checks that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Curve(&'static [(u16, u8)]);
+
+/// 2xAAA alkaline cells in series, the micro:bit battery pack
+pub const ALKALINE_2XAAA: Curve = Curve::sorted(&[
+    (3000, 100),
+    (2900, 80),
+    (2800, 60),
+    (2700, 40),
+    (2500, 20),
+    (2300, 10),
+    (2000, 0),
+]);
+
+/// 2xAAA NiMH cells in series, flat around 1.2 V per cell
+pub const NIMH_2XAAA: Curve = Curve::sorted(&[
+    (2800, 100),
+    (2600, 90),
+    (2500, 70),
+    (2400, 40),
+    (2300, 20),
+    (2200, 10),
+    (2000, 0),
+]);
+
+/// Powered over usb, the regulator keeps VDD up so there is nothing to drain
+pub const USB: Curve = Curve::sorted(&[(0, 100)]);
+
+impl Curve {
+    /// An empty curve is fine, it reads 0 %
+    pub const fn new(points: &'static [(u16, u8)]) -> Result<Self, Error> {
+        let mut i = 1;
+        while i < points.len() {
+            let (hi_mv, hi_pct) = points[i - 1];
+            let (lo_mv, lo_pct) = points[i];
+            if lo_mv > hi_mv || lo_pct > hi_pct {
+                return Err(Error::Unsorted);
+            }
+            i += 1;
+        }
+        Ok(Self(points))
+    }
+
+    /// For the curves above, an unsorted one fails the build
+    const fn sorted(points: &'static [(u16, u8)]) -> Self {
+        match Self::new(points) {
+            Ok(curve) => curve,
+            Err(_) => panic!("battery curve not sorted"),
+        }
+    }
+
+    pub const fn points(&self) -> &'static [(u16, u8)] {
+        self.0
+    }
+
+    pub fn percent(&self, mv: u16) -> u8 {
+        let points = self.0;
+        let Some(&(top_mv, top_pct)) = points.first() else {
+            return 0;
+        };
+        if mv >= top_mv {
+            return top_pct;
+        }
+        for pair in points.windows(2) {
+            let (hi_mv, hi_pct) = pair[0];
+            let (lo_mv, lo_pct) = pair[1];
+            if mv >= lo_mv {
+                let span = (hi_mv - lo_mv) as u32;
+                let pct = (mv - lo_mv) as u32 * (hi_pct - lo_pct) as u32 / span.max(1);
+                return lo_pct + pct as u8;
+            }
+        }
+        // below the last point
+        points[points.len() - 1].1
+    }
+}
+
+/// Exponential moving average, every sample moves the output `1 / 2^shift` of the way.
+#[derive(Debug, Clone, Copy)]
+pub struct Filter {
+    shift: u8,
+    /// millivolts with 8 fractional bits, `None` until the first sample
+    state: Option<u32>,
+}
+
+impl Filter {
+    pub const fn new(shift: u8) -> Self {
+        Self { shift, state: None }
+    }
+
+    pub fn update(&mut self, mv: u16) -> u16 {
+        let sample = (mv as u32) << 8;
+        let state = match self.state {
+            None => sample,
+            Some(state) if sample >= state => state + ((sample - state) >> self.shift),
+            Some(state) => state - ((state - sample) >> self.shift),
+        };
+        self.state = Some(state);
+        (state >> 8) as u16
+    }
+}
+
+/// When a subscribed client gets a new battery level
+#[derive(Debug, Clone, Copy)]
+pub struct NotifyPolicy {
+    /// Least time between two notifications to the same client, in ms
+    pub min_interval: u64,
+    /// Least change in percent since the last notification
+    pub hysteresis: u8,
+}
+
+impl NotifyPolicy {
+    /// `last` is the level last sent to the client and when, `None` if nothing has been sent yet
+    pub fn should_notify(&self, last: Option<(u8, u64)>, lvl: u8, now: u64) -> bool {
+        let Some((last_lvl, at)) = last else {
+            return true;
+        };
+        lvl.abs_diff(last_lvl) >= self.hysteresis.max(1)
+            && now.saturating_sub(at) >= self.min_interval
+    }
+}
@@ -0,0 +1,139 @@
+//! Run on the host: `cargo test -p ble-battery --target x86_64-unknown-linux-gnu`
+
+use ble_battery::*;
+
+#[test]
+fn samples_to_millivolts() {
+    assert_eq!(millivolts(0, Source::Vdd), 0);
+    assert_eq!(millivolts(4096, Source::Vdd), 3600);
+    assert_eq!(millivolts(2048, Source::Vdd), 1800);
+    assert_eq!(millivolts(2048, Source::VddhDiv5), 9000);
+    // noise around 0 reads below it
+    assert_eq!(millivolts(-3, Source::Vdd), 0);
+}
+
+#[test]
+fn curve_endpoints() {
+    for curve in [ALKALINE_2XAAA, NIMH_2XAAA] {
+        let (top_mv, top_pct) = curve.points()[0];
+        let (bottom_mv, bottom_pct) = curve.points()[curve.points().len() - 1];
+        assert_eq!(curve.percent(top_mv), top_pct);
+        assert_eq!(curve.percent(bottom_mv), bottom_pct);
+        // every point maps to its own percentage
+        for &(mv, pct) in curve.points() {
+            assert_eq!(curve.percent(mv), pct, "{} mV", mv);
+        }
+    }
+    assert_eq!(ALKALINE_2XAAA.percent(3000), 100);
+    assert_eq!(ALKALINE_2XAAA.percent(2000), 0);
+}
+
+#[test]
+fn interpolates_between_points() {
+    // halfway between (2900, 80) and (2800, 60)
+    assert_eq!(ALKALINE_2XAAA.percent(2850), 70);
+    // a quarter of the way up from (2300, 10) to (2500, 20)
+    assert_eq!(ALKALINE_2XAAA.percent(2350), 12);
+    // halfway between (2500, 70) and (2400, 40)
+    assert_eq!(NIMH_2XAAA.percent(2450), 55);
+    // never goes down as the voltage goes up
+    let mut last = 0;
+    for mv in 1900..3100 {
+        let pct = ALKALINE_2XAAA.percent(mv);
+        assert!(pct >= last, "{} mV", mv);
+        last = pct;
+    }
+}
+
+#[test]
+fn clamps_outside_the_curve() {
+    assert_eq!(ALKALINE_2XAAA.percent(3600), 100);
+    assert_eq!(ALKALINE_2XAAA.percent(1000), 0);
+    assert_eq!(ALKALINE_2XAAA.percent(0), 0);
+    assert_eq!(NIMH_2XAAA.percent(u16::MAX), 100);
+    assert_eq!(NIMH_2XAAA.percent(1999), 0);
+    assert_eq!(USB.percent(0), 100);
+    assert_eq!(USB.percent(3300), 100);
+    assert_eq!(Curve::new(&[]).unwrap().percent(3000), 0);
+}
+
+#[test]
+fn unsorted_curves_are_refused() {
+    // rising voltage
+    assert_eq!(Curve::new(&[(2000, 0), (3000, 100)]), Err(Error::Unsorted));
+    // percent going up as the voltage falls
+    assert_eq!(
+        Curve::new(&[(3000, 50), (2500, 80), (2000, 0)]),
+        Err(Error::Unsorted)
+    );
+    // flat stretches are fine
+    let curve = Curve::new(&[(3000, 100), (3000, 90), (2500, 90), (2000, 0)]).unwrap();
+    assert_eq!(curve.percent(3000), 100);
+    assert_eq!(curve.percent(2700), 90);
+    assert_eq!(curve.percent(2250), 45);
+}
+
+#[test]
+fn filter_starts_at_the_first_sample() {
+    let mut filter = Filter::new(3);
+    assert_eq!(filter.update(2900), 2900);
+}
+
+#[test]
+fn filter_converges() {
+    let mut filter = Filter::new(3);
+    filter.update(3000);
+    // an eighth of the way per sample
+    assert_eq!(filter.update(2200), 2900);
+    let mut out = 0;
+    for _ in 0..100 {
+        out = filter.update(2200);
+    }
+    assert!(out.abs_diff(2200) <= 1, "{}", out);
+    // and back up
+    for _ in 0..100 {
+        out = filter.update(2800);
+    }
+    assert!(out.abs_diff(2800) <= 1, "{}", out);
+    // without smoothing it follows every sample
+    let mut filter = Filter::new(0);
+    filter.update(3000);
+    assert_eq!(filter.update(2500), 2500);
+}
+
+const POLICY: NotifyPolicy = NotifyPolicy {
+    min_interval: 10_000,
+    hysteresis: 2,
+};
+
+#[test]
+fn first_level_is_always_sent() {
+    assert!(POLICY.should_notify(None, 50, 0));
+}
+
+#[test]
+fn hysteresis_holds_back_small_changes() {
+    let last = Some((50, 0));
+    assert!(!POLICY.should_notify(last, 50, 60_000));
+    assert!(!POLICY.should_notify(last, 51, 60_000));
+    assert!(!POLICY.should_notify(last, 49, 60_000));
+    assert!(POLICY.should_notify(last, 52, 60_000));
+    assert!(POLICY.should_notify(last, 48, 60_000));
+    // a hysteresis of 0 still leaves an unchanged level alone
+    let policy = NotifyPolicy {
+        hysteresis: 0,
+        ..POLICY
+    };
+    assert!(!policy.should_notify(last, 50, 60_000));
+    assert!(policy.should_notify(last, 51, 60_000));
+}
+
+#[test]
+fn min_interval_holds_back_quick_changes() {
+    let last = Some((50, 5_000));
+    assert!(!POLICY.should_notify(last, 40, 5_000));
+    assert!(!POLICY.should_notify(last, 40, 14_999));
+    assert!(POLICY.should_notify(last, 40, 15_000));
+    // a clock that went backwards is no reason to send
+    assert!(!POLICY.should_notify(last, 40, 0));
+}
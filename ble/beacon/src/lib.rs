@@ -0,0 +1,159 @@
+//! Beacon frames for ble-batt: iBeacon and Eddystone UID, URL and TLM.
+//!
+//! Every encoder returns the data part of one AD structure, the caller adds the AD
+//! length and type (and the flags structure) around it:
+//! - iBeacon: manufacturer specific data of [`APPLE`], see [`ibeacon`]
+//! - Eddystone: service data of [`EDDYSTONE`], listed in the complete 16 bit service
+//!   uuids as well, https://github.com/google/eddystone/blob/master/protocol-specification.md
+//!
+//! Pure code, host tests: `cargo test -p ble-beacon --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use heapless::Vec;
+
+/// Company identifier of Apple, the iBeacon frames go in its manufacturer data
+pub const APPLE: u16 = 0x004C;
+/// 16 bit service uuid of Eddystone
+pub const EDDYSTONE: u16 = 0xFEAA;
+
+/// Longest Eddystone frame, what fits in a legacy advertisement next to the flags
+/// and the service uuid
+pub const MAX_FRAME: usize = 20;
+/// Longest encoded url after the scheme byte
+pub const MAX_URL: usize = 17;
+
+pub mod frame_type {
+    pub const UID: u8 = 0x00;
+    pub const URL: u8 = 0x10;
+    pub const TLM: u8 = 0x20;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Not `http://` or `https://`
+    UnknownScheme,
+    /// A byte an Eddystone url can't carry, only printable ascii is allowed
+    InvalidUrl,
+    /// Longer than [`MAX_URL`] bytes after compression
+    UrlTooLong,
+}
+
+/// iBeacon manufacturer data after the company id: type, length, proximity uuid,
+/// major, minor (both big endian) and the RSSI measured at 1 m
+pub fn ibeacon(uuid: &[u8; 16], major: u16, minor: u16, measured_power: i8) -> [u8; 23] {
+    let mut out = [0; 23];
+    out[0] = 0x02;
+    out[1] = 0x15;
+    out[2..18].copy_from_slice(uuid);
+    out[18..20].copy_from_slice(&major.to_be_bytes());
+    out[20..22].copy_from_slice(&minor.to_be_bytes());
+    out[22] = measured_power as u8;
+    out
+}
+
+/// Eddystone-UID: a 10 byte namespace and a 6 byte instance, `tx_power` is the
+/// RSSI at 0 m in dBm
+pub fn eddystone_uid(namespace: &[u8; 10], instance: &[u8; 6], tx_power: i8) -> [u8; 20] {
+    let mut out = [0; 20];
+    out[0] = frame_type::UID;
+    out[1] = tx_power as u8;
+    out[2..12].copy_from_slice(namespace);
+    out[12..18].copy_from_slice(instance);
+    // the last two bytes are reserved and zero
+    out
+}
+
+/// Scheme prefixes, longest first so `https://www.` wins over `https://`
+const SCHEMES: [(u8, &str); 4] = [
+    (0x00, "http://www."),
+    (0x01, "https://www."),
+    (0x02, "http://"),
+    (0x03, "https://"),
+];
+
+/// Text expansions, the ones with a slash first so they win
+const EXPANSIONS: [(u8, &str); 14] = [
+    (0x00, ".com/"),
+    (0x01, ".org/"),
+    (0x02, ".edu/"),
+    (0x03, ".net/"),
+    (0x04, ".info/"),
+    (0x05, ".biz/"),
+    (0x06, ".gov/"),
+    (0x07, ".com"),
+    (0x08, ".org"),
+    (0x09, ".edu"),
+    (0x0A, ".net"),
+    (0x0B, ".info"),
+    (0x0C, ".biz"),
+    (0x0D, ".gov"),
+];
+
+/// Eddystone-URL with the scheme and common domain parts compressed to one byte each
+pub fn eddystone_url(url: &str, tx_power: i8) -> Result<Vec<u8, MAX_FRAME>, Error> {
+    let (scheme, mut rest) = SCHEMES
+        .iter()
+        .filter_map(|&(code, prefix)| url.strip_prefix(prefix).map(|rest| (code, rest)))
+        .min_by_key(|(_, rest)| rest.len())
+        .ok_or(Error::UnknownScheme)?;
+
+    let mut out = Vec::new();
+    // three bytes always fit
+    out.extend_from_slice(&[frame_type::URL, tx_power as u8, scheme])
+        .unwrap();
+    while !rest.is_empty() {
+        let expansion = EXPANSIONS
+            .iter()
+            .find_map(|&(code, text)| rest.strip_prefix(text).map(|tail| (code, tail)));
+        let byte = match expansion {
+            Some((code, tail)) => {
+                rest = tail;
+                code
+            }
+            None => {
+                let byte = rest.as_bytes()[0];
+                if !(0x21..0x7F).contains(&byte) {
+                    return Err(Error::InvalidUrl);
+                }
+                rest = &rest[1..];
+                byte
+            }
+        };
+        out.push(byte).map_err(|_| Error::UrlTooLong)?;
+    }
+    Ok(out)
+}
+
+/// Readings for an Eddystone-TLM frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Telemetry {
+    /// Battery voltage in mV, `None` if not known
+    pub battery_mv: Option<u16>,
+    /// Temperature in 0.01 degrees Celsius, `None` if not known
+    pub temperature: Option<i16>,
+    /// Advertising frames sent since power up
+    pub adv_count: u32,
+    /// Time since power up in 0.1 s
+    pub uptime: u32,
+}
+
+/// Unencrypted Eddystone-TLM, all big endian, the temperature as 8.8 fixed point
+pub fn eddystone_tlm(telemetry: &Telemetry) -> [u8; 14] {
+    let mut out = [0; 14];
+    out[0] = frame_type::TLM;
+    // version 0, unencrypted
+    out[1] = 0x00;
+    out[2..4].copy_from_slice(&telemetry.battery_mv.unwrap_or(0).to_be_bytes());
+    let temperature = match telemetry.temperature {
+        // 1/100 to 1/256 degrees, -128 to 127.99 fits
+        Some(centi) => (centi as i32 * 256 / 100) as i16,
+        // -128 degrees means not supported
+        None => i16::MIN,
+    };
+    out[4..6].copy_from_slice(&temperature.to_be_bytes());
+    out[6..10].copy_from_slice(&telemetry.adv_count.to_be_bytes());
+    out[10..14].copy_from_slice(&telemetry.uptime.to_be_bytes());
+    out
+}
@@ -0,0 +1,99 @@
+//! Run on the host: `cargo test -p ble-beacon --target x86_64-unknown-linux-gnu`
+
+use ble_beacon::*;
+
+const UUID: [u8; 16] = [
+    0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96,
+    0xE0,
+];
+
+#[test]
+fn ibeacon_layout() {
+    let frame = ibeacon(&UUID, 0x0102, 0xA0B0, -59);
+    assert_eq!(frame[..2], [0x02, 0x15]);
+    assert_eq!(frame[2..18], UUID);
+    assert_eq!(frame[18..20], [0x01, 0x02]);
+    assert_eq!(frame[20..22], [0xA0, 0xB0]);
+    assert_eq!(frame[22], 0xC5);
+}
+
+#[test]
+fn uid_layout() {
+    let namespace = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+    let instance = [0xA, 0xB, 0xC, 0xD, 0xE, 0xF];
+    let frame = eddystone_uid(&namespace, &instance, -20);
+    assert_eq!(
+        frame,
+        [0x00, 0xEC, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0xA, 0xB, 0xC, 0xD, 0xE, 0xF, 0, 0]
+    );
+}
+
+#[test]
+fn url_compressed() {
+    let frame = eddystone_url("https://www.google.com/", -20).unwrap();
+    assert_eq!(
+        frame[..],
+        [0x10, 0xEC, 0x01, b'g', b'o', b'o', b'g', b'l', b'e', 0x00]
+    );
+    let frame = eddystone_url("http://microbit.org", 0).unwrap();
+    assert_eq!(frame[..3], [0x10, 0x00, 0x02]);
+    assert_eq!(frame[3..], *b"microbit\x08");
+    // an expansion in the middle, the one with the slash wins
+    let frame = eddystone_url("https://a.com/b.org", 0).unwrap();
+    assert_eq!(frame[2..], [0x03, b'a', 0x00, b'b', 0x08]);
+}
+
+#[test]
+fn url_errors() {
+    assert_eq!(
+        eddystone_url("ftp://example.com", 0),
+        Err(Error::UnknownScheme)
+    );
+    assert_eq!(
+        eddystone_url("https://a b.com", 0),
+        Err(Error::InvalidUrl)
+    );
+    assert_eq!(
+        eddystone_url("https://élan.com", 0),
+        Err(Error::InvalidUrl)
+    );
+    // 17 bytes after the scheme is the most that fits
+    assert_eq!(
+        eddystone_url("https://abcdefghijklmnopq", 0).map(|f| f.len()),
+        Ok(MAX_FRAME)
+    );
+    assert_eq!(
+        eddystone_url("https://abcdefghijklmnopqr", 0),
+        Err(Error::UrlTooLong)
+    );
+}
+
+#[test]
+fn tlm_layout() {
+    let frame = eddystone_tlm(&Telemetry {
+        battery_mv: Some(2950),
+        temperature: Some(2325),
+        adv_count: 0x01020304,
+        uptime: 0x0A0B0C0D,
+    });
+    assert_eq!(
+        frame,
+        [
+            0x20, 0x00, 0x0B, 0x86, // 2950 mV
+            0x17, 0x40, // 23.25 degrees in 8.8
+            0x01, 0x02, 0x03, 0x04, 0x0A, 0x0B, 0x0C, 0x0D,
+        ]
+    );
+}
+
+#[test]
+fn tlm_unknown_and_negative() {
+    let frame = eddystone_tlm(&Telemetry::default());
+    assert_eq!(frame[2..6], [0x00, 0x00, 0x80, 0x00]);
+    let frame = eddystone_tlm(&Telemetry {
+        temperature: Some(-150),
+        ..Default::default()
+    });
+    // -1.5 degrees
+    assert_eq!(frame[4..6], (-384i16).to_be_bytes());
+}
@@ -0,0 +1,158 @@
+//! Bond records of ble-batt and their layout in the bond flash page.
+//!
+//! Every record has a fixed size so the page is just an array of records, an
+//! erased (0xFF) or unknown record is a free slot. No softdevice types in here, the
+//! firmware converts its keys to and from [`Bond`].
+//!
+//! Pure code, host tests: `cargo test -p ble-bonds --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use heapless::Vec;
+
+/// Bonds kept, the least recently used one is dropped for a new peer
+pub const MAX_BONDS: usize = 8;
+/// Room for the CCCD values (system attributes) of one peer
+pub const MAX_SYS_ATTRS: usize = 96;
+/// Size of one record in flash, a multiple of the flash word
+pub const RECORD_LEN: usize = 160;
+/// Bytes of the page used by the table
+pub const TABLE_LEN: usize = MAX_BONDS * RECORD_LEN;
+
+/// Start of a valid record, also the layout version
+const MAGIC: [u8; 4] = *b"BND1";
+const HEADER_LEN: usize = 56;
+
+/// Keys and CCCD values of a bonded peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bond {
+    /// Master identification of the LTK, all zero for LE Secure Connections
+    pub ediv: u16,
+    pub rand: [u8; 8],
+    pub ltk: [u8; 16],
+    /// Flags of the encryption info (LESC, authenticated, key length)
+    pub ltk_flags: u8,
+    /// Identity resolving key, all zero when the peer did not distribute one
+    pub irk: [u8; 16],
+    /// Type of the identity address, public or random static
+    pub addr_type: u8,
+    pub addr: [u8; 6],
+    /// System attributes as returned by the softdevice, empty until the first disconnect
+    pub sys_attrs: Vec<u8, MAX_SYS_ATTRS>,
+}
+
+impl Bond {
+    /// Little endian layout: magic, ediv, rand, ltk, ltk flags, address type,
+    /// address, irk, sys attrs length (u16), sys attrs, padding with 0xFF
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut out = [0xFF; RECORD_LEN];
+        out[0..4].copy_from_slice(&MAGIC);
+        out[4..6].copy_from_slice(&self.ediv.to_le_bytes());
+        out[6..14].copy_from_slice(&self.rand);
+        out[14..30].copy_from_slice(&self.ltk);
+        out[30] = self.ltk_flags;
+        out[31] = self.addr_type;
+        out[32..38].copy_from_slice(&self.addr);
+        out[38..54].copy_from_slice(&self.irk);
+        out[54..56].copy_from_slice(&(self.sys_attrs.len() as u16).to_le_bytes());
+        out[HEADER_LEN..HEADER_LEN + self.sys_attrs.len()].copy_from_slice(&self.sys_attrs);
+        out
+    }
+
+    /// `None` for a free slot or a record that doesn't make sense
+    pub fn decode(record: &[u8]) -> Option<Self> {
+        if record.len() < RECORD_LEN || record[0..4] != MAGIC {
+            return None;
+        }
+        let sys_attrs_len = u16::from_le_bytes([record[54], record[55]]) as usize;
+        if sys_attrs_len > MAX_SYS_ATTRS {
+            return None;
+        }
+        Some(Self {
+            ediv: u16::from_le_bytes([record[4], record[5]]),
+            rand: record[6..14].try_into().unwrap(),
+            ltk: record[14..30].try_into().unwrap(),
+            ltk_flags: record[30],
+            addr_type: record[31],
+            addr: record[32..38].try_into().unwrap(),
+            irk: record[38..54].try_into().unwrap(),
+            sys_attrs: Vec::from_slice(&record[HEADER_LEN..HEADER_LEN + sys_attrs_len]).ok()?,
+        })
+    }
+
+    /// The same peer, judged by its identity address
+    pub fn same_peer(&self, other: &Bond) -> bool {
+        self.addr_type == other.addr_type && self.addr == other.addr
+    }
+}
+
+/// All bonds, least recently used first
+#[derive(Debug, Default)]
+pub struct BondTable {
+    bonds: Vec<Bond, MAX_BONDS>,
+}
+
+impl BondTable {
+    pub const fn new() -> Self {
+        Self { bonds: Vec::new() }
+    }
+
+    /// Reads the records of a flash page, skipping the free slots
+    pub fn decode(page: &[u8]) -> Self {
+        let mut table = Self::new();
+        for record in page.chunks_exact(RECORD_LEN).take(MAX_BONDS) {
+            if let Some(bond) = Bond::decode(record) {
+                // at most MAX_BONDS records fit
+                let _ = table.bonds.push(bond);
+            }
+        }
+        table
+    }
+
+    pub fn encode(&self) -> [u8; TABLE_LEN] {
+        let mut page = [0xFF; TABLE_LEN];
+        for (record, bond) in page.chunks_exact_mut(RECORD_LEN).zip(&self.bonds) {
+            record.copy_from_slice(&bond.encode());
+        }
+        page
+    }
+
+    /// Adds or replaces the bond of a peer as the most recently used one,
+    /// dropping the least recently used bond when the table is full
+    pub fn insert(&mut self, bond: Bond) {
+        if let Some(idx) = self.bonds.iter().position(|b| b.same_peer(&bond)) {
+            self.bonds.remove(idx);
+        } else if self.bonds.is_full() {
+            self.bonds.remove(0);
+        }
+        // there is always room after the removals above
+        let _ = self.bonds.push(bond);
+    }
+
+    /// Marks bond `idx` as the most recently used and returns its new index
+    pub fn touch(&mut self, idx: usize) -> usize {
+        let bond = self.bonds.remove(idx);
+        let _ = self.bonds.push(bond);
+        self.bonds.len() - 1
+    }
+
+    pub fn position(&self, f: impl Fn(&Bond) -> bool) -> Option<usize> {
+        self.bonds.iter().position(f)
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Bond> {
+        self.bonds.get(idx)
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut Bond> {
+        self.bonds.get_mut(idx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bonds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bonds.is_empty()
+    }
+}
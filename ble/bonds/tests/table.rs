@@ -0,0 +1,144 @@
+//! Run on the host: `cargo test -p ble-bonds --target x86_64-unknown-linux-gnu`
+
+use ble_bonds::*;
+
+/// A bond of the peer with address `n`, keys made up from `n` too
+fn bond(n: u8) -> Bond {
+    Bond {
+        ediv: 0x1200 + n as u16,
+        rand: [n; 8],
+        ltk: [0xA0u8.wrapping_add(n); 16],
+        ltk_flags: 0x02,
+        irk: [0x50u8.wrapping_add(n); 16],
+        addr_type: 1,
+        addr: [n, 0, 0, 0, 0, 0xC0],
+        sys_attrs: heapless::Vec::from_slice(&[n, 0x01, 0x00]).unwrap(),
+    }
+}
+
+fn addrs(table: &BondTable) -> Vec<u8> {
+    (0..table.len())
+        .map(|idx| table.get(idx).unwrap().addr[0])
+        .collect()
+}
+
+#[test]
+fn record_round_trips() {
+    for bond in [bond(1), bond(0xFE)] {
+        assert_eq!(Bond::decode(&bond.encode()), Some(bond));
+    }
+    // the most CCCD values a record holds
+    let mut full = bond(2);
+    full.sys_attrs = heapless::Vec::from_slice(&[0x5A; MAX_SYS_ATTRS]).unwrap();
+    assert_eq!(Bond::decode(&full.encode()), Some(full));
+    // none yet, before the first disconnect
+    let mut empty = bond(3);
+    empty.sys_attrs.clear();
+    assert_eq!(Bond::decode(&empty.encode()), Some(empty));
+}
+
+#[test]
+fn record_layout() {
+    let record = bond(1).encode();
+    assert_eq!(record.len(), RECORD_LEN);
+    assert_eq!(&record[0..4], b"BND1");
+    // little endian ediv, then rand and ltk
+    assert_eq!(record[4..6], [0x01, 0x12]);
+    assert_eq!(record[6..14], [1; 8]);
+    assert_eq!(record[14..30], [0xA1; 16]);
+    assert_eq!(record[30], 0x02);
+    assert_eq!(record[31], 1);
+    assert_eq!(record[32..38], [1, 0, 0, 0, 0, 0xC0]);
+    assert_eq!(record[38..54], [0x51; 16]);
+    assert_eq!(record[54..56], [3, 0]);
+    assert_eq!(record[56..59], [1, 0x01, 0x00]);
+    // the rest is left erased
+    assert!(record[59..].iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn records_that_are_not_bonds() {
+    // erased flash
+    assert_eq!(Bond::decode(&[0xFF; RECORD_LEN]), None);
+    assert_eq!(Bond::decode(&[0x00; RECORD_LEN]), None);
+    // an older layout
+    let mut record = bond(1).encode();
+    record[3] = b'0';
+    assert_eq!(Bond::decode(&record), None);
+    // more CCCD values than a record holds
+    let mut record = bond(1).encode();
+    record[54..56].copy_from_slice(&(MAX_SYS_ATTRS as u16 + 1).to_le_bytes());
+    assert_eq!(Bond::decode(&record), None);
+    // cut short
+    assert_eq!(Bond::decode(&bond(1).encode()[..RECORD_LEN - 1]), None);
+}
+
+#[test]
+fn page_round_trips() {
+    assert!(BondTable::decode(&[0xFF; TABLE_LEN]).is_empty());
+    let mut table = BondTable::new();
+    for n in 1..=3 {
+        table.insert(bond(n));
+    }
+    let page = table.encode();
+    assert!(page[3 * RECORD_LEN..].iter().all(|&b| b == 0xFF));
+    let read = BondTable::decode(&page);
+    assert_eq!(addrs(&read), [1, 2, 3]);
+    assert_eq!(read.get(1), Some(&bond(2)));
+}
+
+#[test]
+fn free_slots_are_skipped() {
+    let mut page = [0xFF; TABLE_LEN];
+    page[RECORD_LEN..2 * RECORD_LEN].copy_from_slice(&bond(4).encode());
+    page[3 * RECORD_LEN..4 * RECORD_LEN].copy_from_slice(&bond(5).encode());
+    // a half written record
+    page[5 * RECORD_LEN..5 * RECORD_LEN + 10].copy_from_slice(&[0; 10]);
+    assert_eq!(addrs(&BondTable::decode(&page)), [4, 5]);
+}
+
+#[test]
+fn least_recently_used_is_dropped() {
+    let mut table = BondTable::new();
+    for n in 0..MAX_BONDS as u8 {
+        table.insert(bond(n));
+    }
+    assert_eq!(table.len(), MAX_BONDS);
+    // 0 is used again, 1 is now the oldest
+    let idx = table.touch(0);
+    assert_eq!(idx, MAX_BONDS - 1);
+    assert_eq!(table.get(idx), Some(&bond(0)));
+    table.insert(bond(100));
+    assert_eq!(table.len(), MAX_BONDS);
+    assert_eq!(table.position(|b| b.addr[0] == 1), None);
+    assert_eq!(table.position(|b| b.addr[0] == 0), Some(MAX_BONDS - 2));
+    assert_eq!(table.position(|b| b.addr[0] == 100), Some(MAX_BONDS - 1));
+}
+
+#[test]
+fn a_new_pairing_replaces_the_bond_of_the_peer() {
+    let mut table = BondTable::new();
+    table.insert(bond(1));
+    table.insert(bond(2));
+    let mut again = bond(1);
+    again.ltk = [0x77; 16];
+    table.insert(again.clone());
+    assert_eq!(addrs(&table), [2, 1]);
+    assert_eq!(table.get(1), Some(&again));
+    // same address but of another type is another peer
+    let mut public = bond(2);
+    public.addr_type = 0;
+    assert!(!public.same_peer(&bond(2)));
+    table.insert(public);
+    assert_eq!(table.len(), 3);
+}
+
+#[test]
+fn cccd_values_change_in_place() {
+    let mut table = BondTable::new();
+    table.insert(bond(1));
+    table.get_mut(0).unwrap().sys_attrs.clear();
+    let read = BondTable::decode(&table.encode());
+    assert!(read.get(0).unwrap().sys_attrs.is_empty());
+    assert_eq!(read.get(1), None);
+}
@@ -0,0 +1,29 @@
+//! This build script copies the flash layout of the softdevice from the crate root
+//! into a directory where the linker can always find it at build time, as `memory.x`,
+//! and asks Cargo to re-run it whenever a layout changes. No `memory.x` may sit in
+//! the crate root, the linker looks in the working directory before the search path.
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    // the app's slots move up with the bigger S140
+    let memory: &[u8] = if env::var_os("CARGO_FEATURE_S140").is_some() {
+        include_bytes!("memory-s140.x")
+    } else {
+        include_bytes!("memory-s113.x")
+    };
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(memory)
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory-s113.x");
+    println!("cargo:rerun-if-changed=memory-s140.x");
+
+    println!("cargo:rustc-link-arg-bins=--nmagic");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+}
@@ -0,0 +1,28 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* same partitions as memory-s140.x at the workspace root */
+  FLASH                             : ORIGIN = 0x00077000, LENGTH = 24K
+  BOOTLOADER_STATE                  : ORIGIN = 0x0007E000, LENGTH = 4K
+  ACTIVE                            : ORIGIN = 0x00027000, LENGTH = 156K
+  DFU                               : ORIGIN = 0x0004E000, LENGTH = 160K
+  /* the MBR keeps the first 8 bytes */
+  RAM                               : ORIGIN = 0x20000008, LENGTH = 0x1FFF8
+  /* UICR.NRFFW[0], where the MBR looks for a bootloader */
+  UICR_BOOTLOADER_ADDR              : ORIGIN = 0x10001014, LENGTH = 4
+}
+
+SECTIONS
+{
+  .uicr_bootloader_addr :
+  {
+    LONG(ORIGIN(FLASH))
+  } > UICR_BOOTLOADER_ADDR
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE);
+__bootloader_active_start = ORIGIN(ACTIVE);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE);
+__bootloader_dfu_start = ORIGIN(DFU);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU);
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+//! Bootloader for ble-batt, swaps in an image the app received over BLE.
+//!
+//! The MBR starts us through UICR.NRFFW[0] (set from memory-*.x). We swap the DFU and
+//! active slots when the app marked an update, or swap back when an updated image
+//! was reset before it marked itself booted. Then we jump to the softdevice, which
+//! starts the app in the active slot.
+//!
+//! The watchdog is started here and keeps running, the app has to feed it. A new image
+//! that hangs is reset by it and rolled back.
+//!
+//! Built on its own, from this directory: `cargo build --release`.
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use embassy_boot_nrf::{BootLoader, BootLoaderConfig, WatchdogFlash};
+use embassy_nrf::nvmc::Nvmc;
+use embassy_nrf::wdt;
+use embassy_sync::blocking_mutex::Mutex;
+
+/// Must give the app time to start its own watchdog task, in 32768 Hz ticks
+const WATCHDOG_TICKS: u32 = 32768 * 8;
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_nrf::init(Default::default());
+
+    let mut wdt_config = wdt::Config::default();
+    wdt_config.timeout_ticks = WATCHDOG_TICKS;
+    wdt_config.run_during_sleep = true;
+    wdt_config.run_during_debug_halt = false;
+
+    // the softdevice isn't running yet, the flash is ours
+    let flash = WatchdogFlash::start(Nvmc::new(p.NVMC), p.WDT, wdt_config);
+    let flash = Mutex::new(RefCell::new(flash));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash);
+    let active_offset = config.active.offset();
+    let bl: BootLoader = BootLoader::prepare(config);
+
+    unsafe { bl.load(active_offset) }
+}
+
+#[no_mangle]
+#[cfg_attr(target_os = "none", link_section = ".HardFault.user")]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::asm::udf();
+}
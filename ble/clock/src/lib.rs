@@ -0,0 +1,233 @@
+//! Wall clock for ble-batt: the Current Time characteristic (0x2A2B) of the Current Time
+//! Service and the calendar arithmetic to keep the time as milliseconds.
+//!
+//! [`CurrentTime::decode`] reads the value a phone serves, Current Time Service 1.1,
+//! section 3.1. The firmware turns it into milliseconds since 1970-01-01 with
+//! [`DateTime::to_unix_millis`] and back with [`DateTime::from_unix_millis`]. The phone
+//! sends its local time and so does everything here, there are no time zones. Dates are
+//! proleptic Gregorian, the day counting follows Howard Hinnant's `days_from_civil`.
+//!
+//! Pure code, host tests: `cargo test -p ble-clock --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use core::fmt;
+
+/// Length of a Current Time value: Exact Time 256 and the adjust reason
+pub const LEN: usize = 10;
+
+const MILLIS_PER_DAY: u64 = 86_400_000;
+/// Days from 0000-03-01 to 1970-01-01
+const EPOCH_DAYS: i64 = 719_468;
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// Bits of the adjust reason, why the phone's time changed
+pub mod adjust_reason {
+    pub const MANUAL: u8 = 0x01;
+    pub const EXTERNAL_REFERENCE: u8 = 0x02;
+    pub const TIME_ZONE: u8 = 0x04;
+    pub const DST: u8 = 0x08;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Fewer than [`LEN`] bytes
+    TooShort,
+    /// The phone doesn't know the date, a 0 year, month or day
+    Unknown,
+    /// A field past what the spec allows
+    OutOfRange,
+}
+
+/// Day of the week as CTS numbers it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Weekday {
+    Monday = 1,
+    Tuesday = 2,
+    Wednesday = 3,
+    Thursday = 4,
+    Friday = 5,
+    Saturday = 6,
+    Sunday = 7,
+}
+
+impl Weekday {
+    /// 1 for Monday up to 7 for Sunday
+    pub fn from_number(n: u8) -> Option<Self> {
+        Some(match n {
+            1 => Weekday::Monday,
+            2 => Weekday::Tuesday,
+            3 => Weekday::Wednesday,
+            4 => Weekday::Thursday,
+            5 => Weekday::Friday,
+            6 => Weekday::Saturday,
+            7 => Weekday::Sunday,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to the length of the month
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub millis: u16,
+}
+
+/// A decoded Current Time value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CurrentTime {
+    pub time: DateTime,
+    /// `None` when the phone leaves it out
+    pub weekday: Option<Weekday>,
+    /// [`adjust_reason`] bits
+    pub adjust_reason: u8,
+}
+
+impl CurrentTime {
+    /// Year as u16, month, day, hours, minutes, seconds, day of week, fractions of 1/256 s
+    /// and the adjust reason, all little endian. Bytes after the first [`LEN`] are left
+    /// for later versions of the spec.
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let Some(&[y0, y1, month, day, hours, minutes, seconds, weekday, fractions, reason]) =
+            data.get(..LEN)
+        else {
+            return Err(Error::TooShort);
+        };
+        let year = u16::from_le_bytes([y0, y1]);
+        if year == 0 || month == 0 || day == 0 {
+            return Err(Error::Unknown);
+        }
+        let time = DateTime {
+            year,
+            month,
+            day,
+            hours,
+            minutes,
+            seconds,
+            millis: (fractions as u32 * 1000 / 256) as u16,
+        };
+        // the spec starts at the Gregorian calendar
+        if !(1582..=9999).contains(&year) || !time.is_valid() {
+            return Err(Error::OutOfRange);
+        }
+        let weekday = match weekday {
+            0 => None,
+            n => Some(Weekday::from_number(n).ok_or(Error::OutOfRange)?),
+        };
+        Ok(Self {
+            time,
+            weekday,
+            adjust_reason: reason,
+        })
+    }
+}
+
+impl DateTime {
+    /// Every field within its range
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hours < 24
+            && self.minutes < 60
+            && self.seconds < 60
+            && self.millis < 1000
+    }
+
+    /// Milliseconds since 1970-01-01 00:00, `None` before that
+    pub fn to_unix_millis(&self) -> Option<u64> {
+        let days = u64::try_from(days_from_civil(self.year, self.month, self.day)).ok()?;
+        let seconds = self.hours as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64;
+        Some(days * MILLIS_PER_DAY + seconds * 1000 + self.millis as u64)
+    }
+
+    pub fn from_unix_millis(millis: u64) -> Self {
+        let (year, month, day) = civil_from_days((millis / MILLIS_PER_DAY) as i64);
+        let rest = millis % MILLIS_PER_DAY;
+        let seconds = rest / 1000;
+        Self {
+            year,
+            month,
+            day,
+            hours: (seconds / 3600) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+            seconds: (seconds % 60) as u8,
+            millis: (rest % 1000) as u16,
+        }
+    }
+
+    pub fn weekday(&self) -> Weekday {
+        // 1970-01-01 was a Thursday
+        let days = days_from_civil(self.year, self.month, self.day);
+        let n = (days + Weekday::Thursday as i64 - 1).rem_euclid(7) + 1;
+        Weekday::from_number(n as u8).unwrap()
+    }
+}
+
+/// `2024-02-29 13:05:09`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+/// 0 for a month out of range
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+/// Days from 1970-01-01 to a valid date, negative before it
+pub fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    // years start in March, the leap day is the last day of the year before
+    let year = year as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * DAYS_PER_ERA + day_of_era - EPOCH_DAYS
+}
+
+/// Year, month and day `days` after 1970-01-01, for days from 0000-03-01 to year 65535
+pub fn civil_from_days(days: i64) -> (u16, u8, u8) {
+    let days = days + EPOCH_DAYS;
+    let era = days.div_euclid(DAYS_PER_ERA);
+    let day_of_era = days - era * DAYS_PER_ERA;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as u16, month as u8, day as u8)
+}
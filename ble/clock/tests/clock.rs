@@ -0,0 +1,136 @@
+//! Run on the host: `cargo test -p ble-clock --target x86_64-unknown-linux-gnu`
+
+use ble_clock::*;
+
+fn date(year: u16, month: u8, day: u8, hours: u8, minutes: u8, seconds: u8) -> DateTime {
+    DateTime {
+        year,
+        month,
+        day,
+        hours,
+        minutes,
+        seconds,
+        millis: 0,
+    }
+}
+
+#[test]
+fn decodes_current_time() {
+    // 2024-02-29 13:05:09.5, a Thursday, set by hand
+    let data = [0xE8, 0x07, 2, 29, 13, 5, 9, 4, 128, adjust_reason::MANUAL];
+    let time = CurrentTime::decode(&data).unwrap();
+    assert_eq!(
+        time.time,
+        DateTime {
+            millis: 500,
+            ..date(2024, 2, 29, 13, 5, 9)
+        }
+    );
+    assert_eq!(time.weekday, Some(Weekday::Thursday));
+    assert_eq!(time.adjust_reason, adjust_reason::MANUAL);
+    // unknown day of week, and a longer value from a newer spec
+    let mut longer = [0u8; 12];
+    longer[..LEN].copy_from_slice(&[0xE8, 0x07, 2, 29, 13, 5, 9, 0, 0, 0]);
+    let time = CurrentTime::decode(&longer).unwrap();
+    assert_eq!(time.weekday, None);
+    assert_eq!(time.time.millis, 0);
+}
+
+#[test]
+fn rejects_bad_values() {
+    let ok = [0xE8, 0x07, 2, 29, 13, 5, 9, 4, 0, 0];
+    assert_eq!(CurrentTime::decode(&ok[..9]), Err(Error::TooShort));
+    assert_eq!(CurrentTime::decode(&[]), Err(Error::TooShort));
+
+    let with = |i: usize, value: u8| {
+        let mut data = ok;
+        data[i] = value;
+        CurrentTime::decode(&data)
+    };
+    // unknown year, month or day
+    assert_eq!(
+        CurrentTime::decode(&[0, 0, 2, 29, 13, 5, 9, 4, 0, 0]),
+        Err(Error::Unknown)
+    );
+    assert_eq!(with(2, 0), Err(Error::Unknown));
+    assert_eq!(with(3, 0), Err(Error::Unknown));
+    // no 13th month, 2023 is no leap year, no 24:00, 60 s or 8th weekday
+    assert_eq!(with(2, 13), Err(Error::OutOfRange));
+    assert_eq!(with(0, 0xE7), Err(Error::OutOfRange));
+    assert_eq!(with(4, 24), Err(Error::OutOfRange));
+    assert_eq!(with(6, 60), Err(Error::OutOfRange));
+    assert_eq!(with(7, 8), Err(Error::OutOfRange));
+    // before the Gregorian calendar
+    assert_eq!(
+        CurrentTime::decode(&[0x2D, 0x06, 1, 1, 0, 0, 0, 0, 0, 0]),
+        Err(Error::OutOfRange)
+    );
+}
+
+#[test]
+fn leap_years_and_month_lengths() {
+    assert!(is_leap_year(2024));
+    assert!(is_leap_year(2000));
+    assert!(!is_leap_year(1900));
+    assert!(!is_leap_year(2023));
+    assert_eq!(days_in_month(2024, 2), 29);
+    assert_eq!(days_in_month(2100, 2), 28);
+    assert_eq!(days_in_month(2024, 4), 30);
+    assert_eq!(days_in_month(2024, 12), 31);
+    assert_eq!(days_in_month(2024, 13), 0);
+}
+
+#[test]
+fn counts_days_from_the_epoch() {
+    assert_eq!(days_from_civil(1970, 1, 1), 0);
+    assert_eq!(days_from_civil(1969, 12, 31), -1);
+    assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+    assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    // every day there and back, over several leap centuries
+    for days in days_from_civil(1582, 10, 15)..days_from_civil(2401, 3, 1) {
+        let (year, month, day) = civil_from_days(days);
+        assert!(day >= 1 && day <= days_in_month(year, month));
+        assert_eq!(days_from_civil(year, month, day), days);
+    }
+}
+
+#[test]
+fn unix_millis_round_trip() {
+    assert_eq!(date(1970, 1, 1, 0, 0, 0).to_unix_millis(), Some(0));
+    assert_eq!(date(1969, 12, 31, 23, 59, 59).to_unix_millis(), None);
+    // 2024-02-29 13:05:09 UTC
+    let time = DateTime {
+        millis: 500,
+        ..date(2024, 2, 29, 13, 5, 9)
+    };
+    assert_eq!(time.to_unix_millis(), Some(1_709_211_909_500));
+    assert_eq!(DateTime::from_unix_millis(1_709_211_909_500), time);
+    // a year and a bit of uptime later
+    let later = DateTime::from_unix_millis(1_709_211_909_500 + 366 * 86_400_000 + 11 * 3_600_000);
+    assert_eq!(
+        later,
+        DateTime {
+            millis: 500,
+            ..date(2025, 3, 2, 0, 5, 9)
+        }
+    );
+}
+
+#[test]
+fn weekdays() {
+    assert_eq!(date(1970, 1, 1, 0, 0, 0).weekday(), Weekday::Thursday);
+    assert_eq!(date(1969, 12, 28, 0, 0, 0).weekday(), Weekday::Sunday);
+    assert_eq!(date(2024, 2, 29, 0, 0, 0).weekday(), Weekday::Thursday);
+    assert_eq!(date(2000, 1, 3, 0, 0, 0).weekday(), Weekday::Monday);
+}
+
+#[test]
+fn displays_the_date() {
+    let mut text = String::new();
+    use std::fmt::Write;
+    write!(text, "{}", date(2024, 2, 9, 3, 5, 0)).unwrap();
+    assert_eq!(text, "2024-02-09 03:05:00");
+}
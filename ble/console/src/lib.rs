@@ -0,0 +1,121 @@
+//! Line based command console for ble-batt, fed with whatever bytes the client writes to
+//! the NUS rx characteristic. [`LineBuffer`] splits the writes into lines, [`parse`] turns
+//! a line into a [`Command`]. Running the commands is up to the firmware, see `nus.rs`.
+//!
+//! Pure code, host tests: `cargo test -p ble-console --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use heapless::Vec;
+
+/// Longest line that is kept, longer lines are thrown away
+pub const MAX_LINE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    Help,
+    /// Current battery level
+    Batt,
+    /// Firmware and connection info
+    Info,
+    /// Local time, once a phone has set the clock
+    Time,
+    /// Set the device name
+    Name(&'a str),
+    /// List the devices around, S140 builds only
+    Scan,
+    /// Connect to a device of the last scan by its index, S140 builds only
+    Connect(usize),
+    Disconnect,
+    /// Turn the proximity radar on or off, S140 builds only
+    Radar(bool),
+    Reboot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    Empty,
+    Unknown,
+    MissingArgument,
+    /// The command does not take arguments
+    UnexpectedArgument,
+    InvalidArgument,
+    NotUtf8,
+    /// The line did not fit in [`MAX_LINE`]
+    TooLong,
+}
+
+pub const HELP: &str =
+    "commands: help, batt, info, time, name <name>, scan, connect <n>, disconnect, radar on|off, reboot";
+
+pub fn parse(line: &[u8]) -> Result<Command<'_>, ParseError> {
+    let line = core::str::from_utf8(line).map_err(|_| ParseError::NotUtf8)?;
+    let line = line.trim();
+    let (cmd, arg) = match line.split_once(char::is_whitespace) {
+        Some((cmd, arg)) => (cmd, arg.trim()),
+        None => (line, ""),
+    };
+    let no_arg = |cmd| match arg.is_empty() {
+        true => Ok(cmd),
+        false => Err(ParseError::UnexpectedArgument),
+    };
+    match cmd {
+        "" => Err(ParseError::Empty),
+        "help" | "?" => no_arg(Command::Help),
+        "batt" => no_arg(Command::Batt),
+        "info" => no_arg(Command::Info),
+        "time" => no_arg(Command::Time),
+        "reboot" => no_arg(Command::Reboot),
+        "name" if arg.is_empty() => Err(ParseError::MissingArgument),
+        "name" => Ok(Command::Name(arg)),
+        "scan" => no_arg(Command::Scan),
+        "connect" if arg.is_empty() => Err(ParseError::MissingArgument),
+        "connect" => arg
+            .parse()
+            .map(Command::Connect)
+            .map_err(|_| ParseError::InvalidArgument),
+        "disconnect" => no_arg(Command::Disconnect),
+        "radar" => match arg {
+            "" => Err(ParseError::MissingArgument),
+            "on" => Ok(Command::Radar(true)),
+            "off" => Ok(Command::Radar(false)),
+            _ => Err(ParseError::InvalidArgument),
+        },
+        _ => Err(ParseError::Unknown),
+    }
+}
+
+/// Collects bytes until a line ending, a write may hold part of a line or several lines
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    buf: Vec<u8, MAX_LINE>,
+    overflowed: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Feeds `data` and calls `on_line` for every complete line.
+    /// Both `\n` and `\r` end a line, empty lines are skipped.
+    pub fn feed(&mut self, data: &[u8], mut on_line: impl FnMut(Result<&[u8], ParseError>)) {
+        for &byte in data {
+            if byte == b'\n' || byte == b'\r' {
+                if self.overflowed {
+                    on_line(Err(ParseError::TooLong));
+                } else if !self.buf.is_empty() {
+                    on_line(Ok(&self.buf));
+                }
+                self.buf.clear();
+                self.overflowed = false;
+            } else if self.buf.push(byte).is_err() {
+                self.overflowed = true;
+            }
+        }
+    }
+}
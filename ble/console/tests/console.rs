@@ -0,0 +1,117 @@
+//! Run on the host: `cargo test -p ble-console --target x86_64-unknown-linux-gnu`
+
+use ble_console::*;
+
+/// Feeds every write in turn and collects what comes out, lines as strings
+fn feed(buf: &mut LineBuffer, writes: &[&[u8]]) -> Vec<Result<String, ParseError>> {
+    let mut lines = Vec::new();
+    for data in writes {
+        buf.feed(data, |line| {
+            lines.push(line.map(|line| String::from_utf8(line.to_vec()).unwrap()))
+        });
+    }
+    lines
+}
+
+#[test]
+fn line_split_across_writes() {
+    let mut buf = LineBuffer::new();
+    assert_eq!(feed(&mut buf, &[b"na", b"me ", b"bo"]), []);
+    assert_eq!(feed(&mut buf, &[b"ard\n"]), [Ok("name board".into())]);
+    // several lines in one write, the last one left open
+    assert_eq!(
+        feed(&mut buf, &[b"batt\ninfo\nti"]),
+        [Ok("batt".into()), Ok("info".into())]
+    );
+    assert_eq!(feed(&mut buf, &[b"me", b"\n"]), [Ok("time".into())]);
+}
+
+#[test]
+fn line_endings() {
+    let mut buf = LineBuffer::new();
+    assert_eq!(feed(&mut buf, &[b"batt\r"]), [Ok("batt".into())]);
+    assert_eq!(feed(&mut buf, &[b"batt\n"]), [Ok("batt".into())]);
+    // the \n of a crlf ends an empty line, which is skipped
+    assert_eq!(feed(&mut buf, &[b"batt\r\n"]), [Ok("batt".into())]);
+    assert_eq!(feed(&mut buf, &[b"batt\r", b"\n"]), [Ok("batt".into())]);
+    assert_eq!(feed(&mut buf, &[b"\n\r\n\r\r", b"\n\n"]), []);
+    assert_eq!(
+        feed(&mut buf, &[b"\r\nhelp\r\n\r\nbatt\n"]),
+        [Ok("help".into()), Ok("batt".into())]
+    );
+}
+
+#[test]
+fn too_long_is_reported_once_then_recovers() {
+    let mut buf = LineBuffer::new();
+    let long = vec![b'x'; MAX_LINE + 10];
+    // reported at the end of the line, not for every byte that didn't fit
+    assert_eq!(feed(&mut buf, &[&long, &long]), []);
+    assert_eq!(feed(&mut buf, &[b"yy\r\n"]), [Err(ParseError::TooLong)]);
+    assert_eq!(feed(&mut buf, &[b"batt\n"]), [Ok("batt".into())]);
+    // exactly full still fits
+    let full = vec![b'z'; MAX_LINE];
+    assert_eq!(feed(&mut buf, &[&full, b"\n"]), [Ok("z".repeat(MAX_LINE))]);
+    let over = vec![b'z'; MAX_LINE + 1];
+    assert_eq!(
+        feed(&mut buf, &[&over, b"\nbatt\n"]),
+        [Err(ParseError::TooLong), Ok("batt".into())]
+    );
+}
+
+#[test]
+fn commands() {
+    assert_eq!(parse(b"help"), Ok(Command::Help));
+    assert_eq!(parse(b"?"), Ok(Command::Help));
+    assert_eq!(parse(b"batt"), Ok(Command::Batt));
+    assert_eq!(parse(b"info"), Ok(Command::Info));
+    assert_eq!(parse(b"time"), Ok(Command::Time));
+    assert_eq!(parse(b"scan"), Ok(Command::Scan));
+    assert_eq!(parse(b"disconnect"), Ok(Command::Disconnect));
+    assert_eq!(parse(b"reboot"), Ok(Command::Reboot));
+    assert_eq!(parse(b"  batt \t"), Ok(Command::Batt));
+    assert_eq!(parse(b""), Err(ParseError::Empty));
+    assert_eq!(parse(b"   "), Err(ParseError::Empty));
+    assert_eq!(parse(b"bat"), Err(ParseError::Unknown));
+    assert_eq!(parse(b"BATT"), Err(ParseError::Unknown));
+    assert_eq!(parse(b"batt now"), Err(ParseError::UnexpectedArgument));
+}
+
+#[test]
+fn name_argument() {
+    assert_eq!(parse(b"name board"), Ok(Command::Name("board")));
+    // the rest of the line, inner spaces kept
+    assert_eq!(parse(b"name  my  board "), Ok(Command::Name("my  board")));
+    assert_eq!(parse(b"name"), Err(ParseError::MissingArgument));
+    assert_eq!(parse(b"name   "), Err(ParseError::MissingArgument));
+}
+
+#[test]
+fn connect_argument() {
+    assert_eq!(parse(b"connect 0"), Ok(Command::Connect(0)));
+    assert_eq!(parse(b"connect 12"), Ok(Command::Connect(12)));
+    assert_eq!(parse(b"connect"), Err(ParseError::MissingArgument));
+    assert_eq!(parse(b"connect x"), Err(ParseError::InvalidArgument));
+    assert_eq!(parse(b"connect -1"), Err(ParseError::InvalidArgument));
+    assert_eq!(parse(b"connect 1 2"), Err(ParseError::InvalidArgument));
+}
+
+#[test]
+fn radar_argument() {
+    assert_eq!(parse(b"radar on"), Ok(Command::Radar(true)));
+    assert_eq!(parse(b"radar off"), Ok(Command::Radar(false)));
+    assert_eq!(parse(b"radar"), Err(ParseError::MissingArgument));
+    assert_eq!(parse(b"radar maybe"), Err(ParseError::InvalidArgument));
+    assert_eq!(parse(b"radar ON"), Err(ParseError::InvalidArgument));
+}
+
+#[test]
+fn not_utf8() {
+    assert_eq!(parse(&[0xFF, 0xFE]), Err(ParseError::NotUtf8));
+    assert_eq!(parse(b"name \xC3"), Err(ParseError::NotUtf8));
+    // utf8 names are fine
+    assert_eq!(
+        parse("name b\u{f6}rd".as_bytes()),
+        Ok(Command::Name("b\u{f6}rd"))
+    );
+}
@@ -0,0 +1,152 @@
+//! ES Trigger Setting descriptor for ble-batt, decides when a measurement is notified.
+//!
+//! Layout and conditions from the Environmental Sensing Service spec, section 3.1.2.2.
+//! Operands of the value conditions have the type of the characteristic, for the
+//! temperature that is an `i16` in 0.01 degrees. Times are milliseconds since boot,
+//! whatever clock the caller has.
+//!
+//! One extension: "value changed" (0x03) takes an optional `u16` operand, the change the
+//! value has to exceed, like the "value changed more than" condition of the Value Trigger
+//! Setting (AIOS spec, section 3.6). Without it every change counts, as the ESS spec says.
+//!
+//! Pure code, host tests: `cargo test -p ble-ess --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use heapless::Vec;
+
+/// Largest interval operand, they are 24 bits
+pub const MAX_SECS: u32 = 0xFF_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    Inactive,
+    /// Every this many seconds, whatever the value
+    FixedInterval(u32),
+    /// On change, but no more often than every this many seconds
+    MinInterval(u32),
+    /// When the value has changed by more than this, 0 for any change
+    OnChange(u16),
+    LessThan(i16),
+    LessOrEqual(i16),
+    GreaterThan(i16),
+    GreaterOrEqual(i16),
+    Equal(i16),
+    NotEqual(i16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    UnknownCondition,
+    /// The operand is missing or has the wrong size for the condition
+    BadOperand,
+    /// A fixed interval of 0 seconds
+    OutOfRange,
+}
+
+/// ATT error Write Request Rejected, from the common profile error codes (CSS part B)
+pub const ATT_WRITE_REQUEST_REJECTED: u8 = 0xFC;
+/// ATT error Out of Range, from the common profile error codes (CSS part B)
+pub const ATT_OUT_OF_RANGE: u8 = 0xFF;
+
+impl Error {
+    /// The ATT error a write of a trigger that doesn't decode is answered with
+    pub fn att_error(self) -> u8 {
+        match self {
+            Error::UnknownCondition | Error::BadOperand => ATT_WRITE_REQUEST_REJECTED,
+            Error::OutOfRange => ATT_OUT_OF_RANGE,
+        }
+    }
+}
+
+fn u24(data: &[u8]) -> Result<u32, Error> {
+    match data {
+        [a, b, c] => Ok(u32::from_le_bytes([*a, *b, *c, 0])),
+        _ => Err(Error::BadOperand),
+    }
+}
+
+fn i16_operand(data: &[u8]) -> Result<i16, Error> {
+    match data {
+        [a, b] => Ok(i16::from_le_bytes([*a, *b])),
+        _ => Err(Error::BadOperand),
+    }
+}
+
+impl Trigger {
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let (&condition, operand) = data.split_first().ok_or(Error::BadOperand)?;
+        let no_operand = |trigger| match operand {
+            [] => Ok(trigger),
+            _ => Err(Error::BadOperand),
+        };
+        match condition {
+            0x00 => no_operand(Trigger::Inactive),
+            0x01 => match u24(operand)? {
+                0 => Err(Error::OutOfRange),
+                secs => Ok(Trigger::FixedInterval(secs)),
+            },
+            0x02 => Ok(Trigger::MinInterval(u24(operand)?)),
+            0x03 => match operand {
+                [] => Ok(Trigger::OnChange(0)),
+                [a, b] => Ok(Trigger::OnChange(u16::from_le_bytes([*a, *b]))),
+                _ => Err(Error::BadOperand),
+            },
+            0x04 => Ok(Trigger::LessThan(i16_operand(operand)?)),
+            0x05 => Ok(Trigger::LessOrEqual(i16_operand(operand)?)),
+            0x06 => Ok(Trigger::GreaterThan(i16_operand(operand)?)),
+            0x07 => Ok(Trigger::GreaterOrEqual(i16_operand(operand)?)),
+            0x08 => Ok(Trigger::Equal(i16_operand(operand)?)),
+            0x09 => Ok(Trigger::NotEqual(i16_operand(operand)?)),
+            _ => Err(Error::UnknownCondition),
+        }
+    }
+
+    /// Intervals past [`MAX_SECS`] are sent as [`MAX_SECS`]
+    pub fn encode(&self) -> Vec<u8, 4> {
+        let secs = |secs: &u32| (*secs).min(MAX_SECS).to_le_bytes();
+        let (condition, operand): (u8, &[u8]) = match self {
+            Trigger::Inactive => (0x00, &[]),
+            Trigger::FixedInterval(s) => (0x01, &secs(s)[..3]),
+            Trigger::MinInterval(s) => (0x02, &secs(s)[..3]),
+            Trigger::OnChange(0) => (0x03, &[]),
+            Trigger::OnChange(threshold) => (0x03, &threshold.to_le_bytes()),
+            Trigger::LessThan(v) => (0x04, &v.to_le_bytes()),
+            Trigger::LessOrEqual(v) => (0x05, &v.to_le_bytes()),
+            Trigger::GreaterThan(v) => (0x06, &v.to_le_bytes()),
+            Trigger::GreaterOrEqual(v) => (0x07, &v.to_le_bytes()),
+            Trigger::Equal(v) => (0x08, &v.to_le_bytes()),
+            Trigger::NotEqual(v) => (0x09, &v.to_le_bytes()),
+        };
+        let mut out = Vec::new();
+        out.push(condition).unwrap();
+        out.extend_from_slice(operand).unwrap();
+        out
+    }
+
+    /// Should a client that was last sent `last` (value and when) get `value` now
+    pub fn should_notify(&self, last: Option<(i16, u64)>, value: i16, now: u64) -> bool {
+        let elapsed = |secs: u32| match last {
+            None => true,
+            Some((_, at)) => now.saturating_sub(at) >= secs as u64 * 1000,
+        };
+        let changed = |threshold: u16| match last {
+            None => true,
+            Some((last, _)) => value.abs_diff(last) > threshold,
+        };
+        match *self {
+            Trigger::Inactive => false,
+            Trigger::FixedInterval(secs) => elapsed(secs),
+            Trigger::MinInterval(secs) => changed(0) && elapsed(secs),
+            Trigger::OnChange(threshold) => changed(threshold),
+            Trigger::LessThan(v) => value < v,
+            Trigger::LessOrEqual(v) => value <= v,
+            Trigger::GreaterThan(v) => value > v,
+            Trigger::GreaterOrEqual(v) => value >= v,
+            Trigger::Equal(v) => value == v,
+            Trigger::NotEqual(v) => value != v,
+        }
+    }
+}
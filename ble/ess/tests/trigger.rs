@@ -0,0 +1,199 @@
+//! Run on the host: `cargo test -p ble-ess --target x86_64-unknown-linux-gnu`
+
+use ble_ess::*;
+
+const ALL: [Trigger; 11] = [
+    Trigger::Inactive,
+    Trigger::FixedInterval(60),
+    Trigger::MinInterval(0x12_3456),
+    Trigger::OnChange(0),
+    Trigger::OnChange(100),
+    Trigger::LessThan(-500),
+    Trigger::LessOrEqual(0),
+    Trigger::GreaterThan(2500),
+    Trigger::GreaterOrEqual(i16::MIN),
+    Trigger::Equal(i16::MAX),
+    Trigger::NotEqual(0x0102),
+];
+
+#[test]
+fn round_trips() {
+    for trigger in ALL {
+        assert_eq!(
+            Trigger::decode(&trigger.encode()),
+            Ok(trigger),
+            "{:?}",
+            trigger
+        );
+    }
+}
+
+#[test]
+fn layouts() {
+    assert_eq!(Trigger::Inactive.encode(), [0x00]);
+    // 24 bit seconds, little endian
+    assert_eq!(
+        Trigger::FixedInterval(0x12_3456).encode(),
+        [0x01, 0x56, 0x34, 0x12]
+    );
+    assert_eq!(Trigger::MinInterval(1).encode(), [0x02, 0x01, 0x00, 0x00]);
+    // as the ESS spec has it, no operand
+    assert_eq!(Trigger::OnChange(0).encode(), [0x03]);
+    assert_eq!(Trigger::OnChange(0x0102).encode(), [0x03, 0x02, 0x01]);
+    // the temperature type, i16 little endian
+    assert_eq!(Trigger::LessThan(-2).encode(), [0x04, 0xFE, 0xFF]);
+    assert_eq!(Trigger::LessOrEqual(1).encode(), [0x05, 0x01, 0x00]);
+    assert_eq!(Trigger::GreaterThan(1).encode(), [0x06, 0x01, 0x00]);
+    assert_eq!(Trigger::GreaterOrEqual(1).encode(), [0x07, 0x01, 0x00]);
+    assert_eq!(Trigger::Equal(1).encode(), [0x08, 0x01, 0x00]);
+    assert_eq!(Trigger::NotEqual(1).encode(), [0x09, 0x01, 0x00]);
+}
+
+#[test]
+fn wrong_operand_length() {
+    assert_eq!(Trigger::decode(&[]), Err(Error::BadOperand));
+    assert_eq!(Trigger::decode(&[0x00, 0x01]), Err(Error::BadOperand));
+    for condition in [0x01, 0x02] {
+        assert_eq!(Trigger::decode(&[condition]), Err(Error::BadOperand));
+        assert_eq!(Trigger::decode(&[condition, 1, 0]), Err(Error::BadOperand));
+        assert_eq!(
+            Trigger::decode(&[condition, 1, 0, 0, 0]),
+            Err(Error::BadOperand)
+        );
+    }
+    assert_eq!(Trigger::decode(&[0x03, 0x01]), Err(Error::BadOperand));
+    assert_eq!(Trigger::decode(&[0x03, 1, 0, 0]), Err(Error::BadOperand));
+    for condition in 0x04..=0x09 {
+        assert_eq!(Trigger::decode(&[condition]), Err(Error::BadOperand));
+        assert_eq!(Trigger::decode(&[condition, 1]), Err(Error::BadOperand));
+        assert_eq!(
+            Trigger::decode(&[condition, 1, 0, 0]),
+            Err(Error::BadOperand)
+        );
+    }
+}
+
+#[test]
+fn out_of_range() {
+    for condition in 0x0A..=0xFF {
+        assert_eq!(Trigger::decode(&[condition]), Err(Error::UnknownCondition));
+        assert_eq!(
+            Trigger::decode(&[condition, 1, 0]),
+            Err(Error::UnknownCondition)
+        );
+    }
+    // a fixed interval needs time between notifications
+    assert_eq!(Trigger::decode(&[0x01, 0, 0, 0]), Err(Error::OutOfRange));
+    // a minimum interval of 0 is just on change
+    assert_eq!(
+        Trigger::decode(&[0x02, 0, 0, 0]),
+        Ok(Trigger::MinInterval(0))
+    );
+    // intervals don't fit in 24 bits
+    assert_eq!(
+        Trigger::FixedInterval(0x0100_0000).encode(),
+        [0x01, 0xFF, 0xFF, 0xFF]
+    );
+    assert_eq!(
+        Trigger::decode(&Trigger::MinInterval(u32::MAX).encode()),
+        Ok(Trigger::MinInterval(MAX_SECS))
+    );
+}
+
+#[test]
+fn att_errors() {
+    assert_eq!(
+        Error::UnknownCondition.att_error(),
+        ATT_WRITE_REQUEST_REJECTED
+    );
+    assert_eq!(Error::BadOperand.att_error(), ATT_WRITE_REQUEST_REJECTED);
+    assert_eq!(Error::OutOfRange.att_error(), ATT_OUT_OF_RANGE);
+    // what clients see for the writes that don't decode
+    assert_eq!(
+        Trigger::decode(&[0x0A]).map_err(Error::att_error),
+        Err(0xFC)
+    );
+    assert_eq!(
+        Trigger::decode(&[0x01, 0, 0, 0]).map_err(Error::att_error),
+        Err(0xFF)
+    );
+}
+
+#[test]
+fn inactive_never_notifies() {
+    assert!(!Trigger::Inactive.should_notify(None, 0, 0));
+    assert!(!Trigger::Inactive.should_notify(Some((0, 0)), 100, 1_000_000));
+}
+
+#[test]
+fn fixed_interval() {
+    let trigger = Trigger::FixedInterval(10);
+    assert!(trigger.should_notify(None, 0, 0));
+    // whatever the value
+    assert!(!trigger.should_notify(Some((0, 1_000)), 5_000, 10_999));
+    assert!(trigger.should_notify(Some((0, 1_000)), 0, 11_000));
+    // a clock that went backwards
+    assert!(!trigger.should_notify(Some((0, 1_000)), 0, 0));
+}
+
+#[test]
+fn min_interval() {
+    let trigger = Trigger::MinInterval(10);
+    assert!(trigger.should_notify(None, 0, 0));
+    let last = Some((2000, 1_000));
+    assert!(!trigger.should_notify(last, 2025, 10_999));
+    assert!(trigger.should_notify(last, 2025, 11_000));
+    // not without a change
+    assert!(!trigger.should_notify(last, 2000, 60_000));
+}
+
+#[test]
+fn on_change() {
+    let any = Trigger::OnChange(0);
+    assert!(any.should_notify(None, 2000, 0));
+    assert!(!any.should_notify(Some((2000, 0)), 2000, 0));
+    assert!(any.should_notify(Some((2000, 0)), 2001, 0));
+    assert!(any.should_notify(Some((2000, 0)), 1975, 0));
+    // the threshold the client wrote, it has to be exceeded
+    let half_degree = Trigger::OnChange(50);
+    assert!(half_degree.should_notify(None, 2000, 0));
+    assert!(!half_degree.should_notify(Some((2000, 0)), 2025, 0));
+    assert!(!half_degree.should_notify(Some((2000, 0)), 2050, 0));
+    assert!(half_degree.should_notify(Some((2000, 0)), 2075, 0));
+    assert!(half_degree.should_notify(Some((2000, 0)), 1925, 0));
+    // across the whole range
+    assert!(Trigger::OnChange(u16::MAX - 1).should_notify(Some((i16::MIN, 0)), i16::MAX, 0));
+    assert!(!Trigger::OnChange(u16::MAX).should_notify(Some((i16::MIN, 0)), i16::MAX, 0));
+}
+
+#[test]
+fn value_conditions() {
+    let last = Some((0, 0));
+    let cases = [
+        (Trigger::LessThan(100), [true, false, false]),
+        (Trigger::LessOrEqual(100), [true, true, false]),
+        (Trigger::GreaterThan(100), [false, false, true]),
+        (Trigger::GreaterOrEqual(100), [false, true, true]),
+        (Trigger::Equal(100), [false, true, false]),
+        (Trigger::NotEqual(100), [true, false, true]),
+    ];
+    for (trigger, expected) in cases {
+        for (value, expected) in [99, 100, 101].into_iter().zip(expected) {
+            // while the condition holds, every sample and whatever was sent before
+            assert_eq!(
+                trigger.should_notify(None, value, 0),
+                expected,
+                "{:?} {}",
+                trigger,
+                value
+            );
+            assert_eq!(
+                trigger.should_notify(last, value, 0),
+                expected,
+                "{:?} {}",
+                trigger,
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! HID reports for ble-batt: a keyboard and a consumer control (media keys).
+//!
+//! [`REPORT_MAP`] describes both to the host, the firmware serves it in the Report Map
+//! characteristic of the HID service. Over GATT a report goes without its report id,
+//! the Report Reference descriptor of its characteristic carries the id instead, so the
+//! encoders here leave it out. The keyboard report has the boot keyboard layout and works
+//! in boot protocol mode as is.
+//!
+//! Usage ids from the HID Usage Tables 1.4, section 10 (keyboard) and 15 (consumer).
+//!
+//! Pure code, host tests: `cargo test -p ble-hid --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+pub mod report_id {
+    pub const KEYBOARD: u8 = 1;
+    pub const CONSUMER: u8 = 2;
+}
+
+/// Keyboard input report: modifiers, reserved, six keys
+pub const KEYBOARD_LEN: usize = 8;
+/// Consumer input report: one 16 bit usage
+pub const CONSUMER_LEN: usize = 2;
+/// Keys pressed at once, more and the report says so with [`key::ERROR_ROLL_OVER`]
+pub const MAX_KEYS: usize = 6;
+
+/// Bits of the first byte of a keyboard report
+pub mod modifier {
+    pub const LEFT_CTRL: u8 = 0x01;
+    pub const LEFT_SHIFT: u8 = 0x02;
+    pub const LEFT_ALT: u8 = 0x04;
+    pub const LEFT_GUI: u8 = 0x08;
+    pub const RIGHT_CTRL: u8 = 0x10;
+    pub const RIGHT_SHIFT: u8 = 0x20;
+    pub const RIGHT_ALT: u8 = 0x40;
+    pub const RIGHT_GUI: u8 = 0x80;
+}
+
+/// Keyboard usage ids, the ones a remote has a use for
+pub mod key {
+    pub const ERROR_ROLL_OVER: u8 = 0x01;
+    pub const A: u8 = 0x04;
+    pub const B: u8 = 0x05;
+    pub const ENTER: u8 = 0x28;
+    pub const ESCAPE: u8 = 0x29;
+    pub const SPACE: u8 = 0x2C;
+    pub const F5: u8 = 0x3E;
+    pub const PAGE_UP: u8 = 0x4B;
+    pub const PAGE_DOWN: u8 = 0x4E;
+    pub const RIGHT_ARROW: u8 = 0x4F;
+    pub const LEFT_ARROW: u8 = 0x50;
+    pub const DOWN_ARROW: u8 = 0x51;
+    pub const UP_ARROW: u8 = 0x52;
+}
+
+/// Consumer usage ids
+pub mod consumer {
+    pub const SCAN_NEXT_TRACK: u16 = 0xB5;
+    pub const SCAN_PREVIOUS_TRACK: u16 = 0xB6;
+    pub const PLAY_PAUSE: u16 = 0xCD;
+    pub const MUTE: u16 = 0xE2;
+    pub const VOLUME_UP: u16 = 0xE9;
+    pub const VOLUME_DOWN: u16 = 0xEA;
+}
+
+/// Bits of the keyboard output report the host writes
+pub mod led {
+    pub const NUM_LOCK: u8 = 0x01;
+    pub const CAPS_LOCK: u8 = 0x02;
+    pub const SCROLL_LOCK: u8 = 0x04;
+}
+
+#[rustfmt::skip]
+pub const REPORT_MAP: [u8; 90] = [
+    0x05, 0x01,         // Usage Page (Generic Desktop)
+    0x09, 0x06,         // Usage (Keyboard)
+    0xA1, 0x01,         // Collection (Application)
+    0x85, report_id::KEYBOARD, // Report Id
+    0x05, 0x07,         //   Usage Page (Keyboard)
+    0x19, 0xE0,         //   Usage Minimum (Left Control)
+    0x29, 0xE7,         //   Usage Maximum (Right GUI)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x25, 0x01,         //   Logical Maximum (1)
+    0x75, 0x01,         //   Report Size (1)
+    0x95, 0x08,         //   Report Count (8)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute): modifiers
+    0x95, 0x01,         //   Report Count (1)
+    0x75, 0x08,         //   Report Size (8)
+    0x81, 0x01,         //   Input (Constant): reserved
+    0x95, 0x05,         //   Report Count (5)
+    0x75, 0x01,         //   Report Size (1)
+    0x05, 0x08,         //   Usage Page (LEDs)
+    0x19, 0x01,         //   Usage Minimum (Num Lock)
+    0x29, 0x05,         //   Usage Maximum (Kana)
+    0x91, 0x02,         //   Output (Data, Variable, Absolute): leds
+    0x95, 0x01,         //   Report Count (1)
+    0x75, 0x03,         //   Report Size (3)
+    0x91, 0x01,         //   Output (Constant): padding
+    0x95, 0x06,         //   Report Count (6)
+    0x75, 0x08,         //   Report Size (8)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x25, 0x65,         //   Logical Maximum (101)
+    0x05, 0x07,         //   Usage Page (Keyboard)
+    0x19, 0x00,         //   Usage Minimum (0)
+    0x29, 0x65,         //   Usage Maximum (101)
+    0x81, 0x00,         //   Input (Data, Array): keys
+    0xC0,               // End Collection
+
+    0x05, 0x0C,         // Usage Page (Consumer)
+    0x09, 0x01,         // Usage (Consumer Control)
+    0xA1, 0x01,         // Collection (Application)
+    0x85, report_id::CONSUMER, // Report Id
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x26, 0xFF, 0x03,   //   Logical Maximum (1023)
+    0x19, 0x00,         //   Usage Minimum (0)
+    0x2A, 0xFF, 0x03,   //   Usage Maximum (1023)
+    0x75, 0x10,         //   Report Size (16)
+    0x95, 0x01,         //   Report Count (1)
+    0x81, 0x00,         //   Input (Data, Array, Absolute): usage
+    0xC0,               // End Collection
+];
+
+/// What a button does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Action {
+    /// A key with its [`modifier`]s held down
+    Key { modifiers: u8, key: u8 },
+    /// A [`consumer`] usage
+    Consumer(u16),
+}
+
+/// An input report, without the report id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Report {
+    Keyboard([u8; KEYBOARD_LEN]),
+    Consumer([u8; CONSUMER_LEN]),
+}
+
+impl Report {
+    pub fn id(&self) -> u8 {
+        match self {
+            Report::Keyboard(_) => report_id::KEYBOARD,
+            Report::Consumer(_) => report_id::CONSUMER,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Report::Keyboard(report) => report,
+            Report::Consumer(report) => report,
+        }
+    }
+}
+
+impl Action {
+    /// The report while the button is down
+    pub fn press(&self) -> Report {
+        match *self {
+            Action::Key { modifiers, key } => Report::Keyboard(keyboard(modifiers, &[key])),
+            Action::Consumer(usage) => Report::Consumer(consumer(usage)),
+        }
+    }
+
+    /// The report once it is up again, nothing pressed
+    pub fn release(&self) -> Report {
+        match self {
+            Action::Key { .. } => Report::Keyboard(keyboard(0, &[])),
+            Action::Consumer(_) => Report::Consumer(consumer(0)),
+        }
+    }
+}
+
+/// A keyboard report with `keys` down, all of them [`key::ERROR_ROLL_OVER`] if there are
+/// more than [`MAX_KEYS`]
+pub fn keyboard(modifiers: u8, keys: &[u8]) -> [u8; KEYBOARD_LEN] {
+    let mut report = [0; KEYBOARD_LEN];
+    report[0] = modifiers;
+    if keys.len() > MAX_KEYS {
+        report[2..].fill(key::ERROR_ROLL_OVER);
+    } else {
+        report[2..2 + keys.len()].copy_from_slice(keys);
+    }
+    report
+}
+
+/// A consumer report with `usage` down, 0 for none
+pub fn consumer(usage: u16) -> [u8; CONSUMER_LEN] {
+    usage.to_le_bytes()
+}
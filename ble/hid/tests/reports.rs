@@ -0,0 +1,106 @@
+//! Run on the host: `cargo test -p ble-hid --target x86_64-unknown-linux-gnu`
+
+use ble_hid::*;
+
+/// Bits of the input and output reports of `id` in a report map, walking its short items
+/// the way a host does (HID 1.11, section 6.2.2)
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Sizes {
+    input: u32,
+    output: u32,
+}
+
+fn sizes(map: &[u8], id: u8) -> Sizes {
+    let mut sizes = Sizes::default();
+    let (mut report_id, mut report_size, mut report_count) = (0, 0, 0);
+    let mut depth = 0i32;
+    let mut i = 0;
+    while i < map.len() {
+        let prefix = map[i];
+        let len = match prefix & 0x03 {
+            3 => 4,
+            n => n as usize,
+        };
+        assert!(i + 1 + len <= map.len(), "item at {} runs past the end", i);
+        let mut data = [0u8; 4];
+        data[..len].copy_from_slice(&map[i + 1..i + 1 + len]);
+        let value = u32::from_le_bytes(data);
+        match prefix & 0xFC {
+            0x84 => report_id = value as u8,
+            0x74 => report_size = value,
+            0x94 => report_count = value,
+            0xA0 => depth += 1,
+            0xC0 => depth -= 1,
+            0x80 if report_id == id => sizes.input += report_size * report_count,
+            0x90 if report_id == id => sizes.output += report_size * report_count,
+            _ => {}
+        }
+        assert!(depth >= 0, "end collection without a collection at {}", i);
+        i += 1 + len;
+    }
+    assert_eq!(depth, 0, "collection left open");
+    sizes
+}
+
+#[test]
+fn report_map_matches_the_encoders() {
+    assert_eq!(
+        sizes(&REPORT_MAP, report_id::KEYBOARD),
+        Sizes {
+            input: KEYBOARD_LEN as u32 * 8,
+            output: 8,
+        }
+    );
+    assert_eq!(
+        sizes(&REPORT_MAP, report_id::CONSUMER),
+        Sizes {
+            input: CONSUMER_LEN as u32 * 8,
+            output: 0,
+        }
+    );
+    // nothing outside a report id
+    assert_eq!(sizes(&REPORT_MAP, 0), Sizes::default());
+}
+
+#[test]
+fn report_map_starts_with_the_keyboard() {
+    // generic desktop, keyboard, application collection
+    assert_eq!(REPORT_MAP[..6], [0x05, 0x01, 0x09, 0x06, 0xA1, 0x01]);
+    assert_eq!(*REPORT_MAP.last().unwrap(), 0xC0);
+}
+
+#[test]
+fn keyboard_reports() {
+    let report = keyboard(modifier::LEFT_SHIFT, &[key::A, key::B]);
+    assert_eq!(report, [0x02, 0, 0x04, 0x05, 0, 0, 0, 0]);
+    assert_eq!(keyboard(0, &[]), [0; 8]);
+    let report = keyboard(0, &[key::A; 6]);
+    assert_eq!(report[2..], [key::A; 6]);
+    // one too many
+    let report = keyboard(modifier::LEFT_CTRL, &[key::A; 7]);
+    assert_eq!(report, [0x01, 0, 1, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn consumer_reports() {
+    assert_eq!(consumer(consumer::VOLUME_UP), [0xE9, 0x00]);
+    assert_eq!(consumer(0x0123), [0x23, 0x01]);
+    assert_eq!(consumer(0), [0, 0]);
+}
+
+#[test]
+fn actions_press_and_release() {
+    let next = Action::Key {
+        modifiers: 0,
+        key: key::RIGHT_ARROW,
+    };
+    let press = next.press();
+    assert_eq!(press.id(), report_id::KEYBOARD);
+    assert_eq!(press.as_bytes(), [0, 0, 0x4F, 0, 0, 0, 0, 0]);
+    assert_eq!(next.release().as_bytes(), [0; 8]);
+
+    let play = Action::Consumer(consumer::PLAY_PAUSE);
+    assert_eq!(play.press(), Report::Consumer([0xCD, 0x00]));
+    assert_eq!(play.press().id(), report_id::CONSUMER);
+    assert_eq!(play.release(), Report::Consumer([0, 0]));
+}
@@ -0,0 +1,99 @@
+//! Signs app images for the DFU service of ble-batt.
+//!
+//! ```text
+//! sign-image sign <secret key> <version> <elf or bin> <out>
+//! sign-image pubkey <secret key>
+//! sign-image verify <public key> <image>
+//! ```
+//!
+//! The version has to be the one the binary was built with, the package version of
+//! ble-batt, which the app keeps in its binary as a `version_record`.
+//!
+//! Keys are files with the 32 byte key in hex, like `keys/dev.key`. A new secret key is
+//! 32 random bytes: `openssl rand -hex 32 > release.key`.
+
+use std::process::ExitCode;
+use std::{env, fs};
+
+use ble_image::{
+    elf_to_bin, find_version, is_elf, parse_key, parse_version, public_key, sign, verify,
+};
+
+const USAGE: &str = "usage:
+  sign-image sign <secret key> <version> <elf or bin> <out>
+  sign-image pubkey <secret key>
+  sign-image verify <public key> <image>";
+
+fn read_key(path: &str) -> Result<[u8; 32], String> {
+    let hex = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    parse_key(&hex).ok_or_else(|| format!("{path}: not a 32 byte key in hex"))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("{path}: {e}"))
+}
+
+fn fmt_version(v: u32) -> String {
+    format!("{}.{}.{}", v >> 16, (v >> 8) & 0xFF, v & 0xFF)
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["sign", key, version, path, output] => {
+            let key = read_key(key)?;
+            let version =
+                parse_version(version).ok_or_else(|| format!("{version}: not a version"))?;
+            let input = read(path)?;
+            let binary = if is_elf(&input) {
+                elf_to_bin(&input)?
+            } else {
+                input
+            };
+            // the device remembers the version, a wrong one would lock out real updates
+            match find_version(&binary) {
+                Some(found) if found == version => {}
+                Some(found) => {
+                    return Err(format!(
+                        "{path}: built as version {}, not {}",
+                        fmt_version(found),
+                        fmt_version(version)
+                    ))
+                }
+                None => return Err(format!("{path}: no version record, not a ble-batt build")),
+            }
+            let image = sign(&binary, version, &key);
+            fs::write(output, &image).map_err(|e| format!("{output}: {e}"))?;
+            println!("{output}: {} bytes, {} signed", image.len(), binary.len());
+            Ok(())
+        }
+        ["pubkey", key] => {
+            let key = public_key(&read_key(key)?);
+            let hex: String = key.iter().map(|b| format!("{b:02x}")).collect();
+            println!("{hex}");
+            Ok(())
+        }
+        ["verify", key, image] => {
+            let header = verify(&read(image)?, &read_key(key)?, 0)
+                .map_err(|e| format!("{image}: {e:?}"))?;
+            println!(
+                "{image}: good, version {}, {} bytes",
+                fmt_version(header.version),
+                header.len
+            );
+            Ok(())
+        }
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
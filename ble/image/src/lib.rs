@@ -0,0 +1,232 @@
+//! Signed firmware images for ble-batt.
+//!
+//! An image is a header followed by the raw binary of the app, little endian:
+//!
+//! | offset | size | field                                        |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 4    | magic, `BBI1`                                |
+//! | 4      | 4    | version, see [`version`]                     |
+//! | 8      | 4    | length of the binary                         |
+//! | 12     | 4    | reserved, 0                                  |
+//! | 16     | 64   | ed25519 signature                            |
+//!
+//! The signature is over the SHA-512 digest of the first 16 bytes of the header and
+//! the binary, so the device can hash the binary piece by piece as it reads it back.
+//!
+//! `no_std` for the device, the `std` feature adds signing and the `sign-image` tool.
+//! Host tests: `cargo test -p ble-image --features std --target x86_64-unknown-linux-gnu`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha512};
+
+#[cfg(feature = "std")]
+mod sign;
+#[cfg(feature = "std")]
+pub use sign::*;
+
+pub const MAGIC: [u8; 4] = *b"BBI1";
+pub const HEADER_LEN: usize = 80;
+/// The part of the header covered by the signature
+const SIGNED_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Shorter than a header or not starting with [`MAGIC`]
+    NotAnImage,
+    /// The binary is not as long as the header says
+    LengthMismatch,
+    /// Older than the version the verifier was given
+    Downgrade,
+    /// Not signed with the trusted key, or changed after signing
+    BadSignature,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    /// Length of the binary after the header
+    pub len: u32,
+    pub signature: [u8; 64],
+}
+
+impl Header {
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_LEN || data[0..4] != MAGIC {
+            return Err(Error::NotAnImage);
+        }
+        Ok(Self {
+            version: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            len: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            signature: data[SIGNED_LEN..HEADER_LEN].try_into().unwrap(),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0; HEADER_LEN];
+        out[..SIGNED_LEN].copy_from_slice(&self.signed_part());
+        out[SIGNED_LEN..].copy_from_slice(&self.signature);
+        out
+    }
+
+    fn signed_part(&self) -> [u8; SIGNED_LEN] {
+        let mut out = [0; SIGNED_LEN];
+        out[0..4].copy_from_slice(&MAGIC);
+        out[4..8].copy_from_slice(&self.version.to_le_bytes());
+        out[8..12].copy_from_slice(&self.len.to_le_bytes());
+        out
+    }
+}
+
+/// Starts the [`version_record`] an app links in, so [`find_version`] can tell the
+/// version of a binary
+pub const VERSION_TAG: [u8; 8] = *b"BBIVER1\0";
+pub const VERSION_RECORD_LEN: usize = VERSION_TAG.len() + 4;
+
+/// The tag and the version, little endian, for the app to keep in its binary
+pub const fn version_record(version: u32) -> [u8; VERSION_RECORD_LEN] {
+    let mut out = [0; VERSION_RECORD_LEN];
+    let mut i = 0;
+    while i < VERSION_TAG.len() {
+        out[i] = VERSION_TAG[i];
+        i += 1;
+    }
+    let version = version.to_le_bytes();
+    while i < VERSION_RECORD_LEN {
+        out[i] = version[i - VERSION_TAG.len()];
+        i += 1;
+    }
+    out
+}
+
+/// The version in the first [`version_record`] of a binary, `None` without one
+pub fn find_version(binary: &[u8]) -> Option<u32> {
+    binary
+        .windows(VERSION_RECORD_LEN)
+        .find(|w| w[..VERSION_TAG.len()] == VERSION_TAG)
+        .map(|w| u32::from_le_bytes(w[VERSION_TAG.len()..].try_into().unwrap()))
+}
+
+/// `major.minor.patch` packed so that newer versions compare greater
+pub const fn version(major: u8, minor: u8, patch: u8) -> u32 {
+    (major as u32) << 16 | (minor as u32) << 8 | patch as u32
+}
+
+/// Packs a version like `CARGO_PKG_VERSION`, anything after the patch number is ignored
+pub const fn parse_version(s: &str) -> Option<u32> {
+    let s = s.as_bytes();
+    let mut parts = [0u32; 3];
+    let mut part = 0;
+    let mut digits = 0;
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'0'..=b'9' => {
+                parts[part] = parts[part] * 10 + (s[i] - b'0') as u32;
+                if parts[part] > u8::MAX as u32 {
+                    return None;
+                }
+                digits += 1;
+            }
+            b'.' if part < 2 && digits > 0 => {
+                part += 1;
+                digits = 0;
+            }
+            // pre-release or build metadata
+            b'-' | b'+' if part == 2 => break,
+            _ => return None,
+        }
+        i += 1;
+    }
+    if part != 2 || digits == 0 {
+        return None;
+    }
+    Some(version(parts[0] as u8, parts[1] as u8, parts[2] as u8))
+}
+
+/// A 32 byte key written as hex, like the files in `keys/`, trailing whitespace is ignored
+pub const fn parse_key(hex: &str) -> Option<[u8; 32]> {
+    const fn nibble(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
+        }
+    }
+    let hex = hex.as_bytes();
+    if hex.len() < 64 {
+        return None;
+    }
+    let mut key = [0; 32];
+    let mut i = 0;
+    while i < 32 {
+        match (nibble(hex[2 * i]), nibble(hex[2 * i + 1])) {
+            (Some(hi), Some(lo)) => key[i] = hi << 4 | lo,
+            _ => return None,
+        }
+        i += 1;
+    }
+    let mut i = 64;
+    while i < hex.len() {
+        if !hex[i].is_ascii_whitespace() {
+            return None;
+        }
+        i += 1;
+    }
+    Some(key)
+}
+
+/// Checks an image whose binary comes in pieces, e.g. read back from flash
+pub struct Verifier {
+    header: Header,
+    hasher: Sha512,
+    seen: u32,
+}
+
+impl Verifier {
+    /// Turns down images older than `current` before any of the binary is read
+    pub fn new(header: Header, current: u32) -> Result<Self, Error> {
+        if header.version < current {
+            return Err(Error::Downgrade);
+        }
+        let mut hasher = Sha512::new();
+        hasher.update(header.signed_part());
+        Ok(Self {
+            header,
+            hasher,
+            seen: 0,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The next piece of the binary
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.seen = self.seen.saturating_add(data.len() as u32);
+    }
+
+    pub fn finish(self, public_key: &[u8; 32]) -> Result<(), Error> {
+        if self.seen != self.header.len {
+            return Err(Error::LengthMismatch);
+        }
+        let digest = self.hasher.finalize();
+        let key = VerifyingKey::from_bytes(public_key).map_err(|_| Error::BadSignature)?;
+        key.verify_strict(&digest, &Signature::from_bytes(&self.header.signature))
+            .map_err(|_| Error::BadSignature)
+    }
+}
+
+/// Checks a whole image in memory and returns its header
+pub fn verify(image: &[u8], public_key: &[u8; 32], current: u32) -> Result<Header, Error> {
+    let header = Header::decode(image)?;
+    let mut verifier = Verifier::new(header, current)?;
+    verifier.update(&image[HEADER_LEN..]);
+    verifier.finish(public_key)?;
+    Ok(header)
+}
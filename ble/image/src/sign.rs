@@ -0,0 +1,73 @@
+//! Signing, host only.
+
+use ed25519_dalek::{Signer, SigningKey};
+use object::elf::PT_LOAD;
+use object::read::elf::{ElfFile32, ProgramHeader};
+use object::Endianness;
+use sha2::{Digest, Sha512};
+
+use crate::{Header, HEADER_LEN};
+
+/// The public key of a secret key (the 32 byte seed)
+pub fn public_key(secret_key: &[u8; 32]) -> [u8; 32] {
+    SigningKey::from_bytes(secret_key)
+        .verifying_key()
+        .to_bytes()
+}
+
+/// Puts a signed header in front of `binary`
+pub fn sign(binary: &[u8], version: u32, secret_key: &[u8; 32]) -> Vec<u8> {
+    let mut header = Header {
+        version,
+        len: binary.len() as u32,
+        signature: [0; 64],
+    };
+    let mut hasher = Sha512::new();
+    hasher.update(header.signed_part());
+    hasher.update(binary);
+    let digest = hasher.finalize();
+    header.signature = SigningKey::from_bytes(secret_key).sign(&digest).to_bytes();
+
+    let mut image = Vec::with_capacity(HEADER_LEN + binary.len());
+    image.extend_from_slice(&header.encode());
+    image.extend_from_slice(binary);
+    image
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(b"\x7fELF")
+}
+
+/// What ends up in flash from an ELF: the loadable segments at their load addresses,
+/// from the lowest one up, with gaps filled with 0xFF like erased flash
+pub fn elf_to_bin(elf: &[u8]) -> Result<Vec<u8>, String> {
+    let file = ElfFile32::<Endianness>::parse(elf).map_err(|e| e.to_string())?;
+    let endian = file.endian();
+    let mut segments = Vec::new();
+    for ph in file.elf_program_headers() {
+        if ph.p_type(endian) != PT_LOAD || ph.p_filesz(endian) == 0 {
+            continue;
+        }
+        let data = ph
+            .data(endian, elf)
+            .map_err(|_| "segment outside of the file".to_owned())?;
+        // the load address, .data is copied to ram from here at startup
+        segments.push((ph.p_paddr(endian), data));
+    }
+    let start = segments
+        .iter()
+        .map(|(addr, _)| *addr)
+        .min()
+        .ok_or("no loadable segments")?;
+    let end = segments
+        .iter()
+        .map(|(addr, data)| *addr + data.len() as u32)
+        .max()
+        .unwrap();
+    let mut bin = vec![0xFF; (end - start) as usize];
+    for (addr, data) in segments {
+        let offset = (addr - start) as usize;
+        bin[offset..offset + data.len()].copy_from_slice(data);
+    }
+    Ok(bin)
+}
@@ -0,0 +1,217 @@
+//! Run on the host: `cargo test -p ble-image --features std --target x86_64-unknown-linux-gnu`
+
+use ble_image::*;
+
+/// Test 1 of RFC 8032
+const SECRET: [u8; 32] = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+const PUBLIC: [u8; 32] = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+/// Test 2 of RFC 8032, someone else
+const OTHER_PUBLIC: [u8; 32] =
+    hex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+
+const fn hex(s: &str) -> [u8; 32] {
+    match parse_key(s) {
+        Some(key) => key,
+        None => panic!("bad test key"),
+    }
+}
+
+fn binary() -> Vec<u8> {
+    (0..3000u32).map(|i| (i * 7) as u8).collect()
+}
+
+#[test]
+fn public_key_of_secret() {
+    assert_eq!(public_key(&SECRET), PUBLIC);
+}
+
+#[test]
+fn dev_keys_match() {
+    let secret = parse_key(include_str!("../keys/dev.key")).unwrap();
+    let public = parse_key(include_str!("../keys/dev.pub")).unwrap();
+    assert_eq!(public_key(&secret), public);
+}
+
+#[test]
+fn roundtrip() {
+    let image = sign(&binary(), version(1, 2, 3), &SECRET);
+    assert_eq!(image.len(), HEADER_LEN + binary().len());
+    assert_eq!(&image[HEADER_LEN..], &binary()[..]);
+
+    let header = verify(&image, &PUBLIC, version(1, 2, 3)).unwrap();
+    assert_eq!(header.version, version(1, 2, 3));
+    assert_eq!(header.len, binary().len() as u32);
+    assert_eq!(Header::decode(&header.encode()), Ok(header));
+}
+
+#[test]
+fn verifier_in_pieces() {
+    let image = sign(&binary(), version(0, 1, 0), &SECRET);
+    let header = Header::decode(&image).unwrap();
+    let mut verifier = Verifier::new(header, 0).unwrap();
+    for piece in image[HEADER_LEN..].chunks(256) {
+        verifier.update(piece);
+    }
+    assert_eq!(verifier.finish(&PUBLIC), Ok(()));
+}
+
+#[test]
+fn tampered_body() {
+    let mut image = sign(&binary(), version(1, 0, 0), &SECRET);
+    image[HEADER_LEN + 100] ^= 1;
+    assert_eq!(verify(&image, &PUBLIC, 0), Err(Error::BadSignature));
+}
+
+#[test]
+fn tampered_version() {
+    let mut image = sign(&binary(), version(1, 0, 0), &SECRET);
+    // claims to be newer than it was signed as
+    image[6] = 9;
+    assert_eq!(verify(&image, &PUBLIC, 0), Err(Error::BadSignature));
+}
+
+#[test]
+fn tampered_signature() {
+    let mut image = sign(&binary(), version(1, 0, 0), &SECRET);
+    image[HEADER_LEN - 1] ^= 0x80;
+    assert_eq!(verify(&image, &PUBLIC, 0), Err(Error::BadSignature));
+}
+
+#[test]
+fn wrong_key() {
+    let image = sign(&binary(), version(1, 0, 0), &SECRET);
+    assert_eq!(verify(&image, &OTHER_PUBLIC, 0), Err(Error::BadSignature));
+}
+
+#[test]
+fn downgrade() {
+    let image = sign(&binary(), version(1, 2, 3), &SECRET);
+    assert_eq!(
+        verify(&image, &PUBLIC, version(1, 2, 4)),
+        Err(Error::Downgrade)
+    );
+    assert_eq!(
+        verify(&image, &PUBLIC, version(2, 0, 0)),
+        Err(Error::Downgrade)
+    );
+    // the same version again is fine, e.g. to repair a slot
+    assert!(verify(&image, &PUBLIC, version(1, 2, 3)).is_ok());
+    assert!(verify(&image, &PUBLIC, version(1, 1, 9)).is_ok());
+}
+
+#[test]
+fn unsigned() {
+    assert_eq!(verify(&binary(), &PUBLIC, 0), Err(Error::NotAnImage));
+    assert_eq!(verify(&[], &PUBLIC, 0), Err(Error::NotAnImage));
+    let image = sign(&binary(), version(1, 0, 0), &SECRET);
+    assert_eq!(
+        verify(&image[..HEADER_LEN - 1], &PUBLIC, 0),
+        Err(Error::NotAnImage)
+    );
+}
+
+#[test]
+fn length_mismatch() {
+    let image = sign(&binary(), version(1, 0, 0), &SECRET);
+    assert_eq!(
+        verify(&image[..image.len() - 4], &PUBLIC, 0),
+        Err(Error::LengthMismatch)
+    );
+    let mut longer = image.clone();
+    longer.push(0xFF);
+    assert_eq!(verify(&longer, &PUBLIC, 0), Err(Error::LengthMismatch));
+}
+
+#[test]
+fn versions() {
+    assert_eq!(parse_version("0.1.0"), Some(version(0, 1, 0)));
+    assert_eq!(parse_version("1.20.255"), Some(0x01_14_ff));
+    assert_eq!(parse_version("2.0.1-rc.1"), Some(version(2, 0, 1)));
+    assert_eq!(parse_version("2.0.1+abc"), Some(version(2, 0, 1)));
+    assert_eq!(parse_version("1.0"), None);
+    assert_eq!(parse_version("1.0.256"), None);
+    assert_eq!(parse_version("1..0"), None);
+    assert_eq!(parse_version("1.0.0.0"), None);
+    assert_eq!(parse_version("v1.0.0"), None);
+    assert!(version(1, 0, 0) > version(0, 255, 255));
+}
+
+#[test]
+fn version_in_the_binary() {
+    let record = version_record(version(1, 2, 3));
+    assert_eq!(record, *b"BBIVER1\0\x03\x02\x01\x00");
+    let mut binary = binary();
+    assert_eq!(find_version(&binary), None);
+    // anywhere, not aligned
+    binary.splice(1001..1001, record);
+    assert_eq!(find_version(&binary), Some(version(1, 2, 3)));
+    // the first one counts
+    binary.splice(7..7, version_record(version(0, 1, 0)));
+    assert_eq!(find_version(&binary), Some(version(0, 1, 0)));
+    // the tag alone isn't a record
+    assert_eq!(find_version(&VERSION_TAG), None);
+    assert_eq!(find_version(&record[..VERSION_RECORD_LEN - 1]), None);
+}
+
+#[test]
+fn keys() {
+    let hex = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    assert_eq!(parse_key(hex), Some(PUBLIC));
+    assert_eq!(parse_key(&format!("{hex}\n")), Some(PUBLIC));
+    assert_eq!(parse_key(&hex.to_uppercase()), Some(PUBLIC));
+    assert_eq!(parse_key(&hex[..62]), None);
+    assert_eq!(parse_key(&format!("{hex}00")), None);
+    assert_eq!(parse_key(&hex.replace('d', "g")), None);
+}
+
+/// A little endian ELF32 with two loadable segments and a gap between them
+fn elf() -> Vec<u8> {
+    let segments: [(u32, u32, &[u8]); 2] = [
+        // .text at its address
+        (0x1C000, 0x1C000, &[1, 2, 3, 4, 5, 6]),
+        // .data runs in ram, loaded from flash after .text
+        (0x2000_0000, 0x1C008, &[7, 8, 9]),
+    ];
+    let phoff = 52u32;
+    let data_start = phoff + 32 * segments.len() as u32;
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\x7fELF\x01\x01\x01");
+    out.resize(16, 0);
+    out.extend_from_slice(&2u16.to_le_bytes()); // e_type, executable
+    out.extend_from_slice(&40u16.to_le_bytes()); // e_machine, arm
+    out.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    out.extend_from_slice(&0x1C000u32.to_le_bytes()); // e_entry
+    out.extend_from_slice(&phoff.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // e_shoff
+    out.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    out.extend_from_slice(&52u16.to_le_bytes()); // e_ehsize
+    out.extend_from_slice(&32u16.to_le_bytes()); // e_phentsize
+    out.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+    out.extend_from_slice(&40u16.to_le_bytes()); // e_shentsize
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+    let mut offset = data_start;
+    for (vaddr, paddr, data) in segments {
+        for word in [1, offset, vaddr, paddr, data.len() as u32, data.len() as u32, 5, 4] {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        offset += data.len() as u32;
+    }
+    for (_, _, data) in segments {
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+#[test]
+fn elf_segments() {
+    let elf = elf();
+    assert!(is_elf(&elf));
+    assert!(!is_elf(&binary()));
+    assert_eq!(
+        elf_to_bin(&elf).unwrap(),
+        [1, 2, 3, 4, 5, 6, 0xFF, 0xFF, 7, 8, 9]
+    );
+}
@@ -0,0 +1,294 @@
+//! Payload layouts of the micro:bit profile characteristics, for the `microbit-profile`
+//! feature of ble-batt.
+//!
+//! All multi byte values are little endian. Pure functions only, so the layouts can be
+//! compared with the published profile on the host.
+//!
+//! Pure code, host tests: `cargo test -p ble-profile --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use heapless::Vec;
+use micromath::F32Ext;
+
+/// Update periods in ms the accelerometer and magnetometer services accept
+pub const SENSOR_PERIODS_MS: [u16; 8] = [1, 2, 5, 10, 20, 80, 160, 640];
+
+/// The supported sensor period closest to what the client asked for
+pub fn nearest_period(ms: u16) -> u16 {
+    SENSOR_PERIODS_MS
+        .into_iter()
+        .min_by_key(|period| period.abs_diff(ms))
+        .unwrap()
+}
+
+/// Accelerometer (milli-g) and magnetometer data, three `i16` for x, y and z
+pub fn encode_xyz([x, y, z]: [i32; 3]) -> [u8; 6] {
+    let mut out = [0; 6];
+    for (bytes, value) in out.chunks_exact_mut(2).zip([x, y, z]) {
+        let value = value.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+    out
+}
+
+/// Compass bearing in whole degrees clockwise from magnetic north, 0..360.
+/// Assumes the board is lying flat, there is no tilt compensation.
+pub fn bearing(x: i32, y: i32) -> u16 {
+    // through the trait, on the host std's own f32 methods would shadow it
+    let degrees = F32Ext::atan2(y as f32, x as f32).to_degrees();
+    let degrees = if degrees < 0. {
+        degrees + 360.
+    } else {
+        degrees
+    };
+    (F32Ext::round(degrees) as u16) % 360
+}
+
+/// Die temperature in degrees from the quarter degree steps of the TEMP peripheral
+pub fn temperature(quarter_degrees: i32) -> i8 {
+    // round half away from zero
+    let half = if quarter_degrees < 0 { -2 } else { 2 };
+    ((quarter_degrees + half) / 4).clamp(i8::MIN as i32, i8::MAX as i32) as i8
+}
+
+/// LED Matrix State, one byte per row, bit 4 is the leftmost column
+pub fn encode_led_rows(is_set: impl Fn(usize, usize) -> bool) -> [u8; 5] {
+    let mut rows = [0; 5];
+    for (row, bits) in rows.iter_mut().enumerate() {
+        for col in 0..5 {
+            if is_set(row, col) {
+                *bits |= 1 << (4 - col);
+            }
+        }
+    }
+    rows
+}
+
+/// Inverse of [`encode_led_rows`], calls `set` for every lit led
+pub fn decode_led_rows(rows: [u8; 5], mut set: impl FnMut(usize, usize)) {
+    for (row, bits) in rows.into_iter().enumerate() {
+        for col in 0..5 {
+            if bits & (1 << (4 - col)) != 0 {
+                set(row, col);
+            }
+        }
+    }
+}
+
+/// What an edge pin is set up as, from IO Configuration and AD Configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PinMode {
+    DigitalOut,
+    DigitalIn,
+    /// PWM, driven by Pin Data or PWM Control
+    AnalogOut,
+    AnalogIn,
+}
+
+/// The modes of the first `N` pins, bit n of IO Configuration set: pin n is an input,
+/// bit n of AD Configuration set: pin n is analog
+pub fn pin_modes<const N: usize>(io_configuration: u32, ad_configuration: u32) -> [PinMode; N] {
+    let bit = |bits: u32, n: usize| bits.checked_shr(n as u32).unwrap_or(0) & 1 != 0;
+    core::array::from_fn(
+        |n| match (bit(io_configuration, n), bit(ad_configuration, n)) {
+            (false, false) => PinMode::DigitalOut,
+            (true, false) => PinMode::DigitalIn,
+            (false, true) => PinMode::AnalogOut,
+            (true, true) => PinMode::AnalogIn,
+        },
+    )
+}
+
+/// Pin Data is a list of `(pin, value)` byte pairs, a trailing odd byte is ignored
+pub fn decode_pin_pairs(data: &[u8]) -> impl Iterator<Item = (u8, u8)> + '_ {
+    data.chunks_exact(2).map(|pair| (pair[0], pair[1]))
+}
+
+pub fn encode_pin_pairs<const N: usize>(pairs: impl IntoIterator<Item = (u8, u8)>) -> Vec<u8, N> {
+    let mut out = Vec::new();
+    for (pin, value) in pairs {
+        if out.extend_from_slice(&[pin, value]).is_err() {
+            break;
+        }
+    }
+    out
+}
+
+/// Largest PWM Control value, always high
+pub const PWM_MAX: u16 = 1024;
+/// PWM period of the micro:bit runtime until a client sets one, in µs
+pub const PWM_DEFAULT_PERIOD_US: u32 = 20_000;
+
+/// One PWM Control entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pwm {
+    pub pin: u8,
+    /// Duty cycle 0..=[`PWM_MAX`]
+    pub value: u16,
+    pub period_us: u32,
+}
+
+/// Bytes of a PWM Control entry
+pub const PWM_ENTRY_LEN: usize = 7;
+
+/// PWM Control is a list of entries: pin, value (u16) and period (u32)
+pub fn decode_pwm(data: &[u8]) -> impl Iterator<Item = Pwm> + '_ {
+    data.chunks_exact(PWM_ENTRY_LEN).map(|entry| Pwm {
+        pin: entry[0],
+        value: u16::from_le_bytes([entry[1], entry[2]]),
+        period_us: u32::from_le_bytes([entry[3], entry[4], entry[5], entry[6]]),
+    })
+}
+
+/// Pin Data value of an analog output, 0..=255, as a PWM Control value
+pub fn pwm_value(pin_value: u8) -> u16 {
+    (pin_value as u32 * PWM_MAX as u32 / 255) as u16
+}
+
+/// Counter setup of the nRF PWM for a period: the 16 MHz clock divided by
+/// `2^prescaler` counts up to `top`, which has 15 bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PwmTiming {
+    pub prescaler: u8,
+    pub top: u16,
+}
+
+impl PwmTiming {
+    /// The finest clock the period fits in, periods longer than the slowest clock allows
+    /// (262 ms) are cut to that
+    pub fn new(period_us: u32) -> Self {
+        const TOP_MAX: u64 = 0x7FFF;
+        let ticks = period_us as u64 * 16;
+        let prescaler = (0..7u8)
+            .find(|&prescaler| ticks >> prescaler <= TOP_MAX)
+            .unwrap_or(7);
+        Self {
+            prescaler,
+            top: (ticks >> prescaler).clamp(1, TOP_MAX) as u16,
+        }
+    }
+
+    /// Counts the output is high for, `value` is a PWM Control value
+    pub fn high(&self, value: u16) -> u16 {
+        (self.top as u32 * value.min(PWM_MAX) as u32 / PWM_MAX as u32) as u16
+    }
+}
+
+/// Pin Data value of an analog input, 0..=255, from a 12 bit sample
+pub fn analog_pin_value(sample: i16) -> u8 {
+    (sample.clamp(0, 4095) >> 4) as u8
+}
+
+/// Component ids and event values used by the micro:bit runtime (DAL)
+pub mod dal {
+    pub const ID_BUTTON_A: u16 = 1;
+    pub const ID_BUTTON_B: u16 = 2;
+
+    pub const BUTTON_EVT_DOWN: u16 = 1;
+    pub const BUTTON_EVT_UP: u16 = 2;
+    pub const BUTTON_EVT_CLICK: u16 = 3;
+    pub const BUTTON_EVT_LONG_CLICK: u16 = 4;
+    pub const BUTTON_EVT_HOLD: u16 = 5;
+
+    /// Requirement value that matches every event of a component
+    pub const EVT_ANY: u16 = 0;
+}
+
+/// An event of the event service, `id` is the component and `value` what happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Event {
+    pub id: u16,
+    pub value: u16,
+}
+
+/// Events and requirements are lists of `(id, value)` u16 pairs
+pub fn decode_events(data: &[u8]) -> impl Iterator<Item = Event> + '_ {
+    data.chunks_exact(4).map(|entry| Event {
+        id: u16::from_le_bytes([entry[0], entry[1]]),
+        value: u16::from_le_bytes([entry[2], entry[3]]),
+    })
+}
+
+pub fn encode_event(event: Event) -> [u8; 4] {
+    let mut out = [0; 4];
+    out[..2].copy_from_slice(&event.id.to_le_bytes());
+    out[2..].copy_from_slice(&event.value.to_le_bytes());
+    out
+}
+
+/// Does any of the client's requirements ask for `event`
+pub fn is_required(requirements: &[Event], event: Event) -> bool {
+    requirements
+        .iter()
+        .any(|req| req.id == event.id && (req.value == dal::EVT_ANY || req.value == event.value))
+}
+
+/// DAL button events for a change of button service state
+/// (0 not pressed, 1 pressed, 2 long pressed)
+pub fn button_events(from: u8, to: u8) -> &'static [u16] {
+    match (from, to) {
+        (0, 1) => &[dal::BUTTON_EVT_DOWN],
+        (1, 2) => &[dal::BUTTON_EVT_HOLD],
+        (1, 0) => &[dal::BUTTON_EVT_UP, dal::BUTTON_EVT_CLICK],
+        (2, 0) => &[dal::BUTTON_EVT_UP, dal::BUTTON_EVT_LONG_CLICK],
+        _ => &[],
+    }
+}
+
+/// Values of Magnetometer Calibration
+pub mod calibration {
+    pub const UNKNOWN: u8 = 0;
+    /// Written by the client to start a calibration
+    pub const REQUESTED: u8 = 1;
+    pub const COMPLETED: u8 = 2;
+    pub const ERROR: u8 = 3;
+}
+
+/// Hard iron calibration: the middle of the smallest and largest field seen on every
+/// axis while the board is turned around is the offset the board itself adds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    min: [i32; 3],
+    max: [i32; 3],
+}
+
+impl Calibration {
+    pub const fn new() -> Self {
+        Self {
+            min: [i32::MAX; 3],
+            max: [i32::MIN; 3],
+        }
+    }
+
+    pub fn add(&mut self, xyz: [i32; 3]) {
+        for (axis, value) in xyz.into_iter().enumerate() {
+            self.min[axis] = self.min[axis].min(value);
+            self.max[axis] = self.max[axis].max(value);
+        }
+    }
+
+    /// The offset to subtract from every sample, `None` unless the field swung by at
+    /// least `min_span` on every axis, the board was not turned around enough
+    pub fn offset(&self, min_span: i32) -> Option<[i32; 3]> {
+        let mut offset = [0; 3];
+        for (axis, offset) in offset.iter_mut().enumerate() {
+            let (min, max) = (self.min[axis] as i64, self.max[axis] as i64);
+            if max - min < min_span as i64 {
+                return None;
+            }
+            *offset = ((min + max) / 2) as i32;
+        }
+        Some(offset)
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,331 @@
+//! Run on the host: `cargo test -p ble-profile --target x86_64-unknown-linux-gnu`
+
+use ble_profile::*;
+
+#[test]
+fn led_rows() {
+    // bit 4 is the leftmost column
+    assert_eq!(encode_led_rows(|_, col| col == 0), [0b10000; 5]);
+    assert_eq!(encode_led_rows(|_, col| col == 4), [0b00001; 5]);
+    assert_eq!(
+        encode_led_rows(|row, col| row == col),
+        [0b10000, 0b01000, 0b00100, 0b00010, 0b00001]
+    );
+    assert_eq!(encode_led_rows(|_, _| true), [0x1F; 5]);
+    assert_eq!(encode_led_rows(|_, _| false), [0; 5]);
+
+    let mut lit = Vec::new();
+    decode_led_rows([0b10001, 0, 0b00100, 0, 0], |row, col| lit.push((row, col)));
+    assert_eq!(lit, [(0, 0), (0, 4), (2, 2)]);
+    // the 3 bits above the 5 columns mean nothing
+    let mut lit = Vec::new();
+    decode_led_rows([0xE0, 0, 0, 0, 0], |row, col| lit.push((row, col)));
+    assert!(lit.is_empty());
+    // and back
+    let rows = [0b01010, 0b11111, 0, 0b00001, 0b10000];
+    let mut frame = [[false; 5]; 5];
+    decode_led_rows(rows, |row, col| frame[row][col] = true);
+    assert_eq!(encode_led_rows(|row, col| frame[row][col]), rows);
+}
+
+#[test]
+fn xyz_is_little_endian() {
+    assert_eq!(
+        encode_xyz([1, -1, 0x1234]),
+        [0x01, 0x00, 0xFF, 0xFF, 0x34, 0x12]
+    );
+    assert_eq!(encode_xyz([-1024, 0, 1024]), [0x00, 0xFC, 0, 0, 0x00, 0x04]);
+}
+
+#[test]
+fn xyz_clamps_to_i16() {
+    assert_eq!(
+        encode_xyz([40_000, -40_000, i32::MAX]),
+        [0xFF, 0x7F, 0x00, 0x80, 0xFF, 0x7F]
+    );
+    assert_eq!(
+        encode_xyz([32_767, -32_768, i32::MIN]),
+        [0xFF, 0x7F, 0x00, 0x80, 0x00, 0x80]
+    );
+}
+
+#[test]
+fn bearing_clockwise_from_north() {
+    assert_eq!(bearing(1000, 0), 0);
+    assert_eq!(bearing(0, 1000), 90);
+    assert_eq!(bearing(-1000, 0), 180);
+    assert_eq!(bearing(0, -1000), 270);
+    assert_eq!(bearing(1000, 1000), 45);
+    assert_eq!(bearing(1000, -1000), 315);
+    // just below north rounds up to 360, which is 0
+    assert_eq!(bearing(100_000, -1), 0);
+    for (x, y) in [(3, -7), (-5, -5), (12_345, 678), (-1, 1)] {
+        assert!(bearing(x, y) < 360, "{} {}", x, y);
+    }
+}
+
+#[test]
+fn sensor_periods() {
+    assert_eq!(nearest_period(0), 1);
+    assert_eq!(nearest_period(20), 20);
+    assert_eq!(nearest_period(60), 80);
+    // halfway takes the shorter one
+    assert_eq!(nearest_period(50), 20);
+    assert_eq!(nearest_period(1000), 640);
+    for period in SENSOR_PERIODS_MS {
+        assert_eq!(nearest_period(period), period);
+    }
+}
+
+#[test]
+fn temperature_rounds_half_away_from_zero() {
+    assert_eq!(temperature(0), 0);
+    assert_eq!(temperature(86), 22);
+    assert_eq!(temperature(85), 21);
+    assert_eq!(temperature(-6), -2);
+    assert_eq!(temperature(-5), -1);
+    assert_eq!(temperature(10_000), i8::MAX);
+    assert_eq!(temperature(-10_000), i8::MIN);
+}
+
+#[test]
+fn pin_modes_from_io_and_ad_configuration() {
+    use PinMode::*;
+    // io bit n set: pin n is an input, ad bit n set: pin n is analog
+    assert_eq!(pin_modes::<3>(0, 0), [DigitalOut; 3]);
+    assert_eq!(pin_modes::<3>(u32::MAX, 0), [DigitalIn; 3]);
+    assert_eq!(pin_modes::<3>(0, u32::MAX), [AnalogOut; 3]);
+    assert_eq!(pin_modes::<3>(u32::MAX, u32::MAX), [AnalogIn; 3]);
+    assert_eq!(
+        pin_modes::<4>(0b0101, 0b0011),
+        [AnalogIn, AnalogOut, DigitalIn, DigitalOut]
+    );
+    // bits past the pins served don't matter
+    assert_eq!(pin_modes::<3>(0xFFFF_FFF8, 0xFFFF_FFF8), [DigitalOut; 3]);
+    assert_eq!(pin_modes::<32>(1 << 31, 1 << 31)[31], AnalogIn);
+    assert_eq!(pin_modes::<33>(u32::MAX, u32::MAX)[32], DigitalOut);
+}
+
+#[test]
+fn pin_data_pairs() {
+    let pairs: Vec<_> = decode_pin_pairs(&[0, 1, 2, 0, 1, 255]).collect();
+    assert_eq!(pairs, [(0, 1), (2, 0), (1, 255)]);
+    // a trailing odd byte is ignored
+    let pairs: Vec<_> = decode_pin_pairs(&[1, 1, 2]).collect();
+    assert_eq!(pairs, [(1, 1)]);
+    assert_eq!(decode_pin_pairs(&[]).count(), 0);
+
+    let data: heapless::Vec<u8, 6> = encode_pin_pairs([(0, 1), (2, 0)]);
+    assert_eq!(data, [0, 1, 2, 0]);
+    // what doesn't fit is left out, never half a pair
+    let data: heapless::Vec<u8, 5> = encode_pin_pairs([(0, 1), (1, 1), (2, 1)]);
+    assert_eq!(data, [0, 1, 1, 1]);
+}
+
+#[test]
+fn pwm_control_entries() {
+    let data = [
+        1, 0x00, 0x02, 0x20, 0x4E, 0x00, 0x00, // pin 1, 512, 20 ms
+        2, 0x00, 0x04, 0x40, 0x42, 0x0F, 0x00, // pin 2, 1024, 1 s
+        3, 0x00, // half an entry
+    ];
+    let entries: Vec<_> = decode_pwm(&data).collect();
+    assert_eq!(
+        entries,
+        [
+            Pwm {
+                pin: 1,
+                value: 512,
+                period_us: 20_000
+            },
+            Pwm {
+                pin: 2,
+                value: 1024,
+                period_us: 1_000_000
+            },
+        ]
+    );
+}
+
+#[test]
+fn pwm_values() {
+    assert_eq!(pwm_value(0), 0);
+    assert_eq!(pwm_value(255), PWM_MAX);
+    assert_eq!(pwm_value(128), 514);
+}
+
+#[test]
+fn pwm_timing_fits_15_bits() {
+    // 20 ms, the default: 1 MHz
+    let timing = PwmTiming::new(PWM_DEFAULT_PERIOD_US);
+    assert_eq!(
+        timing,
+        PwmTiming {
+            prescaler: 4,
+            top: 20_000
+        }
+    );
+    // short periods keep the full clock
+    assert_eq!(
+        PwmTiming::new(1000),
+        PwmTiming {
+            prescaler: 0,
+            top: 16_000
+        }
+    );
+    assert_eq!(
+        PwmTiming::new(2047),
+        PwmTiming {
+            prescaler: 0,
+            top: 32_752
+        }
+    );
+    assert_eq!(
+        PwmTiming::new(2048),
+        PwmTiming {
+            prescaler: 1,
+            top: 16_384
+        }
+    );
+    // longer than the slowest clock allows, cut to its longest
+    assert_eq!(
+        PwmTiming::new(1_000_000),
+        PwmTiming {
+            prescaler: 7,
+            top: 0x7FFF
+        }
+    );
+    assert_eq!(
+        PwmTiming::new(0),
+        PwmTiming {
+            prescaler: 0,
+            top: 1
+        }
+    );
+}
+
+#[test]
+fn pwm_high_time() {
+    let timing = PwmTiming::new(PWM_DEFAULT_PERIOD_US);
+    assert_eq!(timing.high(0), 0);
+    assert_eq!(timing.high(512), 10_000);
+    assert_eq!(timing.high(PWM_MAX), 20_000);
+    // past the top is always high
+    assert_eq!(timing.high(u16::MAX), 20_000);
+}
+
+#[test]
+fn analog_inputs_are_8_bit() {
+    assert_eq!(analog_pin_value(0), 0);
+    assert_eq!(analog_pin_value(4095), 255);
+    assert_eq!(analog_pin_value(2048), 128);
+    // noise around 0 and past full scale
+    assert_eq!(analog_pin_value(-5), 0);
+    assert_eq!(analog_pin_value(5000), 255);
+}
+
+#[test]
+fn events_are_id_value_pairs() {
+    let event = Event {
+        id: dal::ID_BUTTON_B,
+        value: dal::BUTTON_EVT_CLICK,
+    };
+    assert_eq!(encode_event(event), [0x02, 0x00, 0x03, 0x00]);
+    assert_eq!(
+        encode_event(Event {
+            id: 0x1234,
+            value: 0xABCD
+        }),
+        [0x34, 0x12, 0xCD, 0xAB]
+    );
+    let decoded: Vec<_> = decode_events(&[1, 0, 0, 0, 2, 0, 5, 0, 9]).collect();
+    assert_eq!(
+        decoded,
+        [
+            Event {
+                id: dal::ID_BUTTON_A,
+                value: dal::EVT_ANY
+            },
+            Event {
+                id: dal::ID_BUTTON_B,
+                value: dal::BUTTON_EVT_HOLD
+            },
+        ]
+    );
+}
+
+#[test]
+fn button_events_follow_the_dal() {
+    // 0 not pressed, 1 pressed, 2 long pressed
+    assert_eq!(button_events(0, 1), [dal::BUTTON_EVT_DOWN]);
+    assert_eq!(button_events(1, 2), [dal::BUTTON_EVT_HOLD]);
+    assert_eq!(
+        button_events(1, 0),
+        [dal::BUTTON_EVT_UP, dal::BUTTON_EVT_CLICK]
+    );
+    assert_eq!(
+        button_events(2, 0),
+        [dal::BUTTON_EVT_UP, dal::BUTTON_EVT_LONG_CLICK]
+    );
+    assert!(button_events(0, 0).is_empty());
+    assert!(button_events(0, 2).is_empty());
+}
+
+#[test]
+fn requirements() {
+    let any_a = Event {
+        id: dal::ID_BUTTON_A,
+        value: dal::EVT_ANY,
+    };
+    let click_b = Event {
+        id: dal::ID_BUTTON_B,
+        value: dal::BUTTON_EVT_CLICK,
+    };
+    let a_down = Event {
+        id: dal::ID_BUTTON_A,
+        value: dal::BUTTON_EVT_DOWN,
+    };
+    let b_down = Event {
+        id: dal::ID_BUTTON_B,
+        value: dal::BUTTON_EVT_DOWN,
+    };
+    assert!(is_required(&[any_a, click_b], a_down));
+    assert!(is_required(&[any_a, click_b], click_b));
+    assert!(!is_required(&[any_a, click_b], b_down));
+    assert!(!is_required(&[], a_down));
+}
+
+#[test]
+fn calibration_offset_is_the_middle() {
+    let mut found = Calibration::new();
+    assert_eq!(found.offset(0), None);
+    // a board that adds 5 µT on x and -3 µT on z, turned around
+    for [x, y, z] in [
+        [45_000, 0, -3_000],
+        [-35_000, 0, -3_000],
+        [5_000, 40_000, 37_000],
+        [5_000, -40_000, -43_000],
+    ] {
+        found.add([x, y, z]);
+    }
+    assert_eq!(found.offset(30_000), Some([5_000, 0, -3_000]));
+    // barely turned around
+    assert_eq!(found.offset(90_000), None);
+}
+
+#[test]
+fn calibration_needs_every_axis() {
+    let mut found = Calibration::default();
+    // turned flat on the table only, z never swings
+    found.add([40_000, 0, 20_000]);
+    found.add([-40_000, 0, 20_000]);
+    found.add([0, 40_000, 21_000]);
+    found.add([0, -40_000, 21_000]);
+    assert_eq!(found.offset(30_000), None);
+    assert_eq!(found.offset(1_000), Some([0, 0, 20_500]));
+    // extremes of i32 don't overflow
+    let mut found = Calibration::new();
+    found.add([i32::MIN; 3]);
+    found.add([i32::MAX; 3]);
+    assert_eq!(found.offset(i32::MAX), Some([0; 3]));
+}
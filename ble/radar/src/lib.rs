@@ -0,0 +1,168 @@
+//! Proximity radar for ble-batt: a table of the devices heard nearby and how close the
+//! closest one is.
+//!
+//! The firmware feeds every advertisement that carries our service uuid into a [`Table`],
+//! which smooths the RSSI of each device and forgets devices that went quiet. [`bars`]
+//! turns the closest one into a level for the 5x5 matrix. Times are milliseconds since
+//! boot, whatever clock the caller has.
+//!
+//! Pure code, host tests: `cargo test -p ble-radar --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+
+use heapless::Vec;
+
+/// Highest level of [`bars`], one per matrix row
+pub const MAX_BARS: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// Weight of a new reading is 1 / 2^`smoothing_shift`, 0 for no smoothing
+    pub smoothing_shift: u8,
+    /// A device not heard for this long is dropped, in ms
+    pub max_age: u64,
+    /// RSSI in dBm at which [`bars`] is full, about touching distance
+    pub near: i8,
+    /// RSSI in dBm at which [`bars`] is down to one, about the other end of a room
+    pub far: i8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            smoothing_shift: 2,
+            max_age: 5_000,
+            near: -45,
+            far: -90,
+        }
+    }
+}
+
+/// A device in the [`Table`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    pub addr: [u8; 6],
+    /// Smoothed RSSI in 1/16 dBm
+    rssi: i32,
+    /// When it was last heard, in ms
+    pub last_seen: u64,
+}
+
+impl Peer {
+    /// Smoothed RSSI in dBm
+    pub fn rssi(&self) -> i8 {
+        // rounded to the nearest dBm, ties up
+        ((self.rssi + 8) >> 4) as i8
+    }
+}
+
+/// The devices heard lately, at most `N`
+#[derive(Debug, Clone)]
+pub struct Table<const N: usize> {
+    peers: Vec<Peer, N>,
+    config: Config,
+}
+
+impl<const N: usize> Table<N> {
+    pub const fn new(config: Config) -> Self {
+        Self {
+            peers: Vec::new(),
+            config,
+        }
+    }
+
+    /// Adds a reading. A full table makes room by dropping the device heard the longest
+    /// time ago, a device close enough to be heard now matters more.
+    pub fn update(&mut self, addr: [u8; 6], rssi: i8, now: u64) {
+        let sample = (rssi as i32) << 4;
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.addr == addr) {
+            // exponential moving average, shifting rounds toward minus infinity, the
+            // difference is under 1/16 dBm
+            peer.rssi += (sample - peer.rssi) >> self.config.smoothing_shift;
+            peer.last_seen = now;
+            return;
+        }
+        let peer = Peer {
+            addr,
+            rssi: sample,
+            last_seen: now,
+        };
+        if let Err(peer) = self.peers.push(peer) {
+            if let Some(oldest) = self.peers.iter_mut().min_by_key(|peer| peer.last_seen) {
+                *oldest = peer;
+            }
+        }
+    }
+
+    /// Drops the devices not heard for [`Config::max_age`]
+    pub fn expire(&mut self, now: u64) {
+        let max_age = self.config.max_age;
+        self.peers
+            .retain(|peer| now.saturating_sub(peer.last_seen) <= max_age);
+    }
+
+    /// The device with the strongest smoothed RSSI
+    pub fn closest(&self) -> Option<&Peer> {
+        self.peers.iter().max_by_key(|peer| peer.rssi)
+    }
+
+    pub fn get(&self, addr: &[u8; 6]) -> Option<&Peer> {
+        self.peers.iter().find(|peer| peer.addr == *addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.peers.clear();
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+/// How close `rssi` is, from 1 at [`Config::far`] and weaker up to [`MAX_BARS`] at
+/// [`Config::near`] and stronger, in even steps in between
+pub fn bars(rssi: i8, config: &Config) -> u8 {
+    let (rssi, near, far) = (rssi as i32, config.near as i32, config.far as i32);
+    if rssi <= far || near <= far {
+        return 1;
+    }
+    if rssi >= near {
+        return MAX_BARS;
+    }
+    // the range split in even parts, each part passed is one bar more
+    let steps = (MAX_BARS - 1) as i32;
+    (1 + (rssi - far) * steps / (near - far)) as u8
+}
+
+/// Whether advertising data lists the 16 bit service `uuid`, in the complete or the
+/// incomplete list. Malformed data ends the search.
+pub fn advertises_uuid16(mut data: &[u8], uuid: u16) -> bool {
+    const INCOMPLETE_UUIDS_16: u8 = 0x02;
+    const COMPLETE_UUIDS_16: u8 = 0x03;
+
+    while let [len, rest @ ..] = data {
+        let len = *len as usize;
+        if len == 0 || len > rest.len() {
+            return false;
+        }
+        let (ad, tail) = rest.split_at(len);
+        if let [INCOMPLETE_UUIDS_16 | COMPLETE_UUIDS_16, uuids @ ..] = ad {
+            if uuids
+                .chunks_exact(2)
+                .any(|le| u16::from_le_bytes([le[0], le[1]]) == uuid)
+            {
+                return true;
+            }
+        }
+        data = tail;
+    }
+    false
+}
@@ -0,0 +1,132 @@
+//! Run on the host: `cargo test -p ble-radar --target x86_64-unknown-linux-gnu`
+
+use ble_radar::*;
+
+const A: [u8; 6] = [1, 0, 0, 0, 0, 0];
+const B: [u8; 6] = [2, 0, 0, 0, 0, 0];
+const C: [u8; 6] = [3, 0, 0, 0, 0, 0];
+
+#[test]
+fn first_reading_is_taken_as_is() {
+    let mut table = Table::<4>::new(Config::default());
+    table.update(A, -60, 0);
+    assert_eq!(table.get(&A).unwrap().rssi(), -60);
+    assert_eq!(table.len(), 1);
+}
+
+#[test]
+fn smoothing_follows_slowly() {
+    let mut table = Table::<4>::new(Config {
+        smoothing_shift: 2,
+        ..Default::default()
+    });
+    table.update(A, -80, 0);
+    // a quarter of the way each time
+    table.update(A, -40, 100);
+    assert_eq!(table.get(&A).unwrap().rssi(), -70);
+    table.update(A, -40, 200);
+    // -62.5
+    assert_eq!(table.get(&A).unwrap().rssi(), -62);
+    // one outlier barely moves it
+    table.update(A, -90, 300);
+    assert_eq!(table.get(&A).unwrap().rssi(), -69);
+    for t in 4..40 {
+        table.update(A, -40, t * 100);
+    }
+    assert_eq!(table.get(&A).unwrap().rssi(), -40);
+    assert_eq!(table.get(&A).unwrap().last_seen, 3900);
+}
+
+#[test]
+fn no_smoothing() {
+    let mut table = Table::<4>::new(Config {
+        smoothing_shift: 0,
+        ..Default::default()
+    });
+    table.update(A, -80, 0);
+    table.update(A, -41, 1);
+    assert_eq!(table.get(&A).unwrap().rssi(), -41);
+}
+
+#[test]
+fn quiet_devices_age_out() {
+    let mut table = Table::<4>::new(Config {
+        max_age: 1000,
+        ..Default::default()
+    });
+    table.update(A, -60, 0);
+    table.update(B, -70, 500);
+    table.expire(1000);
+    assert_eq!(table.len(), 2);
+    table.expire(1001);
+    assert!(table.get(&A).is_none());
+    assert!(table.get(&B).is_some());
+    table.expire(10_000);
+    assert!(table.is_empty());
+}
+
+#[test]
+fn full_table_drops_the_oldest() {
+    let mut table = Table::<2>::new(Config::default());
+    table.update(A, -50, 0);
+    table.update(B, -70, 10);
+    table.update(C, -80, 20);
+    assert_eq!(table.len(), 2);
+    assert!(table.get(&A).is_none());
+    assert!(table.get(&B).is_some());
+    assert!(table.get(&C).is_some());
+}
+
+#[test]
+fn closest_is_the_strongest() {
+    let mut table = Table::<4>::new(Config::default());
+    assert!(table.closest().is_none());
+    table.update(A, -75, 0);
+    table.update(B, -55, 0);
+    table.update(C, -65, 0);
+    assert_eq!(table.closest().unwrap().addr, B);
+}
+
+#[test]
+fn bar_levels() {
+    let config = Config {
+        near: -45,
+        far: -85,
+        ..Default::default()
+    };
+    assert_eq!(bars(-20, &config), MAX_BARS);
+    assert_eq!(bars(-45, &config), 5);
+    assert_eq!(bars(-46, &config), 4);
+    assert_eq!(bars(-55, &config), 4);
+    assert_eq!(bars(-65, &config), 3);
+    assert_eq!(bars(-75, &config), 2);
+    assert_eq!(bars(-84, &config), 1);
+    assert_eq!(bars(-85, &config), 1);
+    assert_eq!(bars(-100, &config), 1);
+    // near and far the wrong way round
+    let config = Config {
+        near: -90,
+        far: -40,
+        ..Default::default()
+    };
+    assert_eq!(bars(-60, &config), 1);
+}
+
+#[test]
+fn uuid16_in_advertising_data() {
+    let data = [
+        0x02, 0x01, 0x06, // flags
+        0x05, 0x03, 0x0A, 0x18, 0x0F, 0x18, // complete list: 0x180A, 0x180F
+        0x03, 0x19, 0x00, 0x02, // appearance
+    ];
+    assert!(advertises_uuid16(&data, 0x180F));
+    assert!(advertises_uuid16(&data, 0x180A));
+    assert!(!advertises_uuid16(&data, 0x1809));
+    // the appearance isn't a uuid
+    assert!(!advertises_uuid16(&data, 0x0200));
+    // incomplete list
+    assert!(advertises_uuid16(&[0x03, 0x02, 0x0F, 0x18], 0x180F));
+    // a length running past the end
+    assert!(!advertises_uuid16(&[0x02, 0x01, 0x06, 0x09, 0x03, 0x0F, 0x18], 0x180F));
+    assert!(!advertises_uuid16(&[], 0x180F));
+}
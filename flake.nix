@@ -40,7 +40,7 @@
       srcFiles = fs.unions [
         files
         (fs.fileFilter (file: file.hasExt "rs") ./.)
-        (fs.fileFilter (file: file.name == "memory.x") ./.)
+        (fs.fileFilter (file: file.hasExt "x") ./.)
       ];
       src = fs.toSource {
         root = ./.;
@@ -0,0 +1,26 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  MBR                               : ORIGIN = 0x00000000, LENGTH = 4K
+  SOFTDEVICE                        : ORIGIN = 0x00001000, LENGTH = 108K
+  /* the active slot, the softdevice starts the app from here */
+  FLASH                             : ORIGIN = 0x0001C000, LENGTH = 180K
+  /* one page bigger than FLASH, the bootloader swaps through it */
+  DFU                               : ORIGIN = 0x00049000, LENGTH = 184K
+  /* ble/bootloader, keep in sync with its memory-s113.x */
+  BOOTLOADER                        : ORIGIN = 0x00077000, LENGTH = 24K
+  /* app settings that outlive reflashing, like the device name */
+  SETTINGS                          : ORIGIN = 0x0007D000, LENGTH = 4K
+  BOOTLOADER_STATE                  : ORIGIN = 0x0007E000, LENGTH = 4K
+  /* last page, outside FLASH so flashing the app leaves it alone */
+  BONDS                             : ORIGIN = 0x0007F000, LENGTH = 4K
+  RAM                               : ORIGIN = 0x2000afa8, LENGTH = 86104
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE);
+__bootloader_dfu_start = ORIGIN(DFU);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU);
+
+__settings_start = ORIGIN(SETTINGS);
+__bonds_start = ORIGIN(BONDS);
@@ -0,0 +1,30 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* the same partitions as memory-s113.x, moved up for the bigger S140 7.3.0 */
+  MBR                               : ORIGIN = 0x00000000, LENGTH = 4K
+  SOFTDEVICE                        : ORIGIN = 0x00001000, LENGTH = 152K
+  /* the active slot, the softdevice starts the app from here */
+  FLASH                             : ORIGIN = 0x00027000, LENGTH = 156K
+  /* one page bigger than FLASH, the bootloader swaps through it */
+  DFU                               : ORIGIN = 0x0004E000, LENGTH = 160K
+  /* 0x00076000, one free page */
+  /* ble/bootloader, keep in sync with its memory-s140.x */
+  BOOTLOADER                        : ORIGIN = 0x00077000, LENGTH = 24K
+  /* app settings that outlive reflashing, like the device name */
+  SETTINGS                          : ORIGIN = 0x0007D000, LENGTH = 4K
+  BOOTLOADER_STATE                  : ORIGIN = 0x0007E000, LENGTH = 4K
+  /* last page, outside FLASH so flashing the app leaves it alone */
+  BONDS                             : ORIGIN = 0x0007F000, LENGTH = 4K
+  /* the central link and the scanner take more softdevice RAM, at boot the softdevice
+     logs the start it needs if this is more than enough, the app can start there */
+  RAM                               : ORIGIN = 0x2000d000, LENGTH = 0x13000
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE);
+__bootloader_dfu_start = ORIGIN(DFU);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU);
+
+__settings_start = ORIGIN(SETTINGS);
+__bonds_start = ORIGIN(BONDS);
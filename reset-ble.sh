@@ -1,11 +1,14 @@
 !#/usr/bin/env bash
 
+# s113 (the default) or s140, has to match the feature the app is built with
+SOFTDEVICE=${1:-s113}
+
 read -p "Are you sure? " -n 1 -r
 echo    # (optional) move to a new line
 if [[ $REPLY =~ ^[Yy]$ ]]
 then
     probe-rs erase --chip nrf52833_xxAA --allow-erase-all
-    probe-rs download --verify --format hex --chip nRF52833_xxAA softdevice/s113_nrf52_7.3.0/s113_nrf52_7.3.0_softdevice.hex
+    probe-rs download --verify --format hex --chip nRF52833_xxAA softdevice/${SOFTDEVICE}_nrf52_7.3.0/${SOFTDEVICE}_nrf52_7.3.0_softdevice.hex
 
 fi
 